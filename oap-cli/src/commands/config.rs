@@ -0,0 +1,76 @@
+use anyhow::{bail, Result};
+use colored::*;
+use serde_json::json;
+use crate::commands::Context;
+use crate::config;
+use crate::output;
+
+/// Reads `key` from the section of the profile in effect (`--profile`, `OAP_PROFILE`
+/// or `active_profile`), or from the top level when no profile is selected.
+pub async fn get(key: String, ctx: &Context) -> Result<()> {
+    let (_, file) = config::read_file(ctx.config.clone())?;
+    let profile = config::selected_profile(&file, ctx.profile.clone(), &config::env_var);
+    let value = config::get_key(&file, profile.as_deref(), &key)?;
+
+    if ctx.json {
+        output::emit(&json!({ "key": key, "profile": profile, "value": value }))?;
+    } else {
+        match value {
+            Some(v) => println!("{}", v),
+            None => bail!("'{}' is not set", key),
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes `key` into the same section [`get`] reads from.
+pub async fn set(key: String, value: String, ctx: &Context) -> Result<()> {
+    let (path, mut file) = config::read_file(ctx.config.clone())?;
+    let profile = config::selected_profile(&file, ctx.profile.clone(), &config::env_var);
+    config::set_key(&mut file, profile.as_deref(), &key, &value)?;
+    config::write_file(&path, &file)?;
+
+    if ctx.json {
+        output::emit(&json!({ "status": "updated", "key": key, "profile": profile, "value": value }))?;
+    } else {
+        let scope = match &profile {
+            Some(p) => format!("[profiles.{}]", p),
+            None => "top level".to_string(),
+        };
+        println!("Set {} = {} ({}) in {}", key.cyan(), value.green(), scope, path.display().to_string().dimmed());
+    }
+
+    Ok(())
+}
+
+pub async fn list(ctx: &Context) -> Result<()> {
+    let (path, file) = config::read_file(ctx.config.clone())?;
+    let effective = config::load(ctx.config.clone(), ctx.profile.clone()).await?;
+
+    if ctx.json {
+        output::emit(&json!({
+            "path": path,
+            "effective": effective,
+            "profiles": file.profiles.keys().collect::<Vec<_>>(),
+            "active_profile": file.active_profile,
+        }))?;
+    } else {
+        println!("Config file: {}", path.display().to_string().dimmed());
+        println!("Profile: {}", effective.profile.as_deref().unwrap_or("(none)").cyan());
+        println!("default_relay = {}", effective.default_relay.green());
+        println!("default_identity = {}", effective.default_identity.as_deref().unwrap_or("(none)").green());
+        println!("timeout_secs = {}", effective.timeout_secs.to_string().green());
+        println!("output = {}", effective.output.green());
+        println!("did_fixtures = {}", effective.did_fixtures.as_deref().unwrap_or("(none)").green());
+        println!("did_cache_ttl_secs = {}", effective.did_cache_ttl_secs.to_string().green());
+        println!("ledger_url = {}", effective.ledger_url.green());
+        println!("discovery_url = {}", effective.discovery_url.green());
+        if !file.profiles.is_empty() {
+            let names: Vec<_> = file.profiles.keys().map(|k| k.as_str()).collect();
+            println!("Available profiles: {}", names.join(", "));
+        }
+    }
+
+    Ok(())
+}
@@ -1,54 +1,138 @@
 use anyhow::Result;
 use colored::*;
+use oap::oaep::did::DidKey;
+use serde_json::json;
+use std::path::PathBuf;
 use crate::commands::Context;
-// Assuming oap crate exposes Agent
-use oap::Agent; 
+use crate::config::Config;
+use crate::handshake;
+use crate::keystore;
+use crate::output::{self, ErrorKind};
+use crate::resolver::Resolver;
+use crate::session::{Role, SessionStore, StoredSession};
 
-pub async fn run(did: String, identity: Option<String>, ctx: &Context) -> Result<()> {
+pub async fn run(did: String, identity: Option<String>, insecure: bool, ctx: &Context) -> Result<()> {
     if ctx.verbose {
         println!("Initiating handshake with {}...", did.cyan());
     }
 
     // Load config
-    let cfg = crate::config::load(ctx.config.clone()).await?;
-
-    // Initialize Agent
-    let mut agent = Agent::new(&cfg.default_relay)?; 
-    
-    // If identity alias provided, we would load it here.
-    // For Phase 3/4 simulation, we just print it.
-    if let Some(id) = identity {
-        if ctx.verbose {
-            println!("Using identity alias: {}", id.blue());
+    let cfg = crate::config::load(ctx.config.clone(), ctx.profile.clone()).await?;
+
+    // Use the keystore identity, or an ephemeral one
+    let alias = identity.or(cfg.default_identity.clone());
+    let did_key = match &alias {
+        Some(alias) => {
+            if ctx.verbose {
+                println!("Using identity alias: {}", alias.blue());
+            }
+            keystore::load_identity(alias)?
         }
-    }
-    
+        None => DidKey::generate(),
+    };
+
     if ctx.verbose {
-        println!("Client DID: {}", agent.identity.did().to_string().green());
+        println!("Client DID: {}", did_key.did().to_string().green());
     }
 
-    // Connect
-    // Assuming agent.connect returns a Result<Session> or similar
-    match agent.connect(&did).await {
-        Ok(_session) => {
+    let store = SessionStore::open_default()?;
+    match establish(&cfg, &store, &did_key, alias.clone(), &did, insecure).await {
+        Ok((trace, Some((session, path)))) => {
             if ctx.json {
-                println!("{{ \"status\": \"connected\", \"peer\": \"{}\" }}", did);
+                output::emit(&json!({
+                    "status": "connected",
+                    "peer": did,
+                    "local_did": did_key.did().to_string(),
+                    "kid": session.kid,
+                    "session_path": path,
+                    "trace": trace,
+                }))?;
             } else {
+                if ctx.verbose {
+                    print_trace(&trace);
+                }
                 println!("{}", "Handshake Successful!".green().bold());
                 println!("Connected to {}", did.cyan());
+                if ctx.verbose {
+                    println!("Session saved to {}", path.display().to_string().dimmed());
+                }
             }
         }
-        Err(e) => {
-            if ctx.json {
-                println!("{{ \"status\": \"failed\", \"error\": \"{}\" }}", e);
+        Ok((trace, None)) => {
+            let reason = if !trace.transcript_hash_matches {
+                "transcript hash mismatch"
             } else {
-                println!("{} {}", "Handshake Failed:".red(), e);
-                if ctx.verbose {
-                    println!("Debug info: {:?}", e);
-                }
+                "proof signature invalid"
+            };
+            if ctx.verbose && !ctx.json {
+                print_trace(&trace);
             }
+            let err = output::error(ErrorKind::Handshake, format!("Handshake with {} failed: {}", did, reason));
+            return Err(output::fail(ctx.json, &json!({ "status": "failed", "peer": did, "trace": trace }), err));
+        }
+        Err(e) => {
+            if ctx.verbose && !ctx.json {
+                eprintln!("Debug info: {:?}", e);
+            }
+            // Keep network/resolution kinds from the cause, everything else is a handshake failure
+            let kind = match output::classify(&e) {
+                ErrorKind::Error => ErrorKind::Handshake,
+                kind => kind,
+            };
+            let message = format!("Handshake with {} failed: {:#}", did, e);
+            return Err(output::error(kind, message));
         }
     }
 
     Ok(())
 }
+
+/// Runs the handshake with `peer` and saves the session in `store`. A handshake that
+/// completed but failed its proof or transcript check is returned without a session.
+pub async fn establish(
+    cfg: &Config,
+    store: &SessionStore,
+    did_key: &DidKey,
+    alias: Option<String>,
+    peer: &str,
+    insecure: bool,
+) -> Result<(handshake::HandshakeTrace, Option<(StoredSession, PathBuf)>)> {
+    let client = cfg.http_client()?;
+    let resolver = Resolver::from_config(cfg, insecure)?;
+
+    let (trace, ephemeral) = handshake::initiate(&client, &resolver, did_key, peer).await?;
+    if !(trace.proof_verified && trace.transcript_hash_matches) {
+        return Ok((trace, None));
+    }
+    let hash = hex::decode(&trace.transcript_hash)?;
+    let keys = handshake::derive_session_keys(
+        &ephemeral,
+        &trace.response.body.key_exchange.public_key,
+        &hash,
+    )?;
+    let session = StoredSession::new(
+        peer.to_string(),
+        did_key.did().to_string(),
+        alias,
+        Role::Initiator,
+        &hash,
+        trace.cipher_suite.to_string(),
+        keys,
+    );
+    let path = store.save(&session)?;
+    Ok((trace, Some((session, path))))
+}
+
+fn print_trace(trace: &handshake::HandshakeTrace) {
+    println!("Sending ConnectionRequest to {}... {}", trace.endpoint, "OK".green());
+    println!("{}", serde_json::to_string_pretty(&trace.request).unwrap_or_default().dimmed());
+    let signature = if trace.proof_verified { "VALID".green() } else { "INVALID".red() };
+    println!("Received ConnectionResponse... Signature {}", signature);
+    println!("{}", serde_json::to_string_pretty(&trace.response).unwrap_or_default().dimmed());
+    println!("Cipher Suite: {}", trace.cipher_suite.to_string().yellow());
+    println!("Initiator Nonce: {}", trace.initiator_nonce);
+    println!("Responder Nonce: {}", trace.responder_nonce);
+    let matches = if trace.transcript_hash_matches { "matches proof".green() } else { "MISMATCH".red() };
+    println!("Transcript Hash: {} ({})", trace.transcript_hash, matches);
+    println!("Key ID (kid): {}", trace.kid.green());
+}
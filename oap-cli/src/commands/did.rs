@@ -1,34 +1,50 @@
 use anyhow::Result;
 use colored::*;
-use oap::oaep::keys::KeyPair;
 use oap::oaep::did::DidKey;
-use oap::oaep::did::DidDocument;
 use serde_json::json;
 use crate::commands::Context;
+use crate::keystore::{self, Keystore};
+use crate::output;
+use crate::resolver::{self, ResolutionResult, Resolver};
+use std::path::PathBuf;
 
-pub async fn generate(format: String, alias: Option<String>) -> Result<()> {
+/// Prints a new identity. Once it is in the keystore the secret key is only shown
+/// with `show_secret`; without an alias the printed key is the only copy.
+pub async fn generate(format: String, alias: Option<String>, show_secret: bool, ctx: &Context) -> Result<()> {
     let did_key = DidKey::generate();
     let did = did_key.did();
     let secret_hex = hex::encode(did_key.keypair().secret_key().as_bytes());
     let public_hex = hex::encode(did_key.keypair().public_key().as_bytes());
 
-    if let Some(a) = &alias {
-        // Mock saving to keystore
-        // In reality: ~/.oap/keystore/{alias}.json
-        let path = format!("~/.oap/keystore/{}.json", a);
-        if !format.contains("json") {
-             println!("Saved to {}", path.dimmed());
+    let saved_path = match &alias {
+        Some(a) => {
+            let keystore = Keystore::open_default()?;
+            keystore::validate_alias(a)?;
+            if keystore.exists(a) {
+                anyhow::bail!("Identity '{}' already exists in keystore", a);
+            }
+            let passphrase = keystore::read_passphrase(&format!("Passphrase for '{}': ", a), true)?;
+            Some(keystore.save(a, &did_key, &passphrase)?)
         }
-    }
+        None => None,
+    };
+    let secret_hex = (saved_path.is_none() || show_secret).then_some(secret_hex);
 
-    if format == "json" {
-        let output = json!({
+    if ctx.json || format == "json" {
+        let mut generated = json!({
             "did": did.to_string(),
-            "secret_key": secret_hex,
             "public_key": public_hex,
-            "alias": alias
+            "alias": alias,
+            "keystore_path": saved_path
         });
-        println!("{}", serde_json::to_string_pretty(&output)?);
+        if let Some(secret_hex) = &secret_hex {
+            generated["secret_key"] = json!(secret_hex);
+        }
+        if ctx.json {
+            output::emit(&generated)?;
+        } else {
+            println!("{}", serde_json::to_string_pretty(&generated)?);
+        }
     } else {
         println!("{}", "Generated new Identity:".green().bold());
         if let Some(a) = alias {
@@ -36,37 +52,60 @@ pub async fn generate(format: String, alias: Option<String>) -> Result<()> {
         }
         println!("DID: {}", did.to_string().cyan());
         println!("Public Key: {}", public_hex);
-        println!("Secret Key: {}", secret_hex.red());
-        println!("{}", "WARNING: Save the Secret Key securely!".yellow());
+        if let Some(secret_hex) = &secret_hex {
+            println!("Secret Key: {}", secret_hex.red());
+            println!("{}", "WARNING: Save the Secret Key securely!".yellow());
+        }
+        if let Some(path) = saved_path {
+            println!("Saved to {}", path.display().to_string().dimmed());
+        }
     }
 
     Ok(())
 }
 
-pub async fn resolve(did_str: String, ctx: &Context) -> Result<()> {
+pub async fn resolve(did_str: String, insecure: bool, no_cache: bool, fixtures: Option<PathBuf>, ctx: &Context) -> Result<()> {
     if ctx.verbose {
         println!("Resolving {}...", did_str.cyan());
     }
 
-    if did_str.starts_with("did:key:") {
-        // For Phase 1/2, we mock or partially implement
-        if ctx.json {
-             // Placeholder JSON output
-             println!("{}", json!({
-                 "did": did_str,
-                 "document": "placeholder"
-             }));
-        } else {
-            println!("DID Document:");
-            println!("{}", "{}".yellow()); // Placeholder
+    let cfg = crate::config::load(ctx.config.clone(), ctx.profile.clone()).await?;
+    let mut resolver = Resolver::from_config(&cfg, insecure)?;
+    if no_cache {
+        resolver = resolver.without_cache();
+    }
+    if let Some(dir) = fixtures {
+        resolver = resolver.with_fixtures(dir);
+    }
+
+    match resolver.resolve(&did_str).await {
+        Ok(result) => {
+            if ctx.json {
+                output::emit(&result)?;
+            } else {
+                println!("{}", "DID Document:".green().bold());
+                println!("{}", serde_json::to_string_pretty(result.document())?);
+                if let Some(source) = result.did_resolution_metadata.get("source").and_then(|v| v.as_str()) {
+                    println!("Source: {}", source.dimmed());
+                }
+                if ctx.verbose {
+                    println!("Metadata: {}", result.did_resolution_metadata.to_string().dimmed());
+                }
+            }
+            Ok(())
         }
+        Err(e) => Err(output::fail(ctx.json, &ResolutionResult::error(&e), e.into())),
+    }
+}
+
+pub async fn clear_cache(ctx: &Context) -> Result<()> {
+    let dir = resolver::default_cache_dir()?;
+    let removed = resolver::clear_cache(&dir)?;
+
+    if ctx.json {
+        output::emit(&json!({ "status": "cleared", "removed": removed, "path": dir }))?;
     } else {
-        if ctx.json {
-             println!("{}", json!({"error": "Only did:key supported"}));
-        } else {
-            println!("Resolving {}...", did_str.cyan());
-            println!("Only did:key is supported locally in Phase 1.");
-        }
+        println!("Removed {} cached DID document(s) from {}", removed, dir.display().to_string().dimmed());
     }
 
     Ok(())
@@ -0,0 +1,153 @@
+use anyhow::{bail, Context as _, Result};
+use colored::*;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use crate::commands::{read_input, Context};
+use crate::config::Config;
+use crate::discovery_client::{hash_identifier, normalize_identifier, DiscoveryClient};
+use crate::output;
+
+/// Options shared by all `discover` subcommands.
+pub struct DiscoverOptions {
+    /// Discovery URL override
+    pub discovery: Option<String>,
+    /// Send normalized identifiers instead of their hashes
+    pub plain: bool,
+}
+
+impl DiscoverOptions {
+    fn client(&self, cfg: &Config) -> Result<DiscoveryClient> {
+        let base = self.discovery.as_deref().unwrap_or(&cfg.discovery_url);
+        Ok(DiscoveryClient::new(base, cfg.http_client()?))
+    }
+
+    fn blind(&self, identifier: &str) -> String {
+        if self.plain { normalize_identifier(identifier) } else { hash_identifier(identifier) }
+    }
+}
+
+/// A contact list: a JSON array of strings, or one identifier per line (`#` comments).
+fn parse_contacts(input: &str) -> Vec<String> {
+    if let Ok(list) = serde_json::from_str::<Vec<String>>(input) {
+        return list;
+    }
+    input.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Seed entries: a JSON object `{identifier: did}`, or `identifier did` per line.
+fn parse_seed(input: &str) -> Result<Vec<(String, String)>> {
+    if let Ok(map) = serde_json::from_str::<BTreeMap<String, String>>(input) {
+        return Ok(map.into_iter().collect());
+    }
+    input.lines()
+        .map(str::trim)
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(i, line)| match line.rsplit_once(char::is_whitespace) {
+            Some((identifier, did)) if did.starts_with("did:") => Ok((identifier.trim().to_string(), did.to_string())),
+            _ => bail!("Line {}: expected '<identifier> <did>'", i + 1),
+        })
+        .collect()
+}
+
+/// Private set intersection of local contacts against the discovery service. Only the
+/// hashed identifiers leave this machine; matches are mapped back to the local entries.
+pub async fn lookup(
+    identifiers: Vec<String>,
+    contacts: Option<PathBuf>,
+    opts: DiscoverOptions,
+    ctx: &Context,
+) -> Result<()> {
+    let cfg = crate::config::load(ctx.config.clone(), ctx.profile.clone()).await?;
+    let mut local = identifiers;
+    if let Some(path) = contacts {
+        let raw = String::from_utf8(read_input(None, Some(path))?).context("Contact list is not UTF-8")?;
+        local.extend(parse_contacts(&raw));
+    }
+    if local.is_empty() {
+        bail!("No contacts given (pass identifiers or --contacts <file>)");
+    }
+
+    // Blinded form -> local identifier, deduplicated
+    let blinded: BTreeMap<String, String> = local.iter().map(|id| (opts.blind(id), id.clone())).collect();
+    let query: Vec<String> = blinded.keys().cloned().collect();
+
+    let client = opts.client(&cfg)?;
+    if ctx.verbose {
+        println!("Intersecting {} contacts with {}", query.len(), client.base().cyan());
+    }
+    let matches = client.intersect(&query).await?;
+
+    let found: Vec<Value> = matches.iter()
+        .filter_map(|(blind, did)| blinded.get(blind).map(|id| json!({ "identifier": id, "did": did })))
+        .collect();
+
+    if ctx.json {
+        output::emit(&json!({ "queried": query.len(), "matches": found }))?;
+    } else {
+        for m in &found {
+            println!("{} → {}", m["identifier"].as_str().unwrap_or_default(), m["did"].as_str().unwrap_or_default().cyan());
+        }
+        println!("{} of {} contacts are on OAP", found.len().to_string().green(), query.len());
+    }
+    Ok(())
+}
+
+/// Admin: links one identifier to a DID.
+pub async fn add(identifier: String, did: String, opts: DiscoverOptions, ctx: &Context) -> Result<()> {
+    let cfg = crate::config::load(ctx.config.clone(), ctx.profile.clone()).await?;
+    let blind = opts.blind(&identifier);
+    opts.client(&cfg)?.add_contact(&blind, &did).await?;
+
+    if ctx.json {
+        output::emit(&json!({ "status": "added", "identifier": identifier, "stored_as": blind, "did": did }))?;
+    } else {
+        println!("Linked {} → {}", identifier, did.cyan());
+    }
+    Ok(())
+}
+
+/// Admin: links every entry of a seed file.
+pub async fn seed(file: PathBuf, opts: DiscoverOptions, ctx: &Context) -> Result<()> {
+    let cfg = crate::config::load(ctx.config.clone(), ctx.profile.clone()).await?;
+    let raw = String::from_utf8(read_input(None, Some(file))?).context("Seed file is not UTF-8")?;
+    let entries = parse_seed(&raw)?;
+    let client = opts.client(&cfg)?;
+
+    for (identifier, did) in &entries {
+        client.add_contact(&opts.blind(identifier), did).await
+            .with_context(|| format!("Failed to add {}", identifier))?;
+        if ctx.verbose {
+            println!("Linked {} → {}", identifier, did.cyan());
+        }
+    }
+
+    if ctx.json {
+        output::emit(&json!({ "status": "seeded", "count": entries.len() }))?;
+    } else {
+        println!("Seeded {} contacts into {}", entries.len().to_string().green(), client.base());
+    }
+    Ok(())
+}
+
+/// Admin: dumps the service's directory (as stored, i.e. hashed).
+pub async fn list(opts: DiscoverOptions, ctx: &Context) -> Result<()> {
+    let cfg = crate::config::load(ctx.config.clone(), ctx.profile.clone()).await?;
+    let directory = opts.client(&cfg)?.list().await?;
+
+    if ctx.json {
+        output::emit(&directory)?;
+    } else if directory.is_empty() {
+        println!("No contacts registered.");
+    } else {
+        for (identifier, did) in &directory {
+            println!("{} → {}", identifier.dimmed(), did.cyan());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,149 @@
+use anyhow::{Context as _, Result};
+use colored::*;
+use oap::oaep::did::{Did, DidKey};
+use oap::oaep::keys::X25519Secret;
+use oap::oaep::messages::{ConnectionRequest, ConnectionResponse};
+use serde_json::json;
+use std::path::PathBuf;
+use crate::commands::{read_json, Context};
+use crate::handshake;
+use crate::keystore::{self, write_private};
+use crate::output::{self, ErrorKind};
+use crate::resolver::Resolver;
+
+fn load_identity(identity: Option<String>, default: Option<String>, ctx: &Context) -> Result<DidKey> {
+    match identity.or(default) {
+        Some(alias) => keystore::load_identity(&alias),
+        None => {
+            if ctx.verbose {
+                eprintln!("{}", "No identity given, using an ephemeral did:key".yellow());
+            }
+            Ok(DidKey::generate())
+        }
+    }
+}
+
+/// Writes the ephemeral X25519 secret as hex (mode `0600`) for `oap session derive`.
+fn save_ephemeral(path: Option<PathBuf>, secret: &X25519Secret) -> Result<()> {
+    if let Some(path) = path {
+        write_private(&path, hex::encode(secret.as_bytes()).as_bytes())?;
+    }
+    Ok(())
+}
+
+pub async fn build_request(
+    peer: String,
+    identity: Option<String>,
+    ephemeral_out: Option<PathBuf>,
+    ctx: &Context,
+) -> Result<()> {
+    let cfg = crate::config::load(ctx.config.clone(), ctx.profile.clone()).await?;
+    let did_key = load_identity(identity, cfg.default_identity.clone(), ctx)?;
+
+    let ephemeral = X25519Secret::generate();
+    let request = handshake::build_request(&did_key, &peer, &ephemeral)?;
+    save_ephemeral(ephemeral_out, &ephemeral)?;
+
+    print_message(&request, ctx)
+}
+
+pub async fn respond(
+    request_path: PathBuf,
+    identity: Option<String>,
+    did: Option<String>,
+    ephemeral_out: Option<PathBuf>,
+    no_verify: bool,
+    insecure: bool,
+    ctx: &Context,
+) -> Result<()> {
+    let cfg = crate::config::load(ctx.config.clone(), ctx.profile.clone()).await?;
+    let request: ConnectionRequest = serde_json::from_value(read_json(request_path)?)
+        .context("Input is not a ConnectionRequest")?;
+    let did_key = load_identity(identity, cfg.default_identity.clone(), ctx)?;
+
+    // Answer as the addressed DID unless told otherwise
+    let did = did.unwrap_or_else(|| request.to.to_string());
+    if !no_verify {
+        let resolver = Resolver::from_config(&cfg, insecure)?;
+        if !handshake::verify_request(&resolver, &request, Some(&did)).await? {
+            return Err(output::error(ErrorKind::Crypto, "Request proof signature invalid (use --no-verify to answer anyway)"));
+        }
+    }
+
+    let verification_method = if did == did_key.did().to_string() {
+        handshake::verification_method(&did_key)
+    } else {
+        format!("{}#key-1", did)
+    };
+    let (response, ephemeral) =
+        handshake::build_response(&did_key, &did.parse::<Did>()?, &verification_method, &request)?;
+    save_ephemeral(ephemeral_out, &ephemeral)?;
+
+    print_message(&response, ctx)
+}
+
+pub async fn verify(message_path: PathBuf, request_path: Option<PathBuf>, insecure: bool, ctx: &Context) -> Result<()> {
+    let cfg = crate::config::load(ctx.config.clone(), ctx.profile.clone()).await?;
+    let resolver = Resolver::from_config(&cfg, insecure)?;
+    let message = read_json(message_path)?;
+
+    let report = if message.get("reply_to").is_some() {
+        let response: ConnectionResponse = serde_json::from_value(message)
+            .context("Input is not a ConnectionResponse")?;
+        let request_path = request_path
+            .ok_or_else(|| anyhow::anyhow!("Verifying a ConnectionResponse needs the request (--request)"))?;
+        let request: ConnectionRequest = serde_json::from_value(read_json(request_path)?)
+            .context("--request is not a ConnectionRequest")?;
+
+        let (matches, verified) = handshake::verify_response(&resolver, &request, &response).await?;
+        let hash = handshake::transcript_hash(&request, &response);
+        json!({
+            "type": "ConnectionResponse",
+            "transcript_hash": hex::encode(hash),
+            "claimed_transcript_hash": response.proof.transcript_hash,
+            "transcript_hash_matches": matches,
+            "proof_verified": verified,
+            "kid": handshake::kid(&hash),
+        })
+    } else {
+        let request: ConnectionRequest = serde_json::from_value(message)
+            .context("Input is neither a ConnectionRequest nor a ConnectionResponse")?;
+        let verified = handshake::verify_request(&resolver, &request, None).await?;
+        json!({
+            "type": "ConnectionRequest",
+            "proof_verified": verified,
+        })
+    };
+
+    let valid = report["proof_verified"] == json!(true) && report.get("transcript_hash_matches") != Some(&json!(false));
+
+    if !ctx.json {
+        println!("Message: {}", report["type"].as_str().unwrap_or_default());
+        if let Some(hash) = report.get("transcript_hash").and_then(|v| v.as_str()) {
+            let matches = if report["transcript_hash_matches"] == json!(true) { "matches proof".green() } else { "MISMATCH".red() };
+            println!("Transcript Hash: {} ({})", hash, matches);
+            println!("Key ID (kid): {}", report["kid"].as_str().unwrap_or_default().green());
+        }
+        let signature = if report["proof_verified"] == json!(true) { "VALID".green() } else { "INVALID".red() };
+        println!("Proof Signature: {}", signature);
+    }
+
+    if !valid {
+        let err = output::error(ErrorKind::Crypto, "Handshake message failed verification");
+        return Err(output::fail(ctx.json, &report, err));
+    }
+    if ctx.json {
+        output::emit(&report)?;
+    }
+    Ok(())
+}
+
+/// Prints a handshake message as-is, or wrapped in the envelope with `--json`.
+fn print_message<T: serde::Serialize>(message: &T, ctx: &Context) -> Result<()> {
+    if ctx.json {
+        output::emit(message)
+    } else {
+        println!("{}", serde_json::to_string_pretty(message)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,197 @@
+use anyhow::{bail, Context as _, Result};
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use colored::*;
+use oap::oaep::did::DidKey;
+use oap::oaep::keys::KeyPair;
+use serde_json::json;
+use std::io::Write;
+use crate::commands::Context;
+use crate::keystore::{self, Keystore, KeystoreEntry, ED25519_PRIV_CODEC};
+use crate::output::{self, ErrorKind};
+use crate::resolver::ED25519_PUB_CODEC;
+
+fn entry_json(entry: &KeystoreEntry, path: &std::path::Path) -> serde_json::Value {
+    json!({
+        "alias": entry.alias,
+        "did": entry.did,
+        "public_key": entry.public_key,
+        "created_at": entry.created_at,
+        "version": entry.version,
+        "path": path,
+    })
+}
+
+pub async fn list(ctx: &Context) -> Result<()> {
+    let keystore = Keystore::open_default()?;
+    let entries = keystore.list()?;
+
+    if ctx.json {
+        let items: Vec<_> = entries
+            .iter()
+            .map(|e| entry_json(e, &keystore.path_for(&e.alias)))
+            .collect();
+        output::emit(&items)?;
+    } else if entries.is_empty() {
+        println!("{}", "No identities in keystore. Create one with `oap did gen --alias <name>`.".dimmed());
+    } else {
+        for e in entries {
+            println!("{:<20} {}", e.alias.cyan(), e.did);
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn show(alias: String, ctx: &Context) -> Result<()> {
+    let keystore = Keystore::open_default()?;
+    let entry = keystore.entry(&alias)?;
+    let path = keystore.path_for(&alias);
+
+    if ctx.json {
+        output::emit(&entry_json(&entry, &path))?;
+    } else {
+        println!("Alias: {}", entry.alias.cyan());
+        println!("DID: {}", entry.did.cyan());
+        println!("Public Key: {}", entry.public_key);
+        println!("Created: {}", entry.created_at);
+        println!("Encryption: {} / {}", entry.crypto.kdf, entry.crypto.cipher);
+        println!("Path: {}", path.display().to_string().dimmed());
+    }
+
+    Ok(())
+}
+
+pub async fn export(alias: String, format: String, private: bool, ctx: &Context) -> Result<()> {
+    let keystore = Keystore::open_default()?;
+    let entry = keystore.entry(&alias)?;
+    let public = hex::decode(&entry.public_key).context("Corrupt public key in keystore")?;
+
+    let secret = if private {
+        let did_key = keystore::load_identity(&alias)?;
+        Some(*did_key.keypair().secret_key().as_bytes())
+    } else {
+        None
+    };
+
+    let exported = match format.as_str() {
+        "jwk" => {
+            let mut jwk = json!({
+                "kty": "OKP",
+                "crv": "Ed25519",
+                "x": URL_SAFE_NO_PAD.encode(&public),
+                "kid": entry.did,
+            });
+            if let Some(sk) = secret {
+                jwk["d"] = json!(URL_SAFE_NO_PAD.encode(sk));
+            }
+            jwk
+        }
+        "multibase" => {
+            let mut out = json!({
+                "did": entry.did,
+                "public_key_multibase": multibase::encode(
+                    multibase::Base::Base58Btc,
+                    [ED25519_PUB_CODEC.as_slice(), &public].concat()
+                ),
+            });
+            if let Some(sk) = secret {
+                out["secret_key_multibase"] = json!(multibase::encode(
+                    multibase::Base::Base58Btc,
+                    [ED25519_PRIV_CODEC.as_slice(), &sk].concat()
+                ));
+            }
+            out
+        }
+        other => bail!("Unknown export format '{}' (expected jwk or multibase)", other),
+    };
+
+    if ctx.json {
+        output::emit(&exported)?;
+    } else if format == "jwk" {
+        println!("{}", serde_json::to_string_pretty(&exported)?);
+    } else {
+        println!("Public Key: {}", exported["public_key_multibase"].as_str().unwrap_or_default());
+        if let Some(sk) = exported.get("secret_key_multibase").and_then(|v| v.as_str()) {
+            println!("Secret Key: {}", sk.red());
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn import(alias: String, secret: Option<String>, ctx: &Context) -> Result<()> {
+    let keystore = Keystore::open_default()?;
+    keystore::validate_alias(&alias)?;
+    if keystore.exists(&alias) {
+        bail!("Identity '{}' already exists in keystore", alias);
+    }
+
+    // Prompt instead of taking the secret as an argument, so it stays out of shell history
+    let secret = match secret {
+        Some(s) => s,
+        None => rpassword::prompt_password("Secret key (hex, multibase or file path): ")
+            .context("Failed to read secret key")?,
+    };
+    let bytes = keystore::parse_secret(secret.trim())?;
+    let did_key = DidKey::from_keypair(KeyPair::from_secret_bytes(&bytes)?);
+
+    let passphrase = keystore::read_passphrase(&format!("Passphrase for '{}': ", alias), true)?;
+    let path = keystore.save(&alias, &did_key, &passphrase)?;
+
+    if ctx.json {
+        output::emit(&json!({
+            "status": "imported",
+            "alias": alias,
+            "did": did_key.did().to_string(),
+            "path": path,
+        }))?;
+    } else {
+        println!("{} {}", "Imported identity".green(), alias.cyan());
+        println!("DID: {}", did_key.did().to_string().cyan());
+        println!("Saved to {}", path.display().to_string().dimmed());
+    }
+
+    Ok(())
+}
+
+pub async fn delete(alias: String, yes: bool, ctx: &Context) -> Result<()> {
+    let keystore = Keystore::open_default()?;
+    let entry = keystore.entry(&alias)?;
+
+    if !yes {
+        if ctx.json {
+            return Err(output::error(ErrorKind::Usage, format!("Refusing to delete '{}' without --yes in JSON mode", alias)));
+        }
+        print!("Delete identity '{}' ({})? This cannot be undone [y/N]: ", alias, entry.did);
+        std::io::stdout().flush()?;
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        if !matches!(answer.trim(), "y" | "Y" | "yes") {
+            println!("Aborted.");
+            return Ok(());
+        }
+    }
+
+    keystore.delete(&alias)?;
+
+    if ctx.json {
+        output::emit(&json!({ "status": "deleted", "alias": alias, "did": entry.did }))?;
+    } else {
+        println!("{} {}", "Deleted identity".yellow(), alias.cyan());
+    }
+
+    Ok(())
+}
+
+pub async fn rename(from: String, to: String, ctx: &Context) -> Result<()> {
+    let keystore = Keystore::open_default()?;
+    let path = keystore.rename(&from, &to)?;
+
+    if ctx.json {
+        output::emit(&json!({ "status": "renamed", "from": from, "to": to, "path": path }))?;
+    } else {
+        println!("Renamed {} -> {}", from.cyan(), to.cyan());
+    }
+
+    Ok(())
+}
@@ -1,46 +1,276 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use colored::*;
+use oap::oaep::did::DidKey;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
 use crate::commands::Context;
-use oap::Agent;
+use crate::config::Config;
+use crate::jwe;
+use crate::keystore;
+use crate::output::{self, ErrorKind};
+use crate::relay_client::{RelayClient, RelayMessage};
+use crate::resolver::Resolver;
+use crate::responder::Responder;
+use crate::session::SessionStore;
 
-pub async fn run(port: Option<u16>, ctx: &Context) -> Result<()> {
-    let p = port.unwrap_or(3000);
-    
-    if ctx.verbose {
-        println!("Listening for connections (Port/Relay: {})...", p);
-    }
+pub struct ListenOptions {
+    pub relay: Option<String>,
+    pub once: bool,
+    pub timeout: Option<u64>,
+    pub count: Option<usize>,
+    pub interval_ms: u64,
+    /// Host name used for the did:web and endpoint in `--port` mode.
+    pub host: String,
+    /// Resolve initiators' did:web over plain HTTP in `--port` mode.
+    pub insecure: bool,
+}
+
+/// One inbox message after decryption, printed as a JSON line in `--json` mode.
+#[derive(Debug, Serialize)]
+pub struct Incoming {
+    pub id: String,
+    pub recipient: String,
+    pub received_at: Option<f64>,
+    pub peer: Option<String>,
+    pub kid: Option<String>,
+    pub seq: Option<u64>,
+    pub payload: Option<Value>,
+    pub error: Option<String>,
+}
 
+pub async fn run(port: Option<u16>, identity: Option<String>, opts: ListenOptions, ctx: &Context) -> Result<()> {
     // Load config
-    let cfg = crate::config::load(ctx.config.clone()).await?;
+    let cfg = crate::config::load(ctx.config.clone(), ctx.profile.clone()).await?;
 
-    // Initialize Agent
-    let agent = Agent::new(&cfg.default_relay)?;
-    let did = agent.identity.did().to_string();
+    let alias = identity.or(cfg.default_identity.clone());
+    let did_key = match &alias {
+        Some(alias) => keystore::load_identity(alias)?,
+        None => DidKey::generate(),
+    };
 
-    if ctx.json {
-        println!("{{ \"status\": \"listening\", \"did\": \"{}\" }}", did);
-    } else {
+    if let Some(port) = port {
+        return serve(port, did_key, alias, opts, &cfg, ctx).await;
+    }
+
+    let did = did_key.did().to_string();
+
+    let relay = RelayClient::new(
+        opts.relay.as_deref().unwrap_or(&cfg.default_relay),
+        cfg.http_client()?,
+    );
+
+    if !ctx.json {
         println!("{}", "Listener Started".green().bold());
         println!("My DID: {}", did.cyan());
-        println!("Waiting for handshake...");
-    }
-
-    // Mock listener loop for Phase 3 if Agent doesn't have a high-level listen
-    // In reality, we'd call agent.listen() or agent.poll()
-    // agent.listen().await?;
-    
-    // For now, we just simulate waiting or exit if it's a mock
-    // To make it useful, we might actually want to poll if we had a relay.
-    // But without a running relay, this will just hang or fail.
-    // I'll add a placeholder loop.
-    
+        println!("Polling {} for messages...", relay.url("/messages").dimmed());
+    }
+
+    let store = SessionStore::open_default()?;
+    let deadline = opts.timeout.map(|t| Instant::now() + Duration::from_secs(t));
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut received = 0usize;
+
     loop {
-        tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+        match relay.messages(Some(&did)).await {
+            Ok(messages) => {
+                for msg in messages {
+                    if !seen.insert(msg.id.clone()) || is_stale(&store, &did, &msg) {
+                        continue;
+                    }
+                    let incoming = process(&store, &did, msg);
+                    print_incoming(&incoming, ctx.json)?;
+                    if incoming.payload.is_some() {
+                        received += 1;
+                    }
+                    if opts.count.is_some_and(|n| received >= n) {
+                        return Ok(());
+                    }
+                }
+            }
+            Err(e) => {
+                if opts.once {
+                    return Err(e);
+                }
+                if ctx.verbose {
+                    eprintln!("{} {}", "Poll failed:".red(), e);
+                }
+            }
+        }
+
+        if opts.once {
+            return Ok(());
+        }
+
+        if let Some(deadline) = deadline {
+            if Instant::now() >= deadline {
+                let timeout = opts.timeout.unwrap_or(0);
+                let message = match opts.count {
+                    Some(n) => format!("Timed out after {}s: received {} of {} messages", timeout, received, n),
+                    None => format!("Timed out after {}s: received {} messages", timeout, received),
+                };
+                return Err(output::error(ErrorKind::Timeout, message));
+            }
+        }
+
+        tokio::time::sleep(Duration::from_millis(opts.interval_ms)).await;
         if ctx.verbose {
-            println!("Polling...");
+            eprintln!("Polling...");
+        }
+    }
+}
+
+/// Runs the embedded responder agent instead of polling a relay.
+async fn serve(port: u16, did_key: DidKey, alias: Option<String>, opts: ListenOptions, cfg: &Config, ctx: &Context) -> Result<()> {
+    let resolver = Resolver::from_config(cfg, opts.insecure)?;
+    let responder = Responder::new(did_key, alias, &opts.host, port, resolver, ctx.json)?;
+
+    if ctx.json {
+        output::emit_line(&json!({ "event": "listening", "did": responder.did, "endpoint": responder.endpoint }))?;
+    } else {
+        println!("{}", "Responder Agent Started".green().bold());
+        println!("My DID: {}", responder.did.cyan());
+        println!("OAEP Endpoint: {}", responder.endpoint.dimmed());
+        println!("Connect with: oap connect {} --insecure", responder.did);
+    }
+
+    responder.serve(port).await
+}
+
+/// Whether a relay message was already received: its `seq` is covered by the session's
+/// `rx_seq`. Replies queued before the listener started are still delivered.
+pub fn is_stale(store: &SessionStore, local_did: &str, msg: &RelayMessage) -> bool {
+    let Ok(header) = jwe::protected_header(&msg.content) else {
+        return false;
+    };
+    let (Some(kid), Some(seq)) = (header.get("kid").and_then(|v| v.as_str()), header.get("seq").and_then(|v| v.as_u64())) else {
+        return false;
+    };
+    match store.find_by_kid(kid, Some(local_did)) {
+        Ok(Some(session)) => session.rx_seq.is_some_and(|last| seq <= last),
+        _ => false,
+    }
+}
+
+/// Decrypts a relay message with the session matching its `kid`.
+pub fn process(store: &SessionStore, local_did: &str, msg: RelayMessage) -> Incoming {
+    let mut incoming = Incoming {
+        id: msg.id,
+        recipient: msg.recipient,
+        received_at: msg.received_at,
+        peer: None,
+        kid: None,
+        seq: None,
+        payload: None,
+        error: None,
+    };
+
+    let result = (|| -> Result<Value> {
+        let header = jwe::protected_header(&msg.content)?;
+        let kid = header.get("kid").and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow!("Message has no kid"))?
+            .to_string();
+        incoming.kid = Some(kid.clone());
+        incoming.seq = header.get("seq").and_then(|v| v.as_u64());
+
+        let session = store.find_by_kid(&kid, Some(local_did))?
+            .ok_or_else(|| anyhow!("No active session with kid {}", kid))?;
+        incoming.peer = Some(session.peer_did.clone());
+
+        let container = jwe::parse_container(&msg.content)?;
+        let plaintext = session.open(&container)?;
+        if let Some(seq) = incoming.seq {
+            store.update(&session.peer_did, |session| session.record_rx_seq(seq))?;
+        }
+        Ok(jwe::payload_value(&plaintext))
+    })();
+
+    match result {
+        Ok(payload) => incoming.payload = Some(payload),
+        Err(e) => incoming.error = Some(e.to_string()),
+    }
+    incoming
+}
+
+pub fn print_incoming(incoming: &Incoming, json: bool) -> Result<()> {
+    if json {
+        return output::emit_line(incoming);
+    }
+    println!("{}", format_incoming(incoming));
+    Ok(())
+}
+
+/// The text form of an incoming message: a header line, then the payload or error.
+pub fn format_incoming(incoming: &Incoming) -> String {
+    let from = incoming.peer.as_deref().unwrap_or("unknown");
+    let seq = incoming.seq.map(|s| s.to_string()).unwrap_or_else(|| "-".to_string());
+    let header = format!("[{}] from {} (seq {})", incoming.id.dimmed(), from.cyan(), seq);
+    match (&incoming.payload, &incoming.error) {
+        (Some(Value::String(s)), _) => format!("{}\n{}", header, s),
+        (Some(payload), _) => format!("{}\n{}", header, serde_json::to_string_pretty(payload).unwrap_or_default()),
+        (None, Some(e)) => format!("{}\n{} {}", header, "Decryption Failed:".red(), e),
+        (None, None) => header,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::{now_secs, Role, StoredSession};
+    use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+
+    const LOCAL: &str = "did:key:z6MkLocal";
+
+    fn message(kid: &str, seq: u64) -> RelayMessage {
+        let header = URL_SAFE_NO_PAD.encode(json!({ "alg": "dir", "kid": kid, "seq": seq }).to_string());
+        RelayMessage {
+            id: format!("m{}", seq),
+            recipient: LOCAL.to_string(),
+            content: format!("{}..iv.ct.tag", header),
+            received_at: Some(1.0),
+            status: None,
         }
     }
 
-    // Unreachable
-    // Ok(())
+    fn store_with_rx_seq(dir: &std::path::Path, rx_seq: Option<u64>) -> SessionStore {
+        let store = SessionStore::open(dir.to_path_buf());
+        store.save(&StoredSession {
+            peer_did: "did:web:peer".to_string(),
+            local_did: LOCAL.to_string(),
+            identity: None,
+            role: Role::Responder,
+            kid: "ab".repeat(16),
+            transcript_hash: "00".repeat(32),
+            cipher_suite: "test".to_string(),
+            tx_key: "11".repeat(32),
+            rx_key: "22".repeat(32),
+            tx_seq: 0,
+            rx_seq,
+            created_at: now_secs(),
+            expires_at: now_secs() + 60,
+        }).unwrap();
+        store
+    }
+
+    #[test]
+    fn messages_queued_before_start_are_delivered() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store_with_rx_seq(dir.path(), None);
+        let kid = "ab".repeat(16);
+
+        // received_at is far in the past, but nothing has been received in this session yet
+        assert!(!is_stale(&store, LOCAL, &message(&kid, 0)));
+    }
+
+    #[test]
+    fn sequence_numbers_already_received_are_stale() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store_with_rx_seq(dir.path(), Some(3));
+        let kid = "ab".repeat(16);
+
+        assert!(is_stale(&store, LOCAL, &message(&kid, 3)));
+        assert!(!is_stale(&store, LOCAL, &message(&kid, 4)));
+        assert!(!is_stale(&store, LOCAL, &message(&"cd".repeat(16), 1)));
+    }
 }
@@ -1,14 +1,52 @@
+use anyhow::{bail, Context as _, Result};
+use std::io::Read;
 use std::path::PathBuf;
 
 pub struct Context {
     pub json: bool,
     pub verbose: bool,
     pub config: Option<PathBuf>,
+    pub profile: Option<String>,
 }
 
+/// Reads a payload from an inline argument, a file, or stdin (`-` for either).
+pub fn read_input(inline: Option<String>, file: Option<PathBuf>) -> Result<Vec<u8>> {
+    match (inline, file) {
+        (Some(_), Some(_)) => bail!("Pass either an inline value or --file, not both"),
+        (Some(s), None) if s == "-" => read_stdin(),
+        (Some(s), None) => Ok(s.into_bytes()),
+        (None, Some(p)) if p.as_os_str() == "-" => read_stdin(),
+        (None, Some(p)) => std::fs::read(&p).with_context(|| format!("Failed to read {}", p.display())),
+        (None, None) => bail!("No input given (pass a value, --file <path>, or - for stdin)"),
+    }
+}
+
+/// Reads a JSON document from a file or stdin, unwrapping `--json` envelopes.
+pub fn read_json(path: PathBuf) -> Result<serde_json::Value> {
+    let bytes = read_input(None, Some(path.clone()))?;
+    let value = serde_json::from_slice(&bytes).with_context(|| format!("{} is not JSON", path.display()))?;
+    Ok(crate::output::unwrap_envelope(value))
+}
+
+fn read_stdin() -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    std::io::stdin().read_to_end(&mut buf).context("Failed to read stdin")?;
+    Ok(buf)
+}
+
+pub mod config;
 pub mod did;
+pub mod discover;
+pub mod identity;
 pub mod relay;
 pub mod msg;
+pub mod oacp;
+pub mod pay;
 pub mod connect;
+pub mod handshake;
 pub mod listen;
+pub mod run;
 pub mod send;
+pub mod session;
+pub mod shell;
+pub mod validate;
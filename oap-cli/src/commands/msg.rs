@@ -1,88 +1,248 @@
-use anyhow::{Context as _, Result};
+use anyhow::{anyhow, bail, Context as _, Result};
 use colored::*;
+use oap::oaep::keys::KeyPair;
 use oap::oatp::JweContainer;
-use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
-use std::fs;
-use crate::commands::Context;
+use oap::oatp::container::{decrypt_padded, encrypt_padded};
+use serde::Serialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use crate::commands::{read_input, Context};
+use crate::jwe;
+use crate::keystore::{self, Keystore, KeystoreEntry};
+use crate::output::{self, ErrorKind};
+use crate::relay_client::RelayMessage;
+use crate::resolver;
+use crate::session::{SessionStore, StoredSession, DEFAULT_PADDING_BLOCK};
+
+pub struct EncryptOptions {
+    /// Recipient X25519 public key (did:key, hex or multibase)
+    pub to: Option<String>,
+    /// Peer DID of a stored session
+    pub session: Option<String>,
+    /// Raw 32-byte content key (hex)
+    pub session_key: Option<String>,
+    pub kid: Option<String>,
+    pub seq: Option<u64>,
+    pub padding: Option<usize>,
+    pub format: String,
+}
 
 pub async fn decode(jwe_str: String, ctx: &Context) -> Result<()> {
     if ctx.verbose {
         println!("{}", "Decoding JWE Headers...".cyan());
     }
 
-    let parts: Vec<&str> = jwe_str.split('.').collect();
-    if parts.is_empty() {
-        if ctx.json { println!("{{ \"error\": \"Invalid JWE\" }}"); }
-        else { println!("{}", "Invalid JWE format".red()); }
-        return Ok(());
+    let raw = String::from_utf8(read_input(Some(jwe_str), None)?).context("JWE is not UTF-8")?;
+    let report = jwe::inspect(&raw);
+
+    if !ctx.json {
+        print_inspection(&report)?;
     }
+    let mut value = serde_json::to_value(&report)?;
+    value["valid"] = json!(report.is_valid());
 
-    let header_b64 = parts[0];
-    let header_bytes = URL_SAFE_NO_PAD.decode(header_b64)
-        .or_else(|_| base64::engine::general_purpose::STANDARD.decode(header_b64))
-        .context("Failed to decode header Base64")?;
+    if !report.is_valid() {
+        let err = output::error(ErrorKind::Validation, format!("Malformed JWE: {}", report.errors.join("; ")));
+        return Err(output::fail(ctx.json, &value, err));
+    }
+    if ctx.json {
+        output::emit(&value)?;
+    }
+    Ok(())
+}
 
-    let header_json: serde_json::Value = serde_json::from_slice(&header_bytes)
-        .context("Failed to parse header JSON")?;
+fn print_inspection(report: &jwe::Inspection) -> Result<()> {
+    println!("Serialization: {}", report.serialization);
+    if let Some(header) = &report.header {
+        println!("{}", serde_json::to_string_pretty(header)?);
+    }
+    if let Some(kid) = &report.kid {
+        println!("Key ID (kid): {}", kid.green());
+    }
+    if let Some(alg) = &report.alg {
+        println!("Algorithm (alg): {}", alg.yellow());
+    }
+    if let Some(enc) = &report.enc {
+        println!("Encryption (enc): {}", enc.yellow());
+    }
+    if let Some(seq) = report.seq {
+        println!("Sequence (seq): {}", seq);
+    }
+    for segment in &report.segments {
+        println!("{:<14} {} bytes", segment.name, segment.length);
+    }
+    match report.padding_block {
+        Some(block) => println!("Padding Block: {} bytes", block),
+        None => println!("Padding Block: unknown"),
+    }
+    for error in &report.errors {
+        println!("{} {}", "Malformed:".red(), error);
+    }
+    Ok(())
+}
 
-    if ctx.json {
-        println!("{}", serde_json::to_string_pretty(&header_json)?);
-    } else {
-        println!("{}", serde_json::to_string_pretty(&header_json)?);
-        if let Some(kid) = header_json.get("kid") {
-            println!("Key ID (kid): {}", kid.to_string().green());
-        }
-        if let Some(alg) = header_json.get("alg") {
-            println!("Algorithm (alg): {}", alg.to_string().yellow());
-        }
-        if let Some(enc) = header_json.get("enc") {
-            println!("Encryption (enc): {}", enc.to_string().yellow());
+/// Which key decrypted a message.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum KeySource {
+    Key,
+    Identity { alias: String, did: String },
+    Session { peer: String, kid: String, direction: &'static str },
+}
+
+impl fmt::Display for KeySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeySource::Key => write!(f, "the given key"),
+            KeySource::Identity { alias, did } => write!(f, "identity '{}' ({})", alias, did),
+            KeySource::Session { peer, direction, .. } => write!(f, "session with {} ({} key)", peer, direction),
         }
     }
+}
 
-    Ok(())
+/// Keys tried by `msg decrypt`: either the one given with `--key`, or every stored
+/// session whose `kid` matches followed by the keystore identities. Identity secrets are
+/// unlocked on first use; identities that don't match the `kid` are only tried when a
+/// passphrase is given (`OAP_PASSPHRASE`), so discovery never prompts for unrelated aliases.
+pub struct Keyring {
+    explicit: Option<([u8; 32], KeySource)>,
+    sessions: Vec<StoredSession>,
+    keystore: Keystore,
+    identities: Vec<KeystoreEntry>,
+    /// Passphrase for every identity; without one, each identity prompts when unlocked
+    passphrase: Option<String>,
+    /// X25519 secrets of the identities unlocked so far, by alias
+    unlocked: HashMap<String, [u8; 32]>,
 }
 
-pub async fn decrypt(jwe_str: String, key_path: String, ctx: &Context) -> Result<()> {
-    if ctx.verbose {
-        println!("Decrypting JWE...");
+impl Keyring {
+    pub fn discover() -> Result<Self> {
+        let keystore = Keystore::open_default()?;
+        Ok(Self {
+            explicit: None,
+            // Expired sessions are kept: dumps are often decrypted long after the fact
+            sessions: SessionStore::open_default()?.list()?,
+            identities: keystore.list()?,
+            keystore,
+            passphrase: std::env::var(keystore::PASSPHRASE_ENV).ok(),
+            unlocked: HashMap::new(),
+        })
     }
 
-    let secret_bytes = if std::path::Path::new(&key_path).exists() {
-        let content = fs::read_to_string(&key_path).context("Failed to read key file")?;
-        if let Ok(json) = serde_json::from_str::<serde_json::Value>(&content) {
-            if let Some(sk) = json.get("secret_key") {
-                hex::decode(sk.as_str().unwrap_or(""))?
-            } else {
-                hex::decode(content.trim())?
-            }
+    /// `key` is a keystore alias, or an identity secret given as a key file path or a
+    /// hex/multibase string. Either way the message is decrypted with its X25519 key.
+    pub fn with_key(key: &str) -> Result<Self> {
+        let keystore = Keystore::open_default()?;
+        let explicit = if !Path::new(key).exists() && keystore.exists(key) {
+            let did_key = keystore::load_identity(key)?;
+            let source = KeySource::Identity { alias: key.to_string(), did: did_key.did().to_string() };
+            (*did_key.keypair().to_x25519().as_bytes(), source)
         } else {
-            hex::decode(content.trim())?
+            let keypair = KeyPair::from_secret_bytes(&keystore::parse_secret(key)?)?;
+            (*keypair.to_x25519().as_bytes(), KeySource::Key)
+        };
+        Ok(Self {
+            explicit: Some(explicit),
+            sessions: Vec::new(),
+            keystore,
+            identities: Vec::new(),
+            passphrase: None,
+            unlocked: HashMap::new(),
+        })
+    }
+
+    pub fn decrypt(&mut self, raw: &str) -> Result<(Vec<u8>, KeySource)> {
+        let container = jwe::parse_container(raw)?;
+
+        if let Some((secret, source)) = &self.explicit {
+            let payload = container.decrypt(secret).map_err(|e| anyhow!("Decryption failed: {}", e))?;
+            return Ok((payload, source.clone()));
+        }
+
+        let kid = jwe::protected_header(raw).ok()
+            .and_then(|h| h.get("kid").and_then(|v| v.as_str()).map(str::to_string));
+
+        if let Some(kid) = &kid {
+            for session in self.sessions.iter().filter(|s| &s.kid == kid) {
+                for (direction, key) in [("rx", session.rx_key_bytes()?), ("tx", session.tx_key_bytes()?)] {
+                    if let Ok(payload) = decrypt_padded(&container, &key) {
+                        let source = KeySource::Session { peer: session.peer_did.clone(), kid: kid.clone(), direction };
+                        return Ok((payload, source));
+                    }
+                }
+            }
+        }
+
+        let matches_kid = |entry: &KeystoreEntry| {
+            kid.as_deref().is_some_and(|k| k == entry.did || k.starts_with(&format!("{}#", entry.did)))
+        };
+        let non_interactive = self.passphrase.is_some();
+        let candidates: Vec<KeystoreEntry> = self.identities.iter().filter(|e| matches_kid(e)).cloned()
+            .chain(self.identities.iter().filter(|e| !matches_kid(e) && non_interactive).cloned())
+            .collect();
+        // An identity that fails to unlock (wrong passphrase, corrupt file) is skipped
+        let mut locked = Vec::new();
+        for entry in candidates {
+            let Ok(secret) = self.unlock(&entry.alias) else {
+                locked.push(entry.alias);
+                continue;
+            };
+            if let Ok(payload) = container.decrypt(&secret) {
+                return Ok((payload, KeySource::Identity { alias: entry.alias, did: entry.did }));
+            }
         }
-    } else {
-        hex::decode(&key_path).context("Failed to decode key hex")?
-    };
 
-    if secret_bytes.len() != 32 {
-        if !ctx.json { println!("{}", "Invalid secret key length".red()); }
+        let mut message = format!(
+            "No stored session or keystore identity could decrypt the message (kid: {})",
+            kid.as_deref().unwrap_or("none")
+        );
+        if !locked.is_empty() {
+            message.push_str(&format!("; could not unlock: {}", locked.join(", ")));
+        }
+        bail!(message)
+    }
+
+    fn unlock(&mut self, alias: &str) -> Result<[u8; 32]> {
+        if let Some(secret) = self.unlocked.get(alias) {
+            return Ok(*secret);
+        }
+        // Messages are encrypted to the X25519 key agreement key, not the Ed25519 key
+        let passphrase = match &self.passphrase {
+            Some(passphrase) => passphrase.clone(),
+            None => keystore::read_passphrase(&format!("Passphrase for '{}': ", alias), false)?,
+        };
+        let secret = *self.keystore.load(alias, &passphrase)?.keypair().to_x25519().as_bytes();
+        self.unlocked.insert(alias.to_string(), secret);
+        Ok(secret)
+    }
+}
+
+pub async fn decrypt(jwe_str: String, key_path: Option<String>, ctx: &Context) -> Result<()> {
+    if ctx.verbose {
+        println!("Decrypting JWE...");
     }
 
-    let jwe = JweContainer::from_compact(&jwe_str).context("Failed to parse JWE")?;
+    let mut keyring = match &key_path {
+        Some(key) => Keyring::with_key(key)?,
+        None => Keyring::discover()?,
+    };
 
-    let secret_array: [u8; 32] = secret_bytes.clone().try_into()
-        .map_err(|_| anyhow::anyhow!("Invalid key length"))?;
+    let raw = String::from_utf8(read_input(Some(jwe_str), None)?).context("JWE is not UTF-8")?;
 
-    match jwe.decrypt(&secret_array) {
-        Ok(payload) => {
+    match keyring.decrypt(&raw) {
+        Ok((payload, source)) => {
             if ctx.json {
-                 if let Ok(json) = serde_json::from_slice::<serde_json::Value>(&payload) {
-                    println!("{}", serde_json::to_string(&json)?);
-                 } else {
-                    // Output as hex string in json
-                    println!("{{ \"payload_hex\": \"{}\" }}", hex::encode(payload));
-                 }
+                output::emit(&json!({
+                    "decrypted_with": source,
+                    "payload": jwe::payload_value(&payload),
+                }))?;
             } else {
                 println!("{}", "Decryption Successful!".green());
+                if key_path.is_none() || ctx.verbose {
+                    println!("Decrypted with {}", source.to_string().cyan());
+                }
                 if let Ok(json) = serde_json::from_slice::<serde_json::Value>(&payload) {
                     println!("{}", serde_json::to_string_pretty(&json)?);
                 } else {
@@ -91,14 +251,235 @@ pub async fn decrypt(jwe_str: String, key_path: String, ctx: &Context) -> Result
                 }
             }
         }
-        Err(e) => {
-            if ctx.json {
-                println!("{{ \"error\": \"{}\" }}", e);
-            } else {
-                println!("{} {}", "Decryption Failed:".red(), e);
+        Err(e) => return Err(output::error(ErrorKind::Crypto, format!("{:#}", e))),
+    }
+
+    Ok(())
+}
+
+/// One line of the `msg decrypt --input` report.
+#[derive(Debug, Serialize)]
+pub struct BatchResult {
+    pub index: usize,
+    pub id: Option<String>,
+    pub recipient: Option<String>,
+    pub ok: bool,
+    pub decrypted_with: Option<KeySource>,
+    pub payload: Option<serde_json::Value>,
+    pub error: Option<String>,
+}
+
+/// One message from a relay dump.
+pub struct DumpEntry {
+    pub id: Option<String>,
+    pub recipient: Option<String>,
+    pub jwe: String,
+}
+
+impl From<RelayMessage> for DumpEntry {
+    fn from(m: RelayMessage) -> Self {
+        Self { id: Some(m.id), recipient: Some(m.recipient), jwe: m.content }
+    }
+}
+
+/// Splits a dump into messages. Accepts the relay's `GET /messages`
+/// JSON array, or one JWE (compact or JSON) or relay message object per line.
+pub fn parse_dump(input: &str) -> Result<Vec<DumpEntry>> {
+    let trimmed = input.trim();
+    if trimmed.starts_with('[') {
+        let messages: Vec<RelayMessage> = serde_json::from_str(trimmed)
+            .context("Input looks like a JSON array but is not a relay message list")?;
+        return Ok(messages.into_iter().map(DumpEntry::from).collect());
+    }
+
+    Ok(trimmed.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| match serde_json::from_str::<RelayMessage>(line) {
+            Ok(m) => DumpEntry::from(m),
+            Err(_) => DumpEntry { id: None, recipient: None, jwe: line.to_string() },
+        })
+        .collect())
+}
+
+pub async fn decrypt_batch(input: PathBuf, key_path: Option<String>, ctx: &Context) -> Result<()> {
+    let raw = String::from_utf8(read_input(None, Some(input))?).context("Input is not UTF-8")?;
+    let entries = parse_dump(&raw)?;
+
+    let mut keyring = match &key_path {
+        Some(key) => Keyring::with_key(key)?,
+        None => Keyring::discover()?,
+    };
+
+    let total = entries.len();
+    let mut decrypted = 0;
+    for (i, entry) in entries.into_iter().enumerate() {
+        let result = match keyring.decrypt(&entry.jwe) {
+            Ok((payload, source)) => {
+                decrypted += 1;
+                BatchResult {
+                    index: i + 1,
+                    id: entry.id,
+                    recipient: entry.recipient,
+                    ok: true,
+                    decrypted_with: Some(source),
+                    payload: Some(jwe::payload_value(&payload)),
+                    error: None,
+                }
             }
+            Err(e) => BatchResult {
+                index: i + 1,
+                id: entry.id,
+                recipient: entry.recipient,
+                ok: false,
+                decrypted_with: None,
+                payload: None,
+                error: Some(e.to_string()),
+            },
+        };
+        if ctx.json {
+            output::emit_line(&result)?;
+        } else {
+            println!("{}", serde_json::to_string(&result)?);
         }
     }
 
+    if ctx.verbose || !ctx.json {
+        eprintln!("Decrypted {} of {} messages", decrypted, total);
+    }
+    if decrypted < total {
+        return Err(output::error(ErrorKind::Crypto, format!("{} of {} messages could not be decrypted", total - decrypted, total)));
+    }
+
     Ok(())
 }
+
+pub async fn encrypt(message: Option<String>, file: Option<PathBuf>, opts: EncryptOptions, ctx: &Context) -> Result<()> {
+    if !matches!(opts.format.as_str(), "compact" | "json") {
+        bail!("Unknown format '{}' (expected compact or json)", opts.format);
+    }
+    let plaintext = read_input(message, file)?;
+
+    let (container, kid, seq, padding) = match (&opts.to, &opts.session, &opts.session_key) {
+        (Some(to), None, None) => {
+            if opts.kid.is_some() || opts.seq.is_some() || opts.padding.is_some() {
+                bail!("--kid, --seq and --padding need --session or --session-key");
+            }
+            let public = resolver::parse_x25519_public(to)?;
+            let container = JweContainer::encrypt(&plaintext, &public)
+                .map_err(|e| anyhow!("Encryption failed: {}", e))?;
+            (container, None, None, None)
+        }
+        (None, Some(peer), None) => {
+            let store = SessionStore::open_default()?;
+            let session = store.load_active(peer)?;
+            let key = session.tx_key_bytes()?;
+            // An explicit --seq leaves the stored counter alone so test vectors can be replayed.
+            let seq = match opts.seq {
+                Some(seq) => seq,
+                None => store.update(peer, |session| Ok(session.next_tx_seq()))?,
+            };
+            let kid = opts.kid.clone().unwrap_or_else(|| session.kid.clone());
+            let padding = opts.padding.unwrap_or(DEFAULT_PADDING_BLOCK);
+            let container = encrypt_padded(&plaintext, &key, &kid, seq, padding)
+                .map_err(|e| anyhow!("Encryption failed: {}", e))?;
+            (container, Some(kid), Some(seq), Some(padding))
+        }
+        (None, None, Some(hex_key)) => {
+            let key: [u8; 32] = hex::decode(hex_key)
+                .context("--session-key must be hex")?
+                .try_into()
+                .map_err(|b: Vec<u8>| anyhow!("Invalid session key length: expected 32 bytes, got {}", b.len()))?;
+            let kid = opts.kid.clone().ok_or_else(|| anyhow!("--session-key needs an explicit --kid"))?;
+            let seq = opts.seq.unwrap_or(0);
+            let padding = opts.padding.unwrap_or(DEFAULT_PADDING_BLOCK);
+            let container = encrypt_padded(&plaintext, &key, &kid, seq, padding)
+                .map_err(|e| anyhow!("Encryption failed: {}", e))?;
+            (container, Some(kid), Some(seq), Some(padding))
+        }
+        _ => bail!("Specify exactly one of --to, --session or --session-key"),
+    };
+
+    if ctx.verbose {
+        eprintln!("Encrypted {} bytes (kid: {}, seq: {}, padding: {})",
+            plaintext.len(),
+            kid.as_deref().unwrap_or("-"),
+            seq.map(|v| v.to_string()).unwrap_or_else(|| "-".to_string()),
+            padding.map(|v| v.to_string()).unwrap_or_else(|| "-".to_string()),
+        );
+    }
+
+    let serialized = match opts.format.as_str() {
+        "json" => serde_json::to_value(&container)?,
+        _ => json!(container.to_compact()),
+    };
+
+    if ctx.json {
+        output::emit(&json!({
+            "format": opts.format,
+            "kid": kid,
+            "seq": seq,
+            "padding": padding,
+            "plaintext_len": plaintext.len(),
+            "jwe": serialized,
+        }))?;
+    } else {
+        match serialized {
+            serde_json::Value::String(compact) => println!("{}", compact),
+            other => println!("{}", serde_json::to_string_pretty(&other)?),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oap::oaep::did::DidKey;
+
+    fn keyring(keystore: Keystore, passphrase: &str) -> Keyring {
+        Keyring {
+            explicit: None,
+            sessions: Vec::new(),
+            identities: keystore.list().unwrap(),
+            keystore,
+            passphrase: Some(passphrase.to_string()),
+            unlocked: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn keystore_identities_decrypt_messages_sent_to_their_did() {
+        let dir = tempfile::tempdir().unwrap();
+        let keystore = Keystore::open(dir.path().to_path_buf());
+        let did_key = DidKey::generate();
+        let did = did_key.did().to_string();
+        keystore.save("bob", &did_key, "pw").unwrap();
+
+        let recipient = resolver::parse_x25519_public(&did).unwrap();
+        let container = JweContainer::encrypt(br#"{"hello":"bob"}"#, &recipient).unwrap();
+        let mut keyring = keyring(keystore, "pw");
+
+        let (payload, source) = keyring.decrypt(&container.to_compact()).unwrap();
+        assert_eq!(jwe::payload_value(&payload), json!({ "hello": "bob" }));
+        assert!(matches!(source, KeySource::Identity { alias, did: d } if alias == "bob" && d == did));
+    }
+
+    #[test]
+    fn identities_that_fail_to_unlock_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let keystore = Keystore::open(dir.path().to_path_buf());
+        keystore.save("alice", &DidKey::generate(), "other").unwrap();
+        let did_key = DidKey::generate();
+        keystore.save("bob", &did_key, "pw").unwrap();
+
+        let recipient = resolver::parse_x25519_public(&did_key.did().to_string()).unwrap();
+        let container = JweContainer::encrypt(b"hi", &recipient).unwrap();
+        let mut keyring = keyring(keystore, "pw");
+
+        let (payload, source) = keyring.decrypt(&container.to_compact()).unwrap();
+        assert_eq!(payload, b"hi");
+        assert!(matches!(source, KeySource::Identity { alias, .. } if alias == "bob"));
+    }
+}
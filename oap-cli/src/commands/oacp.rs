@@ -0,0 +1,201 @@
+use anyhow::{bail, Context as _, Result};
+use colored::*;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use crate::commands::{listen, read_json, send, Context};
+use crate::oacp::{self, Terms};
+use crate::output::{self, ErrorKind};
+use crate::relay_client::RelayClient;
+use crate::schema::OACP_CONTEXT;
+use crate::session::SessionStore;
+
+/// Builds one object and prints it. `previous` is the object being replied to.
+pub async fn create(
+    kind: &str,
+    previous: Option<PathBuf>,
+    template: Option<PathBuf>,
+    mut terms: Terms,
+    details: Option<PathBuf>,
+    ctx: &Context,
+) -> Result<()> {
+    let previous = previous.map(read_json).transpose()?;
+    let template = template.map(read_json).transpose()?;
+    if let Some(path) = details {
+        terms.details = Some(read_json(path)?);
+    }
+
+    let object = oacp::build(kind, previous.as_ref(), template, terms)
+        .map_err(|e| output::error(ErrorKind::Validation, format!("{:#}", e)))?;
+    if ctx.json {
+        output::emit(&object)?;
+    } else {
+        println!("{}", serde_json::to_string_pretty(&object)?);
+    }
+    Ok(())
+}
+
+/// A scripted negotiation, e.g.
+///
+/// ```toml
+/// peer = "did:web:localhost%3A8080"
+///
+/// [[steps]]
+/// send = "Offer"
+/// amount = 10.0
+/// currency = "EUR"
+///
+/// [[steps]]
+/// expect = ["CounterProposal", "Acceptance"]
+/// ```
+#[derive(Debug, Deserialize)]
+struct Script {
+    peer: String,
+    /// How long each `expect` step waits for the peer
+    #[serde(default = "default_step_timeout")]
+    timeout_secs: u64,
+    steps: Vec<Step>,
+}
+
+fn default_step_timeout() -> u64 {
+    30
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Expect {
+    One(String),
+    Any(Vec<String>),
+}
+
+impl Expect {
+    fn types(&self) -> Vec<&str> {
+        match self {
+            Expect::One(t) => vec![t.as_str()],
+            Expect::Any(ts) => ts.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Step {
+    /// OACP type to build and send
+    send: Option<String>,
+    /// OACP type(s) the peer must answer with
+    expect: Option<Expect>,
+    #[serde(flatten)]
+    terms: Terms,
+}
+
+fn load_script(path: &PathBuf) -> Result<Script> {
+    let content = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    if path.extension().and_then(|e| e.to_str()) == Some("json") {
+        serde_json::from_str(&content).context("Invalid play script")
+    } else {
+        toml::from_str(&content).context("Invalid play script")
+    }
+}
+
+fn print_step(ctx: &Context, index: usize, direction: &str, object: &Value) -> Result<()> {
+    let kind = object.get("type").and_then(|v| v.as_str()).unwrap_or("?");
+    let id = object.get("id").and_then(|v| v.as_str()).unwrap_or("?");
+    if ctx.json {
+        output::emit_line(&json!({ "step": index, "direction": direction, "type": kind, "id": id, "object": object }))?;
+    } else {
+        let arrow = if direction == "sent" { "→".green() } else { "←".cyan() };
+        println!("[{}] {} {} {}", index, arrow, kind.bold(), id.dimmed());
+        if ctx.verbose {
+            println!("{}", serde_json::to_string_pretty(object).unwrap_or_default().dimmed());
+        }
+    }
+    Ok(())
+}
+
+/// Runs a scripted negotiation against `peer` over the stored session and the relay.
+pub async fn play(script_path: PathBuf, relay: Option<String>, ctx: &Context) -> Result<()> {
+    let script = load_script(&script_path)?;
+    let cfg = crate::config::load(ctx.config.clone(), ctx.profile.clone()).await?;
+    let relay = RelayClient::new(relay.as_deref().unwrap_or(&cfg.default_relay), cfg.http_client()?);
+    let store = SessionStore::open_default()?;
+    let local_did = store.load_active(&script.peer)?.local_did;
+
+    // Anything already in the inbox predates this run
+    let mut seen: HashSet<String> = relay.messages(Some(&local_did)).await?
+        .into_iter()
+        .map(|m| m.id)
+        .collect();
+
+    let mut last: Option<Value> = None;
+    for (i, step) in script.steps.into_iter().enumerate() {
+        let index = i + 1;
+        match (step.send, step.expect) {
+            (Some(kind), None) => {
+                let mut terms = step.terms;
+                if kind == "Offer" {
+                    terms.provider.get_or_insert_with(|| local_did.clone());
+                    terms.consumer.get_or_insert_with(|| script.peer.clone());
+                }
+                let previous = if kind == "Offer" { None } else { last.as_ref() };
+                let object = oacp::build(&kind, previous, None, terms)
+                    .with_context(|| format!("Step {}", index))?;
+                send::deliver(&store, &relay, &script.peer, &serde_json::to_vec(&object)?).await
+                    .with_context(|| format!("Step {}: delivery failed", index))?;
+                print_step(ctx, index, "sent", &object)?;
+                last = Some(object);
+            }
+            (None, Some(expect)) => {
+                let object = receive(&relay, &store, &local_did, &mut seen, last.as_ref(), script.timeout_secs).await
+                    .with_context(|| format!("Step {}", index))?;
+                print_step(ctx, index, "received", &object)?;
+                let kind = object.get("type").and_then(|v| v.as_str()).unwrap_or("?");
+                if !expect.types().contains(&kind) {
+                    let message = format!("Step {}: expected {}, got {}", index, expect.types().join(" or "), kind);
+                    return Err(output::error(ErrorKind::Validation, message));
+                }
+                last = Some(object);
+            }
+            _ => bail!("Step {}: set exactly one of 'send' or 'expect'", index),
+        }
+    }
+
+    if !ctx.json {
+        println!("{}", "Negotiation completed".green().bold());
+    }
+    Ok(())
+}
+
+/// Waits for the next OACP object from the peer that belongs to the current thread.
+async fn receive(
+    relay: &RelayClient,
+    store: &SessionStore,
+    local_did: &str,
+    seen: &mut HashSet<String>,
+    last: Option<&Value>,
+    timeout_secs: u64,
+) -> Result<Value> {
+    let thread = last.and_then(|l| l.get("thread").or_else(|| l.get("id"))).cloned();
+    let deadline = Instant::now() + Duration::from_secs(timeout_secs);
+
+    loop {
+        for msg in relay.messages(Some(local_did)).await? {
+            if !seen.insert(msg.id.clone()) {
+                continue;
+            }
+            let incoming = listen::process(store, local_did, msg);
+            let Some(payload) = incoming.payload else { continue };
+            if payload.get("@context").and_then(|v| v.as_str()) != Some(OACP_CONTEXT) {
+                continue;
+            }
+            if thread.is_some() && payload.get("thread") != thread.as_ref() {
+                continue;
+            }
+            return Ok(payload);
+        }
+        if Instant::now() >= deadline {
+            return Err(output::error(ErrorKind::Timeout, format!("No OACP reply within {}s", timeout_secs)));
+        }
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+    }
+}
@@ -0,0 +1,200 @@
+use anyhow::{anyhow, bail, Result};
+use colored::*;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use crate::commands::{read_json, Context};
+use crate::config::Config;
+use crate::handshake;
+use crate::keystore::{self, Keystore};
+use crate::ledger_client::{LedgerClient, TransferRequest, UNITS_PER_OAP};
+use crate::output::{self, ErrorKind};
+use crate::schema::{self, Protocol, OAPP_CONTEXT};
+
+/// The only currency the localnet ledger settles.
+pub const LEDGER_CURRENCY: &str = "OAP";
+
+fn ledger(url: Option<&str>, cfg: &Config) -> Result<LedgerClient> {
+    Ok(LedgerClient::new(url.unwrap_or(&cfg.ledger_url), cfg.http_client()?))
+}
+
+/// A DID given directly, or the DID of a keystore identity (no passphrase needed).
+fn target_did(did: Option<String>, identity: Option<String>, cfg: &Config) -> Result<String> {
+    if let Some(did) = did {
+        return Ok(did);
+    }
+    let alias = identity.or(cfg.default_identity.clone())
+        .ok_or_else(|| anyhow!("Pass a DID or --identity"))?;
+    Ok(Keystore::open_default()?.entry(&alias)?.did)
+}
+
+fn oap(units: u64) -> f64 {
+    units as f64 / UNITS_PER_OAP
+}
+
+/// Stamps `@context`, id, type and `created` on an OAPP object and validates it.
+fn finish(kind: &str, mut object: Value) -> Result<Value> {
+    object["@context"] = json!(OAPP_CONTEXT);
+    object["id"] = json!(format!("urn:uuid:{}", uuid::Uuid::new_v4()));
+    object["type"] = json!(kind);
+    object["created"] = json!(chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true));
+
+    let errors = schema::validate(Protocol::Oapp, &object)?;
+    if let Some(e) = errors.first() {
+        bail!("Generated {} is invalid at '{}': {}", kind, e.path, e.message);
+    }
+    Ok(object)
+}
+
+pub async fn fund(did: Option<String>, identity: Option<String>, ledger_url: Option<String>, ctx: &Context) -> Result<()> {
+    let cfg = crate::config::load(ctx.config.clone(), ctx.profile.clone()).await?;
+    let did = target_did(did, identity, &cfg)?;
+    let receipt = ledger(ledger_url.as_deref(), &cfg)?.faucet(&did).await?;
+
+    if ctx.json {
+        output::emit(&json!({ "status": "funded", "did": receipt.did, "balance": oap(receipt.new_balance), "balance_units": receipt.new_balance }))?;
+    } else {
+        println!("Funded {} from the faucet. Balance: {} {}", receipt.did.cyan(), oap(receipt.new_balance).to_string().green(), LEDGER_CURRENCY);
+    }
+    Ok(())
+}
+
+pub async fn balance(did: Option<String>, identity: Option<String>, ledger_url: Option<String>, ctx: &Context) -> Result<()> {
+    let cfg = crate::config::load(ctx.config.clone(), ctx.profile.clone()).await?;
+    let did = target_did(did, identity, &cfg)?;
+    let balance = ledger(ledger_url.as_deref(), &cfg)?.balance(&did).await?;
+
+    if ctx.json {
+        output::emit(&json!({ "did": balance.did, "balance": oap(balance.balance), "balance_units": balance.balance }))?;
+    } else {
+        println!("{}: {} {}", balance.did.cyan(), oap(balance.balance).to_string().green(), LEDGER_CURRENCY);
+    }
+    Ok(())
+}
+
+/// Payment amounts must be positive, finite numbers of OAP.
+fn check_amount(amount: f64) -> Result<()> {
+    if !amount.is_finite() || amount <= 0.0 {
+        return Err(output::error(ErrorKind::Usage, format!("Amount must be a positive number (got {})", amount)));
+    }
+    Ok(())
+}
+
+/// Creates a PaymentRequest, optionally linked to the OACP Agreement it pays for.
+pub async fn request(
+    amount: f64,
+    currency: String,
+    recipient: Option<String>,
+    identity: Option<String>,
+    agreement: Option<PathBuf>,
+    ctx: &Context,
+) -> Result<()> {
+    check_amount(amount)?;
+    let cfg = crate::config::load(ctx.config.clone(), ctx.profile.clone()).await?;
+    let recipient = target_did(recipient, identity, &cfg)?;
+
+    let mut object = json!({
+        "paymentDetails": { "total": amount, "currency": currency, "recipient": recipient },
+    });
+    if let Some(path) = agreement {
+        let agreement = read_json(path)?;
+        if agreement.get("type").and_then(|v| v.as_str()) != Some("Agreement") {
+            bail!("--agreement must be an OACP Agreement");
+        }
+        object["agreement"] = agreement.get("id").cloned().unwrap_or(Value::Null);
+        if let Some(thread) = agreement.get("thread") {
+            object["thread"] = thread.clone();
+        }
+    }
+
+    print_object(&finish("PaymentRequest", object)?, ctx)
+}
+
+/// Pays a PaymentRequest through the ledger's `/transfer` and prints the PaymentProof.
+pub async fn settle(request_path: PathBuf, identity: Option<String>, ledger_url: Option<String>, ctx: &Context) -> Result<()> {
+    let cfg = crate::config::load(ctx.config.clone(), ctx.profile.clone()).await?;
+    let request = read_json(request_path)?;
+    if request.get("type").and_then(|v| v.as_str()) != Some("PaymentRequest") {
+        return Err(output::error(ErrorKind::Usage, "Input is not an OAPP PaymentRequest"));
+    }
+    if let Some(e) = schema::validate(Protocol::Oapp, &request)?.first() {
+        let message = format!("PaymentRequest is invalid at '{}': {}", e.path, e.message);
+        return Err(output::error(ErrorKind::Validation, message));
+    }
+
+    let details = request.get("paymentDetails")
+        .ok_or_else(|| anyhow!("PaymentRequest has no paymentDetails"))?;
+    let currency = details["currency"].as_str().unwrap_or_default();
+    if currency != LEDGER_CURRENCY {
+        let message = format!("The ledger only settles {} (request is in {})", LEDGER_CURRENCY, currency);
+        return Err(output::error(ErrorKind::Usage, message));
+    }
+    let total = details["total"].as_f64().unwrap_or_default();
+    check_amount(total)?;
+    let recipient = details["recipient"].as_str().unwrap_or_default().to_string();
+
+    let alias = identity.or(cfg.default_identity.clone())
+        .ok_or_else(|| anyhow!("Pass --identity for the paying identity"))?;
+    let payer = keystore::load_identity(&alias)?;
+    let sender = payer.did().to_string();
+
+    let amount = (total * UNITS_PER_OAP).round() as u64;
+    let signing_input = handshake::signing_input(&json!({
+        "sender_did": sender,
+        "recipient_did": recipient,
+        "amount": amount,
+    }))?;
+    let transfer = TransferRequest {
+        sender_did: sender.clone(),
+        recipient_did: recipient.clone(),
+        amount,
+        signature: handshake::sign(&payer, &signing_input),
+    };
+
+    let ledger = ledger(ledger_url.as_deref(), &cfg)?;
+    if ctx.verbose {
+        println!("Transferring {} {} from {} to {} via {}", total, currency, sender, recipient, ledger.base());
+    }
+    let tx = ledger.transfer(&transfer).await?;
+
+    let mut proof = json!({
+        "paymentDetails": details,
+        "request": request.get("id"),
+        "payer": sender,
+        "transaction": tx,
+    });
+    for link in ["agreement", "thread"] {
+        if let Some(v) = request.get(link) {
+            proof[link] = v.clone();
+        }
+    }
+    let proof = finish("PaymentProof", proof)?;
+
+    if !ctx.json {
+        println!("{} {} ({})", "Payment Settled:".green(), tx.id, tx.status);
+    }
+    print_object(&proof, ctx)
+}
+
+/// Prints an OAPP object as-is, or wrapped in the envelope with `--json`.
+fn print_object(object: &Value, ctx: &Context) -> Result<()> {
+    if ctx.json {
+        output::emit(object)
+    } else {
+        println!("{}", serde_json::to_string_pretty(object)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn amounts_must_be_positive_and_finite() {
+        assert!(check_amount(0.5).is_ok());
+        for amount in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let err = check_amount(amount).unwrap_err();
+            assert_eq!(output::classify(&err), ErrorKind::Usage, "{}", amount);
+        }
+    }
+}
@@ -1,43 +1,638 @@
-use anyhow::Result;
+use anyhow::{anyhow, bail, Context as _, Result};
 use colored::*;
-use std::time::Instant;
+use serde::Serialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 use crate::commands::Context;
+use crate::config::Config;
+use crate::output::{self, ErrorKind};
+use crate::relay_client::{ChaosConfig, RelayClient};
 
 pub async fn ping(url: String, ctx: &Context) -> Result<()> {
     if ctx.verbose {
         println!("Pinging Relay at {}...", url.cyan());
     }
 
-    let client = reqwest::Client::new();
+    let cfg = crate::config::load(ctx.config.clone(), ctx.profile.clone()).await?;
+    let client = cfg.http_client()?;
     let start = Instant::now();
     
-    let response = client.get(&url).send().await;
+    let res = client.get(&url).send().await
+        .with_context(|| format!("Connection to {} failed", url))?;
 
     let duration = start.elapsed();
 
-    match response {
-        Ok(res) => {
-            if ctx.json {
-                println!("{{ \"status\": {}, \"latency_ms\": {} }}", res.status().as_u16(), duration.as_millis());
-            } else {
-                println!("Status: {}", res.status().to_string().green());
-                println!("Latency: {:.2?}", duration);
-                
-                if let Ok(text) = res.text().await {
-                    if !text.is_empty() {
-                        println!("Response: {}", text.dimmed());
+    if ctx.json {
+        output::emit(&json!({ "status": res.status().as_u16(), "latency_ms": duration.as_millis() }))?;
+    } else {
+        println!("Status: {}", res.status().to_string().green());
+        println!("Latency: {:.2?}", duration);
+
+        if let Ok(text) = res.text().await {
+            if !text.is_empty() {
+                println!("Response: {}", text.dimmed());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// One endpoint probe of `relay info`.
+#[derive(Debug, Serialize)]
+struct EndpointProbe {
+    method: &'static str,
+    path: &'static str,
+    supported: bool,
+    status: Option<u16>,
+}
+
+/// Certificate presented by an `https://` relay.
+#[derive(Debug, Default, Serialize)]
+struct TlsReport {
+    subject: String,
+    issuer: String,
+    names: Vec<String>,
+    not_before: String,
+    not_after: String,
+    sha256: String,
+    expired: bool,
+    self_signed: bool,
+    /// Whether a name in the certificate covers the relay host
+    hostname_match: bool,
+    /// Whether the certificate is (or was issued by) the `--ca` certificate
+    matches_ca: Option<bool>,
+    /// Whether a verifying client (trusting `--ca`, if given) accepts the connection
+    trusted: bool,
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct RelayInfo {
+    url: String,
+    reachable: bool,
+    latency_ms: Option<u128>,
+    relay_id: Option<String>,
+    version: Option<String>,
+    health: Option<Value>,
+    endpoints: Vec<EndpointProbe>,
+    /// `METHOD /path` entries from the relay's OpenAPI document, when it serves one
+    advertised: Vec<String>,
+    tls: Option<TlsReport>,
+}
+
+/// Endpoints of the OATP relay API. Probes only send requests that don't change state:
+/// `/inbox` gets an empty body, which a real inbox rejects as invalid rather than 404.
+const ENDPOINTS: &[(&str, &str)] = &[
+    ("GET", "/health"),
+    ("POST", "/inbox"),
+    ("GET", "/messages"),
+    ("GET", "/ws"),
+];
+
+async fn probe(client: &reqwest::Client, base: &str, method: &'static str, path: &'static str) -> EndpointProbe {
+    let url = format!("{}{}", base, path);
+    let req = match (method, path) {
+        ("POST", _) => client.post(&url).json(&json!({})),
+        (_, "/ws") => client.get(&url)
+            .header("Connection", "Upgrade")
+            .header("Upgrade", "websocket")
+            .header("Sec-WebSocket-Version", "13")
+            .header("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ=="),
+        _ => client.get(&url),
+    };
+    let status = req.send().await.ok().map(|r| r.status().as_u16());
+    let supported = match status {
+        Some(101) => true,
+        // A websocket route answers a plain GET with 400/426, anything else just 404s
+        Some(s) if path == "/ws" => s == 400 || s == 426,
+        Some(s) => s != 404 && s != 405,
+        None => false,
+    };
+    EndpointProbe { method, path, supported, status }
+}
+
+/// Does a certificate name (possibly `*.` wildcard) cover `host`?
+fn name_matches(name: &str, host: &str) -> bool {
+    match name.strip_prefix("*.") {
+        Some(suffix) => host.split_once('.').is_some_and(|(_, rest)| rest.eq_ignore_ascii_case(suffix)),
+        None => name.eq_ignore_ascii_case(host),
+    }
+}
+
+fn read_ca(path: &PathBuf) -> Result<Vec<u8>> {
+    let bytes = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    if bytes.starts_with(b"-----BEGIN") {
+        let (_, pem) = x509_parser::pem::parse_x509_pem(&bytes)
+            .map_err(|e| anyhow!("{} is not a PEM certificate: {}", path.display(), e))?;
+        Ok(pem.contents)
+    } else {
+        Ok(bytes)
+    }
+}
+
+async fn inspect_tls(cfg: &Config, url: &str, ca: Option<&PathBuf>) -> Result<TlsReport> {
+    use x509_parser::prelude::*;
+
+    let host = reqwest::Url::parse(url)?.host_str().unwrap_or_default().to_string();
+    let ca_der = ca.map(read_ca).transpose()?;
+
+    // First look at whatever certificate is presented, then check it would be trusted
+    let inspector = cfg.http_builder().tls_info(true).danger_accept_invalid_certs(true).build()?;
+    let res = inspector.get(format!("{}/health", url)).send().await
+        .with_context(|| format!("TLS connection to {} failed", url))?;
+    let der = res.extensions().get::<reqwest::tls::TlsInfo>()
+        .and_then(|info| info.peer_certificate())
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| anyhow!("Relay presented no certificate"))?;
+    let (_, cert) = X509Certificate::from_der(&der).map_err(|e| anyhow!("Unparseable relay certificate: {}", e))?;
+
+    let mut names: Vec<String> = cert.subject().iter_common_name()
+        .filter_map(|cn| cn.as_str().ok().map(str::to_string))
+        .collect();
+    if let Ok(Some(san)) = cert.subject_alternative_name() {
+        for name in &san.value.general_names {
+            if let GeneralName::DNSName(dns) = name {
+                names.push(dns.to_string());
+            }
+        }
+    }
+    let validity = cert.validity();
+    let mut report = TlsReport {
+        subject: cert.subject().to_string(),
+        issuer: cert.issuer().to_string(),
+        hostname_match: names.iter().any(|n| name_matches(n, &host)),
+        names,
+        not_before: validity.not_before.to_string(),
+        not_after: validity.not_after.to_string(),
+        sha256: hex::encode(Sha256::digest(&der)),
+        expired: !validity.is_valid(),
+        self_signed: cert.subject() == cert.issuer(),
+        ..Default::default()
+    };
+    if let Some(ca_der) = &ca_der {
+        let issued_by_ca = X509Certificate::from_der(ca_der)
+            .map(|(_, ca)| ca.subject() == cert.issuer())
+            .unwrap_or(false);
+        report.matches_ca = Some(ca_der == &der || issued_by_ca);
+    }
+
+    let mut verifier = cfg.http_builder();
+    if let Some(ca_der) = &ca_der {
+        verifier = verifier.add_root_certificate(reqwest::Certificate::from_der(ca_der)?);
+    }
+    match verifier.build()?.get(format!("{}/health", url)).send().await {
+        Ok(_) => report.trusted = true,
+        Err(e) => report.error = Some(anyhow::Error::from(e).root_cause().to_string()),
+    }
+    Ok(report)
+}
+
+/// Health, endpoint, version and TLS probe of a relay.
+pub async fn info(url: String, ca: Option<PathBuf>, ctx: &Context) -> Result<()> {
+    let cfg = crate::config::load(ctx.config.clone(), ctx.profile.clone()).await?;
+    let base = url.trim_end_matches('/').to_string();
+    // Self-signed relays are still probed; trust is reported separately under `tls`
+    let client = cfg.http_builder().danger_accept_invalid_certs(true).build()?;
+
+    let start = Instant::now();
+    let health = client.get(format!("{}/health", base)).send().await;
+    let latency_ms = start.elapsed().as_millis();
+
+    let mut info = RelayInfo {
+        url: base.clone(),
+        reachable: health.is_ok(),
+        latency_ms: None,
+        relay_id: None,
+        version: None,
+        health: None,
+        endpoints: Vec::new(),
+        advertised: Vec::new(),
+        tls: None,
+    };
+
+    if let Ok(res) = health {
+        info.latency_ms = Some(latency_ms);
+        info.version = res.headers().get("x-oatp-version")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        if let Ok(body) = res.json::<Value>().await {
+            info.relay_id = body.get("id").and_then(|v| v.as_str()).map(str::to_string);
+            if info.version.is_none() {
+                info.version = body.get("version").and_then(|v| v.as_str()).map(str::to_string);
+            }
+            info.health = Some(body);
+        }
+
+        for (method, path) in ENDPOINTS {
+            info.endpoints.push(probe(&client, &base, method, path).await);
+        }
+
+        if let Ok(res) = client.get(format!("{}/openapi.json", base)).send().await {
+            if let Ok(doc) = res.json::<Value>().await {
+                if info.version.is_none() {
+                    info.version = doc.pointer("/info/version").and_then(|v| v.as_str()).map(str::to_string);
+                }
+                if let Some(paths) = doc.get("paths").and_then(|p| p.as_object()) {
+                    for (path, ops) in paths {
+                        for method in ops.as_object().map(|o| o.keys().collect()).unwrap_or_else(Vec::new) {
+                            info.advertised.push(format!("{} {}", method.to_uppercase(), path));
+                        }
                     }
                 }
             }
         }
-        Err(e) => {
-            if ctx.json {
-                println!("{{ \"error\": \"{}\" }}", e);
-            } else {
-                println!("{} {}", "Connection Failed:".red(), e);
+
+        if base.starts_with("https://") {
+            // A failed probe is reported under `tls` instead of hiding the rest of the info
+            info.tls = Some(match inspect_tls(&cfg, &base, ca.as_ref()).await {
+                Ok(report) => report,
+                Err(e) => TlsReport { error: Some(format!("{:#}", e)), ..Default::default() },
+            });
+        }
+    }
+
+    if !info.reachable {
+        let err = output::error(ErrorKind::Network, format!("Relay at {} is unreachable", base));
+        return Err(output::fail(ctx.json, &info, err));
+    }
+    if ctx.json {
+        return output::emit(&info);
+    }
+    println!("Relay: {}", base.cyan());
+    println!("Health: {} ({} ms)", "ok".green(), latency_ms);
+    if let Some(id) = &info.relay_id {
+        println!("Relay ID: {}", id);
+    }
+    println!("Protocol version: {}", info.version.as_deref().unwrap_or("unknown"));
+    println!("Endpoints:");
+    for e in &info.endpoints {
+        let mark = if e.supported { "✓".green() } else { "✗".red() };
+        let status = e.status.map(|s| s.to_string()).unwrap_or_else(|| "-".to_string());
+        println!("  {} {:<6} {:<10} {}", mark, e.method, e.path, status.dimmed());
+    }
+    if ctx.verbose && !info.advertised.is_empty() {
+        println!("Advertised (OpenAPI): {}", info.advertised.join(", "));
+    }
+    if let Some(tls) = info.tls.as_ref().filter(|tls| tls.sha256.is_empty()) {
+        println!("TLS: {} ({})", "probe failed".red(), tls.error.as_deref().unwrap_or_default());
+    } else if let Some(tls) = &info.tls {
+        println!("TLS:");
+        println!("  Subject: {}", tls.subject);
+        println!("  Issuer: {}{}", tls.issuer, if tls.self_signed { " (self-signed)" } else { "" });
+        println!("  Names: {}", tls.names.join(", "));
+        println!("  Valid: {} → {}{}", tls.not_before, tls.not_after, if tls.expired { " EXPIRED".red().to_string() } else { String::new() });
+        println!("  SHA-256: {}", tls.sha256.dimmed());
+        println!("  Hostname match: {}", yes_no(tls.hostname_match));
+        if let Some(m) = tls.matches_ca {
+            println!("  Matches --ca: {}", yes_no(m));
+        }
+        match &tls.error {
+            None => println!("  Trusted: {}", yes_no(true)),
+            Some(e) => println!("  Trusted: {} ({})", yes_no(false), e.dimmed()),
+        }
+    }
+    Ok(())
+}
+
+fn yes_no(b: bool) -> ColoredString {
+    if b { "yes".green() } else { "no".red() }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum BenchMode {
+    /// `GET /messages` for an empty inbox (goes through the relay's fault injection, unlike /health)
+    Ping,
+    /// `POST /inbox` and poll `GET /messages` until the message shows up
+    Inbox,
+}
+
+#[derive(Debug, Serialize)]
+struct BenchReport {
+    url: String,
+    mode: String,
+    requests: usize,
+    ok: usize,
+    errors: usize,
+    error_rate: f64,
+    min_ms: Option<f64>,
+    p50_ms: Option<f64>,
+    p95_ms: Option<f64>,
+    p99_ms: Option<f64>,
+    max_ms: Option<f64>,
+    mean_ms: Option<f64>,
+}
+
+/// Nearest-rank percentile of sorted samples.
+fn percentile(sorted: &[f64], p: f64) -> Option<f64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+/// One ping or inbox round-trip. Posted message ids are pushed to `posted` for cleanup.
+async fn bench_once(relay: &RelayClient, mode: BenchMode, recipient: &str, poll_limit: Duration, posted: &mut Vec<String>) -> Result<()> {
+    match mode {
+        BenchMode::Ping => {
+            relay.messages(Some(recipient)).await?;
+        }
+        BenchMode::Inbox => {
+            let receipt = relay.post_inbox("oap-relay-bench", recipient).await?;
+            posted.push(receipt.message_id.clone());
+            let deadline = Instant::now() + poll_limit;
+            loop {
+                if relay.messages(Some(recipient)).await?.iter().any(|m| m.id == receipt.message_id) {
+                    break;
+                }
+                if Instant::now() >= deadline {
+                    bail!("Message {} never showed up", receipt.message_id);
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Runs `count` pings or inbox round-trips and reports latency percentiles and the error rate.
+pub async fn bench(url: String, mode: BenchMode, count: usize, interval_ms: u64, ctx: &Context) -> Result<()> {
+    if count == 0 {
+        return Err(output::error(ErrorKind::Usage, "--count must be at least 1"));
+    }
+    let cfg = crate::config::load(ctx.config.clone(), ctx.profile.clone()).await?;
+    let relay = RelayClient::new(&url, cfg.http_client()?);
+    let recipient = format!("did:example:bench-{}", uuid::Uuid::new_v4());
+    let poll_limit = Duration::from_secs(cfg.timeout_secs);
+
+    let mut samples = Vec::with_capacity(count);
+    let mut errors = 0;
+    let mut posted = Vec::new();
+    for i in 0..count {
+        if i > 0 && interval_ms > 0 {
+            tokio::time::sleep(Duration::from_millis(interval_ms)).await;
+        }
+        let start = Instant::now();
+        match bench_once(&relay, mode, &recipient, poll_limit, &mut posted).await {
+            Ok(()) => samples.push(start.elapsed().as_secs_f64() * 1000.0),
+            Err(e) => {
+                errors += 1;
+                if ctx.verbose {
+                    eprintln!("[{}] {:#}", i + 1, e);
+                }
             }
         }
     }
 
+    // Remove the bench messages so they don't pile up in the relay (fault injection may
+    // fail a delete, so those are retried once)
+    let mut left = 0;
+    for id in &posted {
+        if relay.delete_message(id).await.is_err() && relay.delete_message(id).await.is_err() {
+            left += 1;
+        }
+    }
+    if left > 0 {
+        eprintln!("{} {} bench messages could not be removed from the relay", "Warning:".yellow(), left);
+    }
+
+    samples.sort_by(f64::total_cmp);
+    let mean = (!samples.is_empty()).then(|| samples.iter().sum::<f64>() / samples.len() as f64);
+    let report = BenchReport {
+        url: relay.base().to_string(),
+        mode: format!("{:?}", mode).to_lowercase(),
+        requests: count,
+        ok: samples.len(),
+        errors,
+        error_rate: errors as f64 / count as f64,
+        min_ms: samples.first().copied(),
+        p50_ms: percentile(&samples, 50.0),
+        p95_ms: percentile(&samples, 95.0),
+        p99_ms: percentile(&samples, 99.0),
+        max_ms: samples.last().copied(),
+        mean_ms: mean,
+    };
+
+    if report.ok == 0 {
+        let err = output::error(ErrorKind::Network, format!("All {} requests to {} failed", count, report.url));
+        return Err(output::fail(ctx.json, &report, err));
+    }
+    if ctx.json {
+        return output::emit(&report);
+    }
+    let ms = |v: Option<f64>| v.map(|v| format!("{:.1} ms", v)).unwrap_or_else(|| "-".to_string());
+    println!("Relay: {} ({} × {})", report.url.cyan(), count, report.mode);
+    println!("min {}  p50 {}  p95 {}  p99 {}  max {}", ms(report.min_ms), ms(report.p50_ms), ms(report.p95_ms), ms(report.p99_ms), ms(report.max_ms));
+    let rate = format!("{:.1}%", report.error_rate * 100.0);
+    println!("Errors: {}/{} ({})", errors, count, if errors == 0 { rate.green() } else { rate.red() });
+    Ok(())
+}
+
+async fn admin_client(url: &str, ctx: &Context) -> Result<RelayClient> {
+    let cfg = crate::config::load(ctx.config.clone(), ctx.profile.clone()).await?;
+    Ok(RelayClient::new(url, cfg.http_client()?))
+}
+
+/// Lines of `next` not already printed: the relay keeps a rolling window, so skip the
+/// longest prefix of `next` that is a suffix of `prev`.
+fn new_log_lines<'a>(prev: &[String], next: &'a [String]) -> &'a [String] {
+    let overlap = (0..=prev.len().min(next.len()))
+        .rev()
+        .find(|&k| prev[prev.len() - k..] == next[..k])
+        .unwrap_or(0);
+    &next[overlap..]
+}
+
+fn print_log_line(line: &str, ctx: &Context) -> Result<()> {
+    if ctx.json {
+        output::emit_line(&json!({ "line": line }))?;
+    } else if line.contains("Chaos") {
+        println!("{}", line.yellow());
+    } else {
+        println!("{}", line);
+    }
     Ok(())
 }
+
+/// Prints the relay's recent log lines; with `follow`, keeps polling for new ones.
+pub async fn logs(url: String, follow: bool, interval_ms: u64, ctx: &Context) -> Result<()> {
+    let relay = admin_client(&url, ctx).await?;
+    let mut seen = relay.logs().await?;
+
+    if ctx.json && !follow {
+        return output::emit(&seen);
+    }
+    for line in &seen {
+        print_log_line(line, ctx)?;
+    }
+    if !follow {
+        return Ok(());
+    }
+
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => break,
+            _ = tokio::time::sleep(Duration::from_millis(interval_ms)) => {}
+        }
+        let next = relay.logs().await?;
+        for line in new_log_lines(&seen, &next) {
+            print_log_line(line, ctx)?;
+        }
+        seen = next;
+    }
+    Ok(())
+}
+
+/// Lists the messages stored on the relay, optionally for one recipient.
+pub async fn messages(url: String, recipient: Option<String>, ctx: &Context) -> Result<()> {
+    let relay = admin_client(&url, ctx).await?;
+    let mut messages = relay.messages(recipient.as_deref()).await?;
+    messages.sort_by(|a, b| a.received_at.unwrap_or_default().total_cmp(&b.received_at.unwrap_or_default()));
+
+    if ctx.json {
+        return output::emit(&messages);
+    }
+    if messages.is_empty() {
+        println!("No messages stored.");
+        return Ok(());
+    }
+    for m in &messages {
+        let received = m.received_at
+            .and_then(|t| chrono::DateTime::from_timestamp(t as i64, 0))
+            .map(|t| t.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+            .unwrap_or_else(|| "-".to_string());
+        println!("{}  {}  {}  {} bytes", m.id.dimmed(), received, m.recipient.cyan(), m.content.len());
+    }
+    println!("{} messages", messages.len());
+    Ok(())
+}
+
+/// Deletes every message stored on the relay.
+pub async fn clear(url: String, yes: bool, ctx: &Context) -> Result<()> {
+    let relay = admin_client(&url, ctx).await?;
+
+    if !yes {
+        if ctx.json {
+            return Err(output::error(ErrorKind::Usage, format!("Refusing to clear {} without --yes in JSON mode", relay.base())));
+        }
+        print!("Delete all messages stored on {}? [y/N]: ", relay.base());
+        std::io::stdout().flush()?;
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        if !matches!(answer.trim(), "y" | "Y" | "yes") {
+            println!("Aborted.");
+            return Ok(());
+        }
+    }
+
+    relay.clear_messages().await?;
+
+    if ctx.json {
+        output::emit(&json!({ "status": "cleared", "relay": relay.base() }))?;
+    } else {
+        println!("{} {}", "Cleared messages on".yellow(), relay.base().cyan());
+    }
+    Ok(())
+}
+
+/// The `relay chaos` flags that were given; `None` keeps the relay's current value.
+pub struct ChaosChanges {
+    pub failure_rate: Option<f64>,
+    pub latency_ms: Option<u64>,
+    pub corrupt: Option<bool>,
+    /// Start from all faults off instead of the current configuration
+    pub reset: bool,
+}
+
+impl ChaosChanges {
+    fn is_empty(&self) -> bool {
+        self.failure_rate.is_none() && self.latency_ms.is_none() && self.corrupt.is_none() && !self.reset
+    }
+
+    /// Whether the changes determine every fault without knowing the current ones.
+    fn is_complete(&self) -> bool {
+        self.reset || (self.failure_rate.is_some() && self.latency_ms.is_some() && self.corrupt.is_some())
+    }
+
+    fn apply(&self, current: ChaosConfig) -> ChaosConfig {
+        let base = if self.reset { ChaosConfig::default() } else { current };
+        ChaosConfig {
+            failure_rate: self.failure_rate.unwrap_or(base.failure_rate),
+            latency_ms: self.latency_ms.unwrap_or(base.latency_ms),
+            corrupt_bytes: self.corrupt.unwrap_or(base.corrupt_bytes),
+        }
+    }
+}
+
+pub async fn chaos(url: String, changes: ChaosChanges, ctx: &Context) -> Result<()> {
+    if changes.failure_rate.is_some_and(|rate| !(0.0..=1.0).contains(&rate)) {
+        return Err(output::error(ErrorKind::Usage, "--failure-rate must be between 0 and 1"));
+    }
+    let relay = admin_client(&url, ctx).await?;
+    let applied = match relay.chaos().await? {
+        Some(current) if changes.is_empty() => current,
+        Some(current) => relay.set_chaos(&changes.apply(current)).await?,
+        // Older relays can't report their faults, so only complete settings can be applied
+        None if changes.is_complete() => relay.set_chaos(&changes.apply(ChaosConfig::default())).await?,
+        None => return Err(output::error(
+            ErrorKind::Usage,
+            "The relay has no GET /chaos to read its current faults from; pass --reset, or --failure-rate, --latency-ms and --corrupt together",
+        )),
+    };
+
+    if ctx.json {
+        let status = if changes.is_empty() { "current" } else { "updated" };
+        output::emit(&json!({ "status": status, "relay": relay.base(), "config": applied }))?;
+    } else {
+        let off = applied.failure_rate == 0.0 && applied.latency_ms == 0 && !applied.corrupt_bytes;
+        println!("Chaos on {}: {}", relay.base().cyan(), if off { "off".green() } else { "on".yellow() });
+        println!("  failure_rate = {}", applied.failure_rate);
+        println!("  latency_ms = {}", applied.latency_ms);
+        println!("  corrupt_bytes = {}", applied.corrupt_bytes);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn changes(failure_rate: Option<f64>, latency_ms: Option<u64>, corrupt: Option<bool>, reset: bool) -> ChaosChanges {
+        ChaosChanges { failure_rate, latency_ms, corrupt, reset }
+    }
+
+    #[test]
+    fn percentiles_use_the_nearest_rank() {
+        let samples: Vec<f64> = (1..=20).map(f64::from).collect();
+        assert_eq!(percentile(&samples, 50.0), Some(10.0));
+        assert_eq!(percentile(&samples, 95.0), Some(19.0));
+        assert_eq!(percentile(&samples, 99.0), Some(20.0));
+        assert_eq!(percentile(&samples, 100.0), Some(20.0));
+        assert_eq!(percentile(&samples, 0.0), Some(1.0));
+
+        assert_eq!(percentile(&[4.2], 99.0), Some(4.2));
+        assert_eq!(percentile(&[], 50.0), None);
+    }
+
+    #[test]
+    fn chaos_changes_keep_omitted_faults() {
+        let current = ChaosConfig { failure_rate: 0.2, latency_ms: 150, corrupt_bytes: true };
+
+        let applied = changes(None, Some(0), None, false).apply(current.clone());
+        assert_eq!((applied.failure_rate, applied.latency_ms, applied.corrupt_bytes), (0.2, 0, true));
+
+        let applied = changes(None, None, Some(false), true).apply(current.clone());
+        assert_eq!((applied.failure_rate, applied.latency_ms, applied.corrupt_bytes), (0.0, 0, false));
+
+        assert!(changes(None, None, None, false).is_empty());
+        assert!(!changes(None, None, None, true).is_empty());
+
+        assert!(changes(Some(0.1), Some(5), Some(false), false).is_complete());
+        assert!(changes(None, None, None, true).is_complete());
+        assert!(!changes(Some(0.1), Some(5), None, false).is_complete());
+    }
+}
@@ -0,0 +1,392 @@
+use anyhow::{Context as _, Result};
+use colored::*;
+use oap::oaep::did::DidKey;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use crate::commands::{connect, listen, send, Context};
+use crate::config::Config;
+use crate::keystore;
+use crate::ledger_client::{LedgerClient, UNITS_PER_OAP};
+use crate::output::{self, ErrorKind};
+use crate::relay_client::RelayClient;
+use crate::scenario::{self, Action, CaseResult, Outcome, Scenario, Step, SuiteResult};
+use crate::schema::{self, Protocol};
+use crate::session::SessionStore;
+
+pub struct RunOptions {
+    /// Where to write the JUnit XML report (`-` for stdout)
+    pub junit: Option<PathBuf>,
+    pub relay: Option<String>,
+    pub ledger: Option<String>,
+}
+
+/// Runs each scenario file as a test suite. A failed step skips the rest of its scenario.
+pub async fn run(files: Vec<PathBuf>, opts: RunOptions, ctx: &Context) -> Result<()> {
+    let junit_stdout = opts.junit.as_deref() == Some(Path::new("-"));
+    if junit_stdout && ctx.json {
+        return Err(output::error(ErrorKind::Usage, "--junit - and --json both write to stdout"));
+    }
+    let cfg = crate::config::load(ctx.config.clone(), ctx.profile.clone()).await?;
+    let scenarios = files.iter()
+        .map(|path| scenario::load(path).map(|s| (path, s)))
+        .collect::<Result<Vec<_>>>()?;
+
+    // Progress moves to stderr when stdout carries the report
+    let progress = |line: String| {
+        if ctx.json {
+            return;
+        }
+        if junit_stdout {
+            eprintln!("{}", line);
+        } else {
+            println!("{}", line);
+        }
+    };
+
+    let mut suites = Vec::new();
+    for (path, scenario) in &scenarios {
+        let name = scenario.name.clone().unwrap_or_else(|| {
+            path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default()
+        });
+        progress(format!("{} {} ({})", "Scenario:".bold(), name, path.display()));
+        let mut runner = Runner::new(scenario, &cfg, &opts)?;
+        let suite = runner.run(name, path, ctx.verbose, &progress).await;
+        suites.push(suite);
+    }
+
+    let tests: usize = suites.iter().map(|s| s.cases.len()).sum();
+    let failures: usize = suites.iter().map(SuiteResult::failures).sum();
+    let skipped: usize = suites.iter().map(SuiteResult::skipped).sum();
+
+    match &opts.junit {
+        Some(_) if junit_stdout => print!("{}", scenario::junit(&suites)),
+        Some(path) => {
+            std::fs::write(path, scenario::junit(&suites))
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+        None => {}
+    }
+
+    let summary = format!("{} passed, {} failed, {} skipped", tests - failures - skipped, failures, skipped);
+    progress(if failures > 0 { summary.red().to_string() } else { summary.green().to_string() });
+
+    let data = json!({ "tests": tests, "failures": failures, "skipped": skipped, "suites": suites });
+    if failures > 0 {
+        let kind = suites.iter()
+            .flat_map(|s| &s.cases)
+            .find_map(|c| match &c.outcome {
+                Outcome::Failed { kind, .. } => Some(*kind),
+                _ => None,
+            })
+            .unwrap_or(ErrorKind::Error);
+        let err = output::error(kind, format!("{} of {} steps failed", failures, tests));
+        return Err(output::fail(ctx.json, &data, err));
+    }
+    if ctx.json {
+        output::emit(&data)?;
+    }
+    Ok(())
+}
+
+/// A scenario identity and the sessions it established.
+struct Actor {
+    key: DidKey,
+    alias: Option<String>,
+    store: SessionStore,
+}
+
+/// State carried between the steps of one scenario.
+struct Runner<'a> {
+    scenario: &'a Scenario,
+    cfg: &'a Config,
+    relay: RelayClient,
+    ledger: LedgerClient,
+    /// Holds one session store per identity for the duration of the run, so scenarios
+    /// never touch the user's sessions and two identities can talk to the same peer
+    sessions: tempfile::TempDir,
+    /// Scenario name -> identity
+    identities: BTreeMap<String, Actor>,
+    /// `{{name}}` substitutions: identity DIDs and `peer`
+    vars: BTreeMap<String, String>,
+    /// Relay message ids already looked at
+    seen: HashSet<String>,
+    /// Payload matched by the last `expect`
+    last: Option<Value>,
+}
+
+impl<'a> Runner<'a> {
+    fn new(scenario: &'a Scenario, cfg: &'a Config, opts: &RunOptions) -> Result<Self> {
+        let relay_url = opts.relay.as_deref().or(scenario.relay.as_deref()).unwrap_or(&cfg.default_relay);
+        let ledger_url = opts.ledger.as_deref().or(scenario.ledger.as_deref()).unwrap_or(&cfg.ledger_url);
+        let mut vars = BTreeMap::new();
+        if let Some(peer) = &scenario.peer {
+            vars.insert("peer".to_string(), peer.clone());
+        }
+        Ok(Self {
+            scenario,
+            cfg,
+            relay: RelayClient::new(relay_url, cfg.http_client()?),
+            ledger: LedgerClient::new(ledger_url, cfg.http_client()?),
+            sessions: tempfile::Builder::new().prefix("oap-run-").tempdir()
+                .context("Failed to create the scenario session store")?,
+            identities: BTreeMap::new(),
+            vars,
+            seen: HashSet::new(),
+            last: None,
+        })
+    }
+
+    async fn run(&mut self, name: String, path: &Path, verbose: bool, progress: &impl Fn(String)) -> SuiteResult {
+        let timestamp = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+        let started = Instant::now();
+        let mut cases = Vec::new();
+        let mut failed = false;
+
+        let scenario = self.scenario;
+        for step in &scenario.steps {
+            let label = step.label();
+            if failed {
+                progress(format!("  {} {} {}", "-".dimmed(), label, "(skipped)".dimmed()));
+                let outcome = Outcome::Skipped { message: "An earlier step failed".to_string() };
+                cases.push(CaseResult { name: label, outcome, time: Duration::ZERO, output: None });
+                continue;
+            }
+
+            let step_started = Instant::now();
+            let result = self.step(step).await;
+            let time = step_started.elapsed();
+            let (outcome, detail) = match result {
+                Ok(detail) => {
+                    progress(format!("  {} {} ({:.2}s)", "✓".green(), label, time.as_secs_f64()));
+                    (Outcome::Passed, detail)
+                }
+                Err(e) => {
+                    failed = true;
+                    let message = format!("{:#}", e);
+                    progress(format!("  {} {} ({:.2}s)", "✗".red(), label, time.as_secs_f64()));
+                    for line in message.lines() {
+                        progress(format!("      {}", line.red()));
+                    }
+                    (Outcome::Failed { kind: output::classify(&e), message }, None)
+                }
+            };
+            if verbose {
+                if let Some(detail) = &detail {
+                    progress(format!("      {}", detail.to_string().dimmed()));
+                }
+            }
+            cases.push(CaseResult { name: label, outcome, time, output: detail });
+        }
+
+        SuiteResult {
+            name,
+            file: path.display().to_string(),
+            timestamp,
+            time: started.elapsed(),
+            cases,
+        }
+    }
+
+    async fn step(&mut self, step: &Step) -> Result<Option<Value>> {
+        match step.action()? {
+            Action::Identity(name) => self.identity(name, step.keystore.as_deref()),
+            Action::Connect(target) => self.connect(target, step.as_identity.as_deref()).await,
+            Action::Send(payload) => self.send(payload, step.to.as_deref(), step.as_identity.as_deref()).await,
+            Action::Expect(pattern) => {
+                let timeout = step.timeout_secs.unwrap_or(self.scenario.timeout_secs);
+                self.expect(pattern, step.from.as_deref(), step.as_identity.as_deref(), timeout).await
+            }
+            Action::Validate(choice) => self.validate(choice.protocol()),
+            Action::Fund(who) => {
+                let did = self.did_of(who)?;
+                let receipt = self.ledger.faucet(&did).await?;
+                Ok(Some(json!({ "did": did, "balance": receipt.new_balance as f64 / UNITS_PER_OAP })))
+            }
+            Action::Balance(who) => self.balance(who, step).await,
+        }
+    }
+
+    fn identity(&mut self, name: &str, alias: Option<&str>) -> Result<Option<Value>> {
+        if name == "peer" || self.identities.contains_key(name) {
+            return Err(output::error(ErrorKind::Usage, format!("Identity name '{}' is already taken", name)));
+        }
+        let key = match alias {
+            Some(alias) => keystore::load_identity(alias)?,
+            None => DidKey::generate(),
+        };
+        let did = key.did().to_string();
+        let store = SessionStore::open(self.sessions.path().join(self.identities.len().to_string()));
+        self.vars.insert(name.to_string(), did.clone());
+        self.identities.insert(name.to_string(), Actor { key, alias: alias.map(str::to_string), store });
+        Ok(Some(json!({ "did": did })))
+    }
+
+    async fn connect(&mut self, target: &str, as_identity: Option<&str>) -> Result<Option<Value>> {
+        let peer = scenario::substitute(target, &self.vars)?;
+        let name = match as_identity {
+            Some(name) => name.to_string(),
+            None if self.identities.len() == 1 => self.identities.keys().next().cloned().unwrap_or_default(),
+            None => {
+                let message = "Set 'as' to the identity to connect with (or define exactly one identity)";
+                return Err(output::error(ErrorKind::Usage, message));
+            }
+        };
+        let actor = self.identities.get(&name)
+            .ok_or_else(|| output::error(ErrorKind::Usage, format!("Unknown identity '{}'", name)))?;
+
+        let (trace, session) = connect::establish(self.cfg, &actor.store, &actor.key, actor.alias.clone(), &peer, self.scenario.insecure).await
+            .map_err(|e| match output::classify(&e) {
+                ErrorKind::Error => output::error(ErrorKind::Handshake, format!("{:#}", e)),
+                _ => e,
+            })?;
+        let Some((session, _)) = session else {
+            let reason = if !trace.transcript_hash_matches { "transcript hash mismatch" } else { "proof signature invalid" };
+            return Err(output::error(ErrorKind::Handshake, format!("Handshake with {} failed: {}", peer, reason)));
+        };
+
+        // Anything already in the inbox predates this run
+        if let Ok(messages) = self.relay.messages(Some(&session.local_did)).await {
+            self.seen.extend(messages.into_iter().map(|m| m.id));
+        }
+        Ok(Some(json!({ "peer": peer, "local_did": session.local_did, "kid": session.kid })))
+    }
+
+    async fn send(&mut self, payload: &Value, to: Option<&str>, as_identity: Option<&str>) -> Result<Option<Value>> {
+        let recipient = self.peer(to, "to")?;
+        let body = match scenario::substitute_value(payload, &self.vars)? {
+            Value::String(s) => s.into_bytes(),
+            other => serde_json::to_vec(&other)?,
+        };
+        let store = Self::store_for(&self.identities, &recipient, as_identity)?;
+        let delivery = send::deliver(store, &self.relay, &recipient, &body).await?;
+        Ok(Some(json!({ "id": delivery.message_id, "kid": delivery.kid, "seq": delivery.seq })))
+    }
+
+    /// Waits for a message from the peer that matches `pattern`. Other messages from the
+    /// peer are listed in the failure.
+    async fn expect(&mut self, pattern: &Value, from: Option<&str>, as_identity: Option<&str>, timeout_secs: u64) -> Result<Option<Value>> {
+        let peer = self.peer(from, "from")?;
+        let pattern = scenario::substitute_value(pattern, &self.vars)?;
+        let store = Self::store_for(&self.identities, &peer, as_identity)?;
+        let local_did = store.load_active(&peer)?.local_did;
+        let deadline = Instant::now() + Duration::from_secs(timeout_secs);
+        let mut others = Vec::new();
+
+        loop {
+            for msg in self.relay.messages(Some(&local_did)).await? {
+                if !self.seen.insert(msg.id.clone()) {
+                    continue;
+                }
+                let incoming = listen::process(store, &local_did, msg);
+                if incoming.peer.as_deref() != Some(peer.as_str()) {
+                    continue;
+                }
+                let Some(payload) = incoming.payload else {
+                    others.push(format!("{}: {}", incoming.id, incoming.error.unwrap_or_default()));
+                    continue;
+                };
+                match scenario::mismatch(&pattern, &payload) {
+                    None => {
+                        self.last = Some(payload.clone());
+                        return Ok(Some(payload));
+                    }
+                    Some(diff) => others.push(format!("{}: {}", incoming.id, diff)),
+                }
+            }
+            if Instant::now() >= deadline {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(1000)).await;
+        }
+
+        if others.is_empty() {
+            return Err(output::error(ErrorKind::Timeout, format!("No message from {} within {}s", peer, timeout_secs)));
+        }
+        let message = format!(
+            "No message from {} matched within {}s; got {} other(s):\n{}",
+            peer, timeout_secs, others.len(), others.join("\n")
+        );
+        Err(output::error(ErrorKind::Validation, message))
+    }
+
+    fn validate(&self, protocol: Option<Protocol>) -> Result<Option<Value>> {
+        let payload = self.last.as_ref()
+            .ok_or_else(|| output::error(ErrorKind::Usage, "Nothing to validate: no message has been received yet"))?;
+        let protocol = match protocol {
+            Some(p) => p,
+            None => Protocol::detect(payload).map_err(|e| output::error(ErrorKind::Validation, e.to_string()))?,
+        };
+        let errors = schema::validate(protocol, payload)?;
+        if !errors.is_empty() {
+            let lines: Vec<String> = errors.iter()
+                .map(|e| format!("{}: {}", if e.path.is_empty() { "/" } else { e.path.as_str() }, e.message))
+                .collect();
+            let message = format!("Payload is not valid {}:\n{}", format!("{:?}", protocol).to_uppercase(), lines.join("\n"));
+            return Err(output::error(ErrorKind::Validation, message));
+        }
+        Ok(Some(json!({ "schema": protocol, "type": payload.get("type") })))
+    }
+
+    async fn balance(&self, who: &str, step: &Step) -> Result<Option<Value>> {
+        let did = self.did_of(who)?;
+        let balance = self.ledger.balance(&did).await?;
+        let units = |oap: f64| (oap * UNITS_PER_OAP).round() as u64;
+        let amount = balance.balance as f64 / UNITS_PER_OAP;
+
+        let mut failed = Vec::new();
+        if let Some(v) = step.equals.filter(|v| balance.balance != units(*v)) {
+            failed.push(format!("expected {}", v));
+        }
+        if let Some(v) = step.at_least.filter(|v| balance.balance < units(*v)) {
+            failed.push(format!("expected at least {}", v));
+        }
+        if let Some(v) = step.at_most.filter(|v| balance.balance > units(*v)) {
+            failed.push(format!("expected at most {}", v));
+        }
+        if !failed.is_empty() {
+            let message = format!("Balance of {} is {} OAP, {}", who, amount, failed.join(", "));
+            return Err(output::error(ErrorKind::Validation, message));
+        }
+        Ok(Some(json!({ "did": did, "balance": amount })))
+    }
+
+    /// Session store of the identity talking to `peer`: the one named by `as`, else the
+    /// only identity that connected to it.
+    fn store_for<'i>(identities: &'i BTreeMap<String, Actor>, peer: &str, as_identity: Option<&str>) -> Result<&'i SessionStore> {
+        if let Some(name) = as_identity {
+            return identities.get(name)
+                .map(|actor| &actor.store)
+                .ok_or_else(|| output::error(ErrorKind::Usage, format!("Unknown identity '{}'", name)));
+        }
+        let connected: Vec<&Actor> = identities.values()
+            .filter(|actor| actor.store.load(peer).is_ok())
+            .collect();
+        match connected.as_slice() {
+            [actor] => Ok(&actor.store),
+            [] => Err(output::error(ErrorKind::NotFound, format!("No identity has connected to {}; add a 'connect' step", peer))),
+            _ => Err(output::error(ErrorKind::Usage, format!("Several identities are connected to {}; set 'as'", peer))),
+        }
+    }
+
+    /// `to`/`from` of a step, or the scenario's `peer`.
+    fn peer(&self, explicit: Option<&str>, field: &str) -> Result<String> {
+        match explicit.or(self.scenario.peer.as_deref()) {
+            Some(peer) => scenario::substitute(peer, &self.vars),
+            None => Err(output::error(ErrorKind::Usage, format!("Set '{}' or the scenario's 'peer'", field))),
+        }
+    }
+
+    /// An identity name, a `{{...}}` template or a DID.
+    fn did_of(&self, who: &str) -> Result<String> {
+        if let Some(did) = self.vars.get(who) {
+            return Ok(did.clone());
+        }
+        let did = scenario::substitute(who, &self.vars)?;
+        if !did.starts_with("did:") {
+            return Err(output::error(ErrorKind::Usage, format!("'{}' is neither an identity nor a DID", who)));
+        }
+        Ok(did)
+    }
+}
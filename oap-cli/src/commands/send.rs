@@ -1,31 +1,67 @@
 use anyhow::Result;
 use colored::*;
-use crate::commands::Context;
-use oap::Agent;
+use serde_json::json;
+use std::path::PathBuf;
+use crate::commands::{read_input, Context};
+use crate::output;
+use crate::relay_client::RelayClient;
+use crate::session::SessionStore;
 
-pub async fn run(message: String, recipient: String, ctx: &Context) -> Result<()> {
+pub async fn run(
+    message: Option<String>,
+    file: Option<PathBuf>,
+    recipient: String,
+    relay: Option<String>,
+    ctx: &Context,
+) -> Result<()> {
     if ctx.verbose {
         println!("Sending message to {}...", recipient.cyan());
     }
 
     // Load config
-    let cfg = crate::config::load(ctx.config.clone()).await?;
+    let cfg = crate::config::load(ctx.config.clone(), ctx.profile.clone()).await?;
+    let relay_url = relay.unwrap_or_else(|| cfg.default_relay.clone());
 
-    // Initialize Agent
-    let agent = Agent::new(&cfg.default_relay)?;
+    let body = read_input(message, file)?;
 
-    // Send logic
-    // agent.send(recipient, message).await?
-    
-    // Mock output for workflow example
-    let relay_url = "wss://relay.local";
-    let msg_id = "uuid-123";
+    let store = SessionStore::open_default()?;
+    let relay = RelayClient::new(&relay_url, cfg.http_client()?);
+    let delivery = deliver(&store, &relay, &recipient, &body).await?;
+
+    if ctx.verbose {
+        println!("Encrypted {} bytes (kid: {}, seq: {})", body.len(), delivery.kid.green(), delivery.seq);
+    }
 
     if ctx.json {
-        println!("{{ \"status\": \"sent\", \"id\": \"{}\", \"relay\": \"{}\" }}", msg_id, relay_url);
+        output::emit(&json!({
+            "status": "sent",
+            "id": delivery.message_id,
+            "relay": relay.base(),
+            "recipient": recipient,
+            "kid": delivery.kid,
+            "seq": delivery.seq,
+        }))?;
     } else {
-        println!("Sent via Relay [{}]. ID: {}", relay_url.blue(), msg_id.green());
+        println!("Sent via Relay [{}]. ID: {}", relay.base().blue(), delivery.message_id.green());
     }
 
     Ok(())
 }
+
+pub struct Delivery {
+    pub message_id: String,
+    pub kid: String,
+    pub seq: u64,
+}
+
+/// Encrypts `body` with the active session for `recipient` and posts it to the relay inbox.
+pub async fn deliver(store: &SessionStore, relay: &RelayClient, recipient: &str, body: &[u8]) -> Result<Delivery> {
+    let (container, kid, seq) = store.update(recipient, |session| {
+        session.check_active()?;
+        let (container, seq) = session.seal(body)?;
+        Ok((container, session.kid.clone(), seq))
+    })?;
+
+    let receipt = relay.post_inbox(&serde_json::to_string(&container)?, recipient).await?;
+    Ok(Delivery { message_id: receipt.message_id, kid, seq })
+}
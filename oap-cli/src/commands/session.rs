@@ -0,0 +1,207 @@
+use anyhow::{bail, Context as _, Result};
+use colored::*;
+use oap::oaep::keys::X25519Secret;
+use oap::oaep::messages::{ConnectionRequest, ConnectionResponse};
+use serde_json::json;
+use std::path::PathBuf;
+use crate::commands::{read_json, Context};
+use crate::handshake;
+use crate::keystore;
+use crate::output;
+use crate::session::{now_secs, Role, SessionStore, StoredSession};
+
+fn session_json(session: &StoredSession, reveal: bool) -> serde_json::Value {
+    let mut value = json!({
+        "peer_did": session.peer_did,
+        "local_did": session.local_did,
+        "identity": session.identity,
+        "role": session.role,
+        "kid": session.kid,
+        "transcript_hash": session.transcript_hash,
+        "cipher_suite": session.cipher_suite,
+        "tx_seq": session.tx_seq,
+        "rx_seq": session.rx_seq,
+        "created_at": session.created_at,
+        "expires_at": session.expires_at,
+        "expired": session.is_expired(),
+    });
+    if reveal {
+        value["tx_key"] = json!(session.tx_key);
+        value["rx_key"] = json!(session.rx_key);
+    }
+    value
+}
+
+pub async fn list(ctx: &Context) -> Result<()> {
+    let sessions = SessionStore::open_default()?.list()?;
+
+    if ctx.json {
+        let items: Vec<_> = sessions.iter().map(|s| session_json(s, false)).collect();
+        output::emit(&items)?;
+    } else if sessions.is_empty() {
+        println!("{}", "No sessions. Establish one with `oap connect <did>`.".dimmed());
+    } else {
+        for s in sessions {
+            let state = if s.is_expired() {
+                "expired".red()
+            } else {
+                format!("{}m left", s.expires_at.saturating_sub(now_secs()) / 60).green()
+            };
+            println!("{:<50} kid={} tx={} rx={} {}",
+                s.peer_did.cyan(),
+                s.kid,
+                s.tx_seq,
+                s.rx_seq.map(|v| v.to_string()).unwrap_or_else(|| "-".to_string()),
+                state
+            );
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn show(peer: String, reveal: bool, ctx: &Context) -> Result<()> {
+    let session = SessionStore::open_default()?.load(&peer)?;
+
+    if ctx.json {
+        output::emit(&session_json(&session, reveal))?;
+    } else {
+        println!("Peer: {}", session.peer_did.cyan());
+        println!("Local DID: {}", session.local_did);
+        if let Some(alias) = &session.identity {
+            println!("Identity: {}", alias.blue());
+        }
+        println!("Role: {:?}", session.role);
+        println!("Key ID (kid): {}", session.kid.green());
+        println!("Transcript Hash: {}", session.transcript_hash);
+        println!("Cipher Suite: {}", session.cipher_suite.yellow());
+        println!("Next TX Seq: {}", session.tx_seq);
+        println!("Last RX Seq: {}", session.rx_seq.map(|v| v.to_string()).unwrap_or_else(|| "-".to_string()));
+        let expiry = if session.is_expired() { "expired".red() } else { "active".green() };
+        println!("Expires At: {} ({})", session.expires_at, expiry);
+        if reveal {
+            println!("TX Key: {}", session.tx_key.red());
+            println!("RX Key: {}", session.rx_key.red());
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn drop(peer: Option<String>, all: bool, ctx: &Context) -> Result<()> {
+    let store = SessionStore::open_default()?;
+
+    let peers: Vec<String> = match (peer, all) {
+        (Some(p), false) => vec![p],
+        (None, true) => store.list()?.into_iter().map(|s| s.peer_did).collect(),
+        _ => bail!("Specify a peer DID or --all"),
+    };
+
+    for p in &peers {
+        store.drop_session(p)?;
+    }
+
+    if ctx.json {
+        output::emit(&json!({ "status": "dropped", "peers": peers }))?;
+    } else {
+        for p in &peers {
+            println!("Dropped session with {}", p.cyan());
+        }
+    }
+
+    Ok(())
+}
+
+/// Overrides the stored sequence counters, e.g. to reproduce replay or ordering bugs.
+pub async fn set_seq(peer: String, tx: Option<u64>, rx: Option<u64>, ctx: &Context) -> Result<()> {
+    let store = SessionStore::open_default()?;
+    let session = store.update(&peer, |session| {
+        if let Some(tx) = tx {
+            session.tx_seq = tx;
+        }
+        if let Some(rx) = rx {
+            session.rx_seq = Some(rx);
+        }
+        Ok(session.clone())
+    })?;
+
+    if ctx.json {
+        output::emit(&json!({ "peer_did": peer, "tx_seq": session.tx_seq, "rx_seq": session.rx_seq }))?;
+    } else {
+        println!("Session with {}: next tx={}, last rx={}",
+            peer.cyan(),
+            session.tx_seq,
+            session.rx_seq.map(|v| v.to_string()).unwrap_or_else(|| "-".to_string())
+        );
+    }
+
+    Ok(())
+}
+
+/// Re-derives the session keys of a captured handshake, as a reference for other implementations.
+pub async fn derive(
+    request_path: PathBuf,
+    response_path: PathBuf,
+    secret: String,
+    role: Option<Role>,
+    ctx: &Context,
+) -> Result<()> {
+    let request: ConnectionRequest = serde_json::from_value(read_json(request_path)?)
+        .context("--request is not a ConnectionRequest")?;
+    let response: ConnectionResponse = serde_json::from_value(read_json(response_path)?)
+        .context("--response is not a ConnectionResponse")?;
+    let secret = X25519Secret::from_bytes(keystore::parse_secret(&secret)?);
+
+    // The secret belongs to whichever side's ephemeral public key it reproduces
+    let public = secret.public_key().to_multibase();
+    let initiator_key = &request.body.key_exchange.public_key;
+    let responder_key = &response.body.key_exchange.public_key;
+    let role = match role {
+        Some(role) => role,
+        None if &public == initiator_key => Role::Initiator,
+        None if &public == responder_key => Role::Responder,
+        None => bail!("Secret matches neither ephemeral key in the handshake (pass --role to force)"),
+    };
+    let peer_ephemeral = match role {
+        Role::Initiator => responder_key,
+        Role::Responder => initiator_key,
+    };
+
+    // Keys and kid come from the hash the responder signed, as in the inspector; the
+    // recomputed transcript hash is only reported next to it
+    let hash: [u8; 32] = hex::decode(&response.proof.transcript_hash)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .context("proof.transcript_hash is not a 32-byte hex string")?;
+    let recomputed = handshake::transcript_hash(&request, &response);
+    let (i2r, r2i) = handshake::derive_session_keys(&secret, peer_ephemeral, &hash)?;
+    let kid = handshake::kid(&hash);
+
+    if ctx.json {
+        output::emit(&json!({
+            "role": role,
+            "cipher_suite": response.body.key_exchange.negotiated_suite.to_string(),
+            "transcript_hash": hex::encode(hash),
+            "recomputed_transcript_hash": hex::encode(recomputed),
+            "transcript_hash_matches": recomputed == hash,
+            "kid": kid,
+            "info": String::from_utf8_lossy(handshake::SESSION_INFO),
+            "initiator_to_responder": hex::encode(i2r.as_bytes()),
+            "responder_to_initiator": hex::encode(r2i.as_bytes()),
+        }))?;
+    } else {
+        println!("Role: {:?}", role);
+        println!("Cipher Suite: {}", response.body.key_exchange.negotiated_suite.to_string().yellow());
+        println!("Transcript Hash (proof): {}", hex::encode(hash));
+        if recomputed == hash {
+            println!("Recomputed Hash: {}", "matches proof".green());
+        } else {
+            println!("Recomputed Hash: {} ({})", hex::encode(recomputed), "MISMATCH".red());
+        }
+        println!("Key ID (kid): {}", kid.green());
+        println!("Initiator -> Responder: {}", hex::encode(i2r.as_bytes()));
+        println!("Responder -> Initiator: {}", hex::encode(r2i.as_bytes()));
+    }
+
+    Ok(())
+}
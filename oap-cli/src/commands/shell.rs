@@ -0,0 +1,549 @@
+use anyhow::{anyhow, Result};
+use clap::{CommandFactory, FromArgMatches};
+use colored::*;
+use oap::oaep::did::DidKey;
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{CompletionType, Editor, ExternalPrinter, Helper};
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use crate::commands::{connect, listen, send, Context};
+use crate::config::Config;
+use crate::keystore::{self, Keystore};
+use crate::output::{self, ErrorKind};
+use crate::relay_client::RelayClient;
+use crate::session::SessionStore;
+
+/// Commands that work on the shell's in-memory state. Any other line is parsed as a
+/// regular `oap` command line.
+const BUILTINS: &[(&str, &str)] = &[
+    ("help", "Show this help"),
+    ("whoami", "Show the active identity, peer and relay"),
+    ("use <alias>", "Switch to a keystore identity (asks for the passphrase once)"),
+    ("connect [did] [--insecure]", "Handshake as the active identity; the DID becomes the peer"),
+    ("peer [did]", "Show or set the peer used by send"),
+    ("send [--to <did>] <message>", "Encrypt and deliver the rest of the line (text or JSON)"),
+    ("inbox [clear]", "Poll now and list the messages received in this shell"),
+    ("decode <jwe>", "Inspect a JWE (msg decode)"),
+    ("exit", "Leave the shell (or Ctrl-D)"),
+];
+
+/// Messages received since the shell started.
+#[derive(Default)]
+struct Inbox {
+    seen: HashSet<String>,
+    received: Vec<listen::Incoming>,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn usage(message: &str) -> anyhow::Error {
+    output::error(ErrorKind::Usage, message)
+}
+
+/// `("send", "--to did hi")` from `"send --to did hi"`.
+fn split_first(line: &str) -> (&str, &str) {
+    match line.split_once(char::is_whitespace) {
+        Some((first, rest)) => (first, rest.trim_start()),
+        None => (line, ""),
+    }
+}
+
+/// Byte offset of the word being typed at the end of `line`.
+fn word_start(line: &str) -> usize {
+    line.char_indices().rev()
+        .find(|(_, c)| c.is_whitespace())
+        .map(|(i, c)| i + c.len_utf8())
+        .unwrap_or(0)
+}
+
+/// Completes command names, subcommands of `oap` commands, and DIDs and aliases
+/// (keystore, stored sessions and peers seen in this shell) for arguments.
+struct ShellHelper {
+    commands: BTreeMap<String, Vec<String>>,
+    known: Arc<Mutex<BTreeSet<String>>>,
+}
+
+impl ShellHelper {
+    fn new(known: Arc<Mutex<BTreeSet<String>>>) -> Self {
+        let mut commands = BTreeMap::new();
+        for (usage, _) in BUILTINS {
+            commands.insert(split_first(usage).0.to_string(), Vec::new());
+        }
+        for sub in crate::Cli::command().get_subcommands() {
+            let names = sub.get_subcommands().map(|s| s.get_name().to_string()).collect();
+            commands.entry(sub.get_name().to_string()).or_insert(names);
+        }
+        Self { commands, known }
+    }
+
+    fn values(&self) -> BTreeSet<String> {
+        let mut values = lock(&self.known).clone();
+        if let Ok(entries) = Keystore::open_default().and_then(|k| k.list()) {
+            for entry in entries {
+                values.insert(entry.alias);
+                values.insert(entry.did);
+            }
+        }
+        if let Ok(sessions) = SessionStore::open_default().and_then(|s| s.list()) {
+            values.extend(sessions.into_iter().map(|s| s.peer_did));
+        }
+        values
+    }
+}
+
+impl Completer for ShellHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _: &rustyline::Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let line = &line[..pos];
+        let start = word_start(line);
+        let word = &line[start..];
+        let mut before: Vec<&str> = line[..start].split_whitespace().collect();
+        if before.first() == Some(&"oap") {
+            before.remove(0);
+        }
+
+        let candidates: Vec<String> = match before.as_slice() {
+            [] => self.commands.keys().cloned().collect(),
+            [command] if self.commands.get(*command).is_some_and(|subs| !subs.is_empty()) => {
+                self.commands[*command].clone()
+            }
+            _ => self.values().into_iter().collect(),
+        };
+        let pairs = candidates.into_iter()
+            .filter(|c| c.starts_with(word))
+            .map(|c| Pair { display: c.clone(), replacement: c })
+            .collect();
+        Ok((start, pairs))
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
+
+/// Prints incoming messages when stdin/stdout is not a terminal.
+struct StdoutPrinter;
+
+impl ExternalPrinter for StdoutPrinter {
+    fn print(&mut self, msg: String) -> rustyline::Result<()> {
+        println!("{}", msg);
+        Ok(())
+    }
+}
+
+type SharedPrinter = Arc<Mutex<Box<dyn ExternalPrinter + Send>>>;
+
+/// What the shell keeps between commands. It takes the place of an `oap::Agent`: the
+/// identity, relay client and session store are built once and reused by every command,
+/// through the same handshake and session code as `oap connect`/`send`.
+struct Shell {
+    cfg: Config,
+    json: bool,
+    verbose: bool,
+    config: Option<PathBuf>,
+    profile: Option<String>,
+    interval: Duration,
+    relay: RelayClient,
+    store: SessionStore,
+    identity: DidKey,
+    alias: Option<String>,
+    /// Default recipient of `send`
+    peer: Option<String>,
+    /// DIDs seen in this shell, for completion
+    known: Arc<Mutex<BTreeSet<String>>>,
+    inbox: Arc<Mutex<Inbox>>,
+    printer: SharedPrinter,
+    watcher: Option<JoinHandle<()>>,
+}
+
+/// Runs the interactive shell until `exit` or Ctrl-D.
+pub async fn run(identity: Option<String>, interval_ms: u64, cfg: Config, ctx: &Context) -> Result<()> {
+    let editor_config = rustyline::Config::builder()
+        .auto_add_history(true)
+        .completion_type(CompletionType::List)
+        .build();
+    let mut rl: Editor<ShellHelper, DefaultHistory> = Editor::with_config(editor_config)?;
+    let known = Arc::new(Mutex::new(BTreeSet::new()));
+    rl.set_helper(Some(ShellHelper::new(known.clone())));
+
+    let history = history_path()?;
+    let _ = rl.load_history(&history);
+
+    // Incoming messages are printed above the prompt without breaking the line being edited
+    let printer: Box<dyn ExternalPrinter + Send> = match rl.create_external_printer() {
+        Ok(printer) => Box::new(printer),
+        Err(_) => Box::new(StdoutPrinter),
+    };
+
+    let alias = identity.or(cfg.default_identity.clone());
+    let identity = match &alias {
+        Some(alias) => keystore::load_identity(alias)?,
+        None => DidKey::generate(),
+    };
+
+    let mut shell = Shell {
+        relay: RelayClient::new(&cfg.default_relay, cfg.http_client()?),
+        store: SessionStore::open_default()?,
+        json: ctx.json,
+        verbose: ctx.verbose,
+        config: ctx.config.clone(),
+        profile: ctx.profile.clone(),
+        interval: Duration::from_millis(interval_ms),
+        identity,
+        alias,
+        peer: None,
+        known,
+        inbox: Arc::new(Mutex::new(Inbox::default())),
+        printer: Arc::new(Mutex::new(printer)),
+        watcher: None,
+        cfg,
+    };
+    shell.watch().await;
+    shell.whoami()?;
+    if !shell.json {
+        println!("Type {} for commands, Ctrl-D to exit.", "help".bold());
+    }
+
+    loop {
+        let prompt = shell.prompt();
+        // Blocks this worker only; the inbox watcher keeps running
+        let line = match tokio::task::block_in_place(|| rl.readline(&prompt)) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if matches!(line, "exit" | "quit") {
+            break;
+        }
+
+        // Ctrl-C cancels the running command instead of leaving the shell
+        let result = tokio::select! {
+            result = shell.execute(line) => result,
+            _ = tokio::signal::ctrl_c() => Err(anyhow!("Interrupted")),
+        };
+        if let Err(e) = result {
+            output::report(shell.json, &e);
+        }
+    }
+
+    if let Some(watcher) = shell.watcher.take() {
+        watcher.abort();
+    }
+    let _ = rl.save_history(&history);
+    Ok(())
+}
+
+fn history_path() -> Result<PathBuf> {
+    let home = dirs::home_dir().ok_or_else(|| anyhow!("Could not determine home directory"))?;
+    let dir = home.join(".oap");
+    keystore::create_private_dir(&dir)?;
+    Ok(dir.join("shell_history"))
+}
+
+/// Fetches the relay inbox of `did`, decrypts messages not seen before and returns them
+/// formatted for printing.
+async fn poll(
+    relay: &RelayClient,
+    store: &SessionStore,
+    did: &str,
+    inbox: &Mutex<Inbox>,
+    known: &Mutex<BTreeSet<String>>,
+) -> Result<Vec<String>> {
+    let mut lines = Vec::new();
+    for msg in relay.messages(Some(did)).await? {
+        if !lock(inbox).seen.insert(msg.id.clone()) {
+            continue;
+        }
+        let incoming = listen::process(store, did, msg);
+        if let Some(peer) = &incoming.peer {
+            lock(known).insert(peer.clone());
+        }
+        lines.push(listen::format_incoming(&incoming));
+        lock(inbox).received.push(incoming);
+    }
+    Ok(lines)
+}
+
+impl Shell {
+    fn did(&self) -> String {
+        self.identity.did().to_string()
+    }
+
+    fn prompt(&self) -> String {
+        match &self.alias {
+            Some(alias) => format!("oap ({})> ", alias),
+            None => "oap> ".to_string(),
+        }
+    }
+
+    /// (Re)starts the background poll of the active identity's inbox. Messages already
+    /// waiting at the relay are skipped; `inbox` lists what arrived since.
+    async fn watch(&mut self) {
+        if let Some(watcher) = self.watcher.take() {
+            watcher.abort();
+        }
+        let did = self.did();
+        if let Ok(messages) = self.relay.messages(Some(&did)).await {
+            lock(&self.inbox).seen.extend(messages.into_iter().map(|m| m.id));
+        }
+
+        let relay = self.relay.clone();
+        let inbox = self.inbox.clone();
+        let known = self.known.clone();
+        let printer = self.printer.clone();
+        let interval = self.interval;
+        self.watcher = Some(tokio::spawn(async move {
+            let Ok(store) = SessionStore::open_default() else { return };
+            loop {
+                if let Ok(lines) = poll(&relay, &store, &did, &inbox, &known).await {
+                    for line in lines {
+                        let _ = lock(&printer).print(line);
+                    }
+                }
+                tokio::time::sleep(interval).await;
+            }
+        }));
+    }
+
+    async fn execute(&mut self, line: &str) -> Result<()> {
+        // `oap ...` always runs the CLI command, e.g. `oap connect` instead of the built-in
+        if let Some(rest) = line.strip_prefix("oap ") {
+            return self.delegate(rest).await;
+        }
+        let (command, args) = split_first(line);
+        if BUILTINS.iter().any(|(usage, _)| split_first(usage).0 == command) {
+            output::set_command(command.to_string());
+        }
+        match command {
+            "help" => self.help(),
+            "whoami" => self.whoami(),
+            "use" => self.use_identity(args).await,
+            "connect" => self.connect(args).await,
+            "peer" => self.set_peer(args),
+            "send" => self.send(args).await,
+            "inbox" => self.inbox(args).await,
+            "decode" => self.delegate(&format!("msg decode {}", args)).await,
+            _ => self.delegate(line).await,
+        }
+    }
+
+    /// Runs a regular `oap` command line with the shell's config and flags.
+    async fn delegate(&self, line: &str) -> Result<()> {
+        let words = shell_words::split(line).map_err(|e| usage(&e.to_string()))?;
+        let args = std::iter::once("oap".to_string()).chain(words);
+        let matches = match crate::Cli::command().try_get_matches_from(args) {
+            Ok(matches) => matches,
+            Err(e) => {
+                // Help, version and usage errors, formatted by clap
+                let _ = e.print();
+                return Ok(());
+            }
+        };
+        output::set_command(crate::command_path(&matches));
+        let cli = crate::Cli::from_arg_matches(&matches)?;
+        if matches!(cli.command, crate::Commands::Shell { .. }) {
+            return Err(usage("Already in the shell"));
+        }
+
+        let ctx = Context {
+            json: cli.json || self.json,
+            verbose: cli.verbose || self.verbose,
+            config: cli.config.or_else(|| self.config.clone()),
+            profile: cli.profile.or_else(|| self.profile.clone()),
+        };
+        let cfg = if matches.get_one::<PathBuf>("config").is_some() || matches.get_one::<String>("profile").is_some() {
+            crate::config::load(ctx.config.clone(), ctx.profile.clone()).await?
+        } else {
+            self.cfg.clone()
+        };
+        // Reported here so the line's own --json applies
+        let json = ctx.json;
+        if let Err(e) = Box::pin(crate::run(cli.command, cfg, ctx)).await {
+            output::report(json, &e);
+        }
+        Ok(())
+    }
+
+    fn help(&self) -> Result<()> {
+        for (usage, about) in BUILTINS {
+            println!("  {:<30} {}", usage.bold(), about);
+        }
+        println!();
+        println!("Any other line runs as an oap command, e.g. {} or {}.", "did resolve <did>".bold(), "relay ping".bold());
+        println!("Prefix a line with {} to run the CLI's own connect/send.", "oap".bold());
+        println!("Incoming messages for the active identity are printed as they arrive.");
+        Ok(())
+    }
+
+    fn whoami(&self) -> Result<()> {
+        let did = self.did();
+        if self.json {
+            return output::emit(&json!({ "alias": self.alias, "did": did, "peer": self.peer, "relay": self.relay.base() }));
+        }
+        let label = match &self.alias {
+            Some(alias) => alias.clone(),
+            None => "ephemeral".to_string(),
+        };
+        println!("Identity: {} ({})", did.cyan(), label.dimmed());
+        if let Some(peer) = &self.peer {
+            println!("Peer: {}", peer.cyan());
+        }
+        println!("Relay: {}", self.relay.base().dimmed());
+        Ok(())
+    }
+
+    async fn use_identity(&mut self, alias: &str) -> Result<()> {
+        if alias.is_empty() {
+            return Err(usage("Usage: use <alias>"));
+        }
+        self.identity = keystore::load_identity(alias)?;
+        self.alias = Some(alias.to_string());
+        // Sessions belong to the previous identity
+        self.peer = None;
+        self.watch().await;
+        self.whoami()
+    }
+
+    async fn connect(&mut self, args: &str) -> Result<()> {
+        let words = shell_words::split(args).map_err(|e| usage(&e.to_string()))?;
+        let insecure = words.iter().any(|w| w == "--insecure");
+        let did = words.into_iter()
+            .find(|w| !w.starts_with("--"))
+            .or_else(|| self.peer.clone())
+            .ok_or_else(|| usage("Usage: connect <did> [--insecure]"))?;
+
+        let (trace, session) = connect::establish(&self.cfg, &self.store, &self.identity, self.alias.clone(), &did, insecure).await
+            .map_err(|e| match output::classify(&e) {
+                ErrorKind::Error => output::error(ErrorKind::Handshake, format!("Handshake with {} failed: {:#}", did, e)),
+                _ => e,
+            })?;
+        let Some((session, _)) = session else {
+            let reason = if !trace.transcript_hash_matches { "transcript hash mismatch" } else { "proof signature invalid" };
+            return Err(output::error(ErrorKind::Handshake, format!("Handshake with {} failed: {}", did, reason)));
+        };
+
+        lock(&self.known).insert(did.clone());
+        self.peer = Some(did.clone());
+        if self.json {
+            output::emit(&json!({ "status": "connected", "peer": did, "local_did": session.local_did, "kid": session.kid }))
+        } else {
+            println!("{} {} (kid {})", "Connected to".green(), did.cyan(), session.kid.dimmed());
+            Ok(())
+        }
+    }
+
+    fn set_peer(&mut self, did: &str) -> Result<()> {
+        if !did.is_empty() {
+            self.peer = Some(did.to_string());
+        }
+        match &self.peer {
+            Some(peer) => println!("Peer: {}", peer.cyan()),
+            None => println!("No peer set (connect to one, or: peer <did>)"),
+        }
+        Ok(())
+    }
+
+    async fn send(&mut self, args: &str) -> Result<()> {
+        let (to, body) = match args.strip_prefix("--to ") {
+            Some(rest) => {
+                let (did, body) = split_first(rest.trim_start());
+                (Some(did.to_string()), body)
+            }
+            None => (None, args),
+        };
+        let recipient = to.or_else(|| self.peer.clone())
+            .ok_or_else(|| usage("No peer: connect first or pass --to <did>"))?;
+        if body.is_empty() {
+            return Err(usage("Usage: send [--to <did>] <message>"));
+        }
+
+        self.check_session(&recipient)?;
+        let delivery = send::deliver(&self.store, &self.relay, &recipient, body.as_bytes()).await?;
+        if self.json {
+            output::emit(&json!({ "status": "sent", "id": delivery.message_id, "recipient": recipient, "kid": delivery.kid, "seq": delivery.seq }))
+        } else {
+            println!("Sent {} to {} (seq {})", delivery.message_id.green(), recipient.cyan(), delivery.seq);
+            Ok(())
+        }
+    }
+
+    /// Sessions are stored per peer; refuse one set up by another identity (e.g. before `use`).
+    fn check_session(&self, peer: &str) -> Result<()> {
+        let session = self.store.load_active(peer)?;
+        if session.local_did != self.did() {
+            let message = format!(
+                "The session with {} belongs to {}, not the active identity; connect again",
+                peer, session.local_did
+            );
+            return Err(usage(&message));
+        }
+        Ok(())
+    }
+
+    async fn inbox(&mut self, args: &str) -> Result<()> {
+        if args == "clear" {
+            lock(&self.inbox).received.clear();
+            return Ok(());
+        }
+        poll(&self.relay, &self.store, &self.did(), &self.inbox, &self.known).await?;
+
+        let inbox = lock(&self.inbox);
+        if self.json {
+            for incoming in &inbox.received {
+                output::emit_line(incoming)?;
+            }
+        } else if inbox.received.is_empty() {
+            println!("No messages received yet.");
+        } else {
+            for incoming in &inbox.received {
+                println!("{}", listen::format_incoming(incoming));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completion_starts_after_the_last_whitespace() {
+        assert_eq!(word_start(""), 0);
+        assert_eq!(word_start("con"), 0);
+        assert_eq!(word_start("send did:"), 5);
+        assert_eq!(word_start("send héllo wö"), "send héllo ".len());
+        // Multi-byte whitespace must not split a character
+        let line = "send\u{3000}did";
+        assert_eq!(&line[word_start(line)..], "did");
+    }
+
+    #[test]
+    fn split_first_separates_the_command() {
+        assert_eq!(split_first("send --to did hi"), ("send", "--to did hi"));
+        assert_eq!(split_first("whoami"), ("whoami", ""));
+    }
+}
@@ -0,0 +1,139 @@
+use anyhow::{Context as _, Result};
+use colored::*;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use crate::commands::{read_input, Context};
+use crate::output::{self, ErrorKind};
+use crate::schema::{self, Protocol, SchemaError};
+
+#[derive(Debug, Serialize)]
+struct Report {
+    index: usize,
+    id: Option<String>,
+    schema: Option<Protocol>,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    valid: bool,
+    errors: Vec<SchemaError>,
+}
+
+/// Unwraps `msg decrypt` output (`{decrypted_with, payload}`, or `--input` report lines)
+/// so decrypted payloads can be piped straight in. Returns `(id, payload or error)`.
+fn unwrap_payload(value: Value) -> (Option<String>, Result<Value, String>) {
+    let value = output::unwrap_envelope(value);
+    let id = value.get("id").and_then(|v| v.as_str()).map(str::to_string);
+    if value.get("decrypted_with").is_none() {
+        return (id, Ok(value));
+    }
+    match value.get("payload") {
+        Some(payload) if !payload.is_null() => (id, Ok(payload.clone())),
+        _ => {
+            let error = value.get("error").and_then(|v| v.as_str()).unwrap_or("no payload");
+            (id, Err(format!("Message was not decrypted: {}", error)))
+        }
+    }
+}
+
+/// A single JSON document, or one JSON value per line.
+fn parse_documents(input: &str) -> Result<Vec<Value>> {
+    if let Ok(value) = serde_json::from_str::<Value>(input) {
+        return Ok(vec![value]);
+    }
+    input.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .enumerate()
+        .map(|(i, line)| serde_json::from_str(line).with_context(|| format!("Line {} is not JSON", i + 1)))
+        .collect()
+}
+
+pub async fn run(file: PathBuf, protocol: Option<Protocol>, ctx: &Context) -> Result<()> {
+    let raw = String::from_utf8(read_input(None, Some(file))?).context("Input is not UTF-8")?;
+    let documents = parse_documents(&raw)?;
+
+    let mut reports = Vec::new();
+    for (i, document) in documents.into_iter().enumerate() {
+        let (id, payload) = unwrap_payload(document);
+        let mut report = Report { index: i + 1, id, schema: protocol, kind: None, valid: false, errors: Vec::new() };
+
+        let payload = match payload {
+            Ok(payload) => payload,
+            Err(e) => {
+                report.errors.push(SchemaError { path: String::new(), schema_path: String::new(), message: e });
+                reports.push(report);
+                continue;
+            }
+        };
+        report.kind = payload.get("type").and_then(|v| v.as_str()).map(str::to_string);
+
+        let detected = match protocol {
+            Some(p) => Ok(p),
+            None => Protocol::detect(&payload),
+        };
+        match detected {
+            Ok(p) => {
+                report.schema = Some(p);
+                report.errors = schema::validate(p, &payload)?;
+            }
+            Err(e) => report.errors.push(SchemaError {
+                path: "/@context".to_string(),
+                schema_path: String::new(),
+                message: e.to_string(),
+            }),
+        }
+        report.valid = report.errors.is_empty();
+        reports.push(report);
+    }
+
+    let invalid = reports.iter().filter(|r| !r.valid).count();
+
+    let value = if reports.len() == 1 { json!(reports[0]) } else { json!(reports) };
+    if !ctx.json {
+        for report in &reports {
+            let label = report.id.clone().unwrap_or_else(|| format!("#{}", report.index));
+            let schema = report.schema.map(|p| format!("{:?}", p).to_uppercase()).unwrap_or_else(|| "?".to_string());
+            let kind = report.kind.as_deref().unwrap_or("-");
+            if report.valid {
+                println!("{} {} ({} {})", "VALID".green(), label, schema, kind);
+            } else {
+                println!("{} {} ({} {})", "INVALID".red(), label, schema, kind);
+                for error in &report.errors {
+                    let path = if error.path.is_empty() { "/" } else { error.path.as_str() };
+                    println!("  {} {}", path.yellow(), error.message);
+                }
+            }
+        }
+    }
+
+    if invalid > 0 {
+        let err = output::error(ErrorKind::Validation, format!("{} of {} payloads failed validation", invalid, reports.len()));
+        return Err(output::fail(ctx.json, &value, err));
+    }
+    if ctx.json {
+        output::emit(&value)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_payloads_keep_their_id() {
+        let (id, payload) = unwrap_payload(json!({ "id": "urn:uuid:1", "type": "Offer" }));
+        assert_eq!(id.as_deref(), Some("urn:uuid:1"));
+        assert_eq!(payload.unwrap()["type"], "Offer");
+    }
+
+    #[test]
+    fn decrypt_output_is_unwrapped() {
+        let (id, payload) = unwrap_payload(json!({ "decrypted_with": { "type": "key" }, "payload": { "type": "Offer" } }));
+        assert_eq!(id, None);
+        assert_eq!(payload.unwrap(), json!({ "type": "Offer" }));
+
+        let (_, payload) = unwrap_payload(json!({ "id": "m1", "decrypted_with": null, "payload": null, "error": "bad tag" }));
+        assert!(payload.unwrap_err().contains("bad tag"));
+    }
+}
@@ -1,21 +1,324 @@
-use anyhow::Result;
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::BTreeMap;
+use std::fs;
 use std::path::PathBuf;
+use std::time::Duration;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+pub const DEFAULT_RELAY: &str = "http://localhost:3000";
+pub const DEFAULT_LEDGER: &str = "http://localhost:8082";
+pub const DEFAULT_DISCOVERY: &str = "http://localhost:8081";
+pub const DEFAULT_TIMEOUT_SECS: u64 = 30;
+pub const DEFAULT_DID_CACHE_TTL_SECS: u64 = 300;
+
+/// Keys accepted by `oap config get/set`, at top level and inside `[profiles.*]`.
+pub const KEYS: &[&str] = &[
+    "default_relay", "default_identity", "timeout_secs", "output", "did_fixtures", "did_cache_ttl_secs",
+    "ledger_url", "discovery_url",
+];
+
+/// Effective configuration after merging file, profile and environment.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub default_relay: String,
+    pub default_identity: Option<String>,
+    pub timeout_secs: u64,
+    pub output: String,
+    /// Directory of pinned DID documents; when set, did:web is never fetched
+    pub did_fixtures: Option<String>,
+    /// How long resolved did:web documents are cached (0 disables the cache)
+    pub did_cache_ttl_secs: u64,
+    /// Base URL of the OAPP ledger (localnet mock ledger by default)
+    pub ledger_url: String,
+    /// Base URL of the discovery (PSI) service
+    pub discovery_url: String,
+    pub profile: Option<String>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
-            default_relay: "http://localhost:3000".to_string(),
+            default_relay: DEFAULT_RELAY.to_string(),
+            default_identity: None,
+            timeout_secs: DEFAULT_TIMEOUT_SECS,
+            output: "text".to_string(),
+            did_fixtures: None,
+            did_cache_ttl_secs: DEFAULT_DID_CACHE_TTL_SECS,
+            ledger_url: DEFAULT_LEDGER.to_string(),
+            discovery_url: DEFAULT_DISCOVERY.to_string(),
+            profile: None,
+        }
+    }
+}
+
+impl Config {
+    /// HTTP client honouring the configured timeout.
+    pub fn http_client(&self) -> Result<reqwest::Client> {
+        Ok(self.http_builder().build()?)
+    }
+
+    /// Builder preset with the configured timeout, for clients that need extra TLS settings.
+    pub fn http_builder(&self) -> reqwest::ClientBuilder {
+        reqwest::Client::builder().timeout(Duration::from_secs(self.timeout_secs))
+    }
+}
+
+/// A set of overrides. Used both for the top level of the file and for each profile.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Profile {
+    pub default_relay: Option<String>,
+    pub default_identity: Option<String>,
+    pub timeout_secs: Option<u64>,
+    pub output: Option<String>,
+    pub did_fixtures: Option<String>,
+    pub did_cache_ttl_secs: Option<u64>,
+    pub ledger_url: Option<String>,
+    pub discovery_url: Option<String>,
+}
+
+impl Profile {
+    fn apply(&self, cfg: &mut Config) {
+        if let Some(v) = &self.default_relay { cfg.default_relay = v.clone(); }
+        if let Some(v) = &self.default_identity { cfg.default_identity = Some(v.clone()); }
+        if let Some(v) = self.timeout_secs { cfg.timeout_secs = v; }
+        if let Some(v) = &self.output { cfg.output = v.clone(); }
+        if let Some(v) = &self.did_fixtures { cfg.did_fixtures = Some(v.clone()); }
+        if let Some(v) = self.did_cache_ttl_secs { cfg.did_cache_ttl_secs = v; }
+        if let Some(v) = &self.ledger_url { cfg.ledger_url = v.clone(); }
+        if let Some(v) = &self.discovery_url { cfg.discovery_url = v.clone(); }
+    }
+}
+
+/// On-disk layout of `~/.oap/config.toml`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConfigFile {
+    /// Profile used when neither `--profile` nor `OAP_PROFILE` is set
+    pub active_profile: Option<String>,
+    #[serde(flatten)]
+    pub defaults: Profile,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+pub fn default_path() -> Result<PathBuf> {
+    let home = dirs::home_dir().ok_or_else(|| anyhow!("Could not determine home directory"))?;
+    Ok(home.join(".oap").join("config.toml"))
+}
+
+fn resolve_path(path: Option<PathBuf>) -> Result<PathBuf> {
+    match path {
+        Some(p) => Ok(p),
+        None => default_path(),
+    }
+}
+
+/// Reads the config file. A missing file at the default location is not an error,
+/// but an explicit `--config` path must exist.
+pub fn read_file(path: Option<PathBuf>) -> Result<(PathBuf, ConfigFile)> {
+    let explicit = path.is_some();
+    let path = resolve_path(path)?;
+    if !path.exists() {
+        if explicit {
+            bail!("Config file {} not found", path.display());
+        }
+        return Ok((path, ConfigFile::default()));
+    }
+    let content = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let file: ConfigFile = toml::from_str(&content)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    Ok((path, file))
+}
+
+pub fn write_file(path: &PathBuf, file: &ConfigFile) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, toml::to_string_pretty(file)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Reads an environment variable; the lookup [`merge`] and [`selected_profile`] use outside tests.
+pub fn env_var(key: &str) -> Option<String> {
+    std::env::var(key).ok()
+}
+
+/// The profile in effect: `--profile`, else `OAP_PROFILE`, else the file's `active_profile`.
+pub fn selected_profile(
+    file: &ConfigFile,
+    flag: Option<String>,
+    env: &impl Fn(&str) -> Option<String>,
+) -> Option<String> {
+    flag.or_else(|| env("OAP_PROFILE")).or_else(|| file.active_profile.clone())
+}
+
+/// Loads the effective config.
+/// Precedence: built-in defaults < file top level < selected profile < `OAP_*` env vars.
+pub async fn load(path: Option<PathBuf>, profile: Option<String>) -> Result<Config> {
+    let (path, file) = read_file(path)?;
+    merge(&file, profile, &env_var)
+        .with_context(|| format!("Invalid configuration ({})", path.display()))
+}
+
+/// Merges the file, the selected profile and the `OAP_*` variables read through `env`.
+pub fn merge(file: &ConfigFile, profile: Option<String>, env: &impl Fn(&str) -> Option<String>) -> Result<Config> {
+    let mut cfg = Config::default();
+    file.defaults.apply(&mut cfg);
+
+    if let Some(name) = selected_profile(file, profile, env) {
+        let p = file.profiles.get(&name).ok_or_else(|| anyhow!("Profile '{}' not found", name))?;
+        p.apply(&mut cfg);
+        cfg.profile = Some(name);
+    }
+
+    if let Some(v) = env("OAP_RELAY") { cfg.default_relay = v; }
+    if let Some(v) = env("OAP_IDENTITY") { cfg.default_identity = Some(v); }
+    if let Some(v) = env("OAP_TIMEOUT") {
+        cfg.timeout_secs = v.parse().context("OAP_TIMEOUT must be a number of seconds")?;
+    }
+    if let Some(v) = env("OAP_OUTPUT") { cfg.output = v; }
+    if let Some(v) = env("OAP_DID_FIXTURES") { cfg.did_fixtures = Some(v); }
+    if let Some(v) = env("OAP_LEDGER") { cfg.ledger_url = v; }
+    if let Some(v) = env("OAP_DISCOVERY") { cfg.discovery_url = v; }
+
+    if cfg.output != "text" && cfg.output != "json" {
+        bail!("Invalid output format '{}' (expected text or json)", cfg.output);
+    }
+
+    Ok(cfg)
+}
+
+/// Reads a single key from the top level or from `profile`.
+pub fn get_key(file: &ConfigFile, profile: Option<&str>, key: &str) -> Result<Option<String>> {
+    if key == "active_profile" {
+        return Ok(file.active_profile.clone());
+    }
+    let section = match profile {
+        Some(name) => file.profiles.get(name).ok_or_else(|| anyhow!("Profile '{}' not found", name))?,
+        None => &file.defaults,
+    };
+    Ok(match key {
+        "default_relay" => section.default_relay.clone(),
+        "default_identity" => section.default_identity.clone(),
+        "timeout_secs" => section.timeout_secs.map(|v| v.to_string()),
+        "output" => section.output.clone(),
+        "did_fixtures" => section.did_fixtures.clone(),
+        "did_cache_ttl_secs" => section.did_cache_ttl_secs.map(|v| v.to_string()),
+        "ledger_url" => section.ledger_url.clone(),
+        "discovery_url" => section.discovery_url.clone(),
+        _ => bail!("Unknown config key '{}' (expected one of: active_profile, {})", key, KEYS.join(", ")),
+    })
+}
+
+/// Sets a single key at the top level or in `profile` (created if missing).
+pub fn set_key(file: &mut ConfigFile, profile: Option<&str>, key: &str, value: &str) -> Result<()> {
+    if key == "active_profile" {
+        file.active_profile = Some(value.to_string());
+        return Ok(());
+    }
+    let section = match profile {
+        Some(name) => file.profiles.entry(name.to_string()).or_default(),
+        None => &mut file.defaults,
+    };
+    match key {
+        "default_relay" => section.default_relay = Some(value.to_string()),
+        "default_identity" => section.default_identity = Some(value.to_string()),
+        "timeout_secs" => {
+            section.timeout_secs = Some(value.parse().context("timeout_secs must be a number of seconds")?)
         }
+        "output" => {
+            if value != "text" && value != "json" {
+                bail!("Invalid output format '{}' (expected text or json)", value);
+            }
+            section.output = Some(value.to_string())
+        }
+        "did_fixtures" => section.did_fixtures = Some(value.to_string()),
+        "did_cache_ttl_secs" => {
+            section.did_cache_ttl_secs = Some(value.parse().context("did_cache_ttl_secs must be a number of seconds")?)
+        }
+        "ledger_url" => section.ledger_url = Some(value.to_string()),
+        "discovery_url" => section.discovery_url = Some(value.to_string()),
+        _ => bail!("Unknown config key '{}' (expected one of: active_profile, {})", key, KEYS.join(", ")),
     }
+    Ok(())
 }
 
-pub async fn load(_path: Option<PathBuf>) -> Result<Config> {
-    // TODO: Load from file
-    Ok(Config::default())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str = r#"
+default_relay = "http://file"
+timeout_secs = 10
+
+[profiles.staging]
+default_relay = "http://staging"
+ledger_url = "http://staging-ledger"
+
+[profiles.ci]
+output = "json"
+"#;
+
+    fn file() -> ConfigFile {
+        toml::from_str(FILE).unwrap()
+    }
+
+    fn env(vars: &'static [(&'static str, &'static str)]) -> impl Fn(&str) -> Option<String> {
+        move |key| vars.iter().find(|(k, _)| *k == key).map(|(_, v)| v.to_string())
+    }
+
+    #[test]
+    fn file_overrides_defaults() {
+        let cfg = merge(&file(), None, &env(&[])).unwrap();
+        assert_eq!(cfg.default_relay, "http://file");
+        assert_eq!(cfg.timeout_secs, 10);
+        assert_eq!(cfg.ledger_url, DEFAULT_LEDGER);
+        assert_eq!(cfg.profile, None);
+    }
+
+    #[test]
+    fn profile_overrides_file_and_env_overrides_profile() {
+        let cfg = merge(&file(), Some("staging".into()), &env(&[])).unwrap();
+        assert_eq!(cfg.default_relay, "http://staging");
+        assert_eq!(cfg.ledger_url, "http://staging-ledger");
+        assert_eq!(cfg.timeout_secs, 10);
+
+        let cfg = merge(&file(), Some("staging".into()), &env(&[("OAP_RELAY", "http://env"), ("OAP_TIMEOUT", "3")])).unwrap();
+        assert_eq!(cfg.default_relay, "http://env");
+        assert_eq!(cfg.ledger_url, "http://staging-ledger");
+        assert_eq!(cfg.timeout_secs, 3);
+    }
+
+    #[test]
+    fn profile_selection_order() {
+        let mut file = file();
+        file.active_profile = Some("ci".into());
+        assert_eq!(selected_profile(&file, None, &env(&[])).as_deref(), Some("ci"));
+        assert_eq!(selected_profile(&file, None, &env(&[("OAP_PROFILE", "staging")])).as_deref(), Some("staging"));
+        assert_eq!(
+            selected_profile(&file, Some("other".into()), &env(&[("OAP_PROFILE", "staging")])).as_deref(),
+            Some("other")
+        );
+
+        let cfg = merge(&file, None, &env(&[])).unwrap();
+        assert_eq!(cfg.profile.as_deref(), Some("ci"));
+        assert_eq!(cfg.output, "json");
+    }
+
+    #[test]
+    fn rejects_bad_values() {
+        assert!(merge(&file(), Some("missing".into()), &env(&[])).is_err());
+        assert!(merge(&file(), None, &env(&[("OAP_TIMEOUT", "soon")])).is_err());
+        assert!(merge(&file(), None, &env(&[("OAP_OUTPUT", "yaml")])).is_err());
+    }
+
+    #[test]
+    fn get_and_set_target_the_profile_section() {
+        let mut file = file();
+        set_key(&mut file, Some("dev"), "default_relay", "http://dev").unwrap();
+        assert_eq!(get_key(&file, Some("dev"), "default_relay").unwrap().as_deref(), Some("http://dev"));
+        assert_eq!(get_key(&file, None, "default_relay").unwrap().as_deref(), Some("http://file"));
+        assert!(set_key(&mut file, None, "timeout_secs", "abc").is_err());
+        assert!(get_key(&file, None, "nope").is_err());
+    }
 }
@@ -0,0 +1,101 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+/// Client for the discovery service (Layer 0: phone/email -> DID), as implemented by
+/// the localnet mock.
+pub struct DiscoveryClient {
+    base: String,
+    client: reqwest::Client,
+}
+
+#[derive(Debug, Serialize)]
+struct IntersectRequest<'a> {
+    identifiers: &'a [String],
+}
+
+#[derive(Debug, Deserialize)]
+struct IntersectResponse {
+    matches: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize)]
+struct AddContactRequest<'a> {
+    identifier: &'a str,
+    did: &'a str,
+}
+
+/// Canonical form of a contact identifier: emails are lowercased, phone numbers keep
+/// only the leading `+` and digits. Anything else is just trimmed.
+pub fn normalize_identifier(raw: &str) -> String {
+    let raw = raw.trim();
+    if raw.contains('@') {
+        return raw.to_lowercase();
+    }
+    let is_phone = raw.starts_with('+') || raw.starts_with(|c: char| c.is_ascii_digit());
+    if is_phone && raw.chars().all(|c| c.is_ascii_digit() || "+-(). ".contains(c)) {
+        let digits: String = raw.chars().filter(|c| c.is_ascii_digit()).collect();
+        return if raw.starts_with('+') { format!("+{}", digits) } else { digits };
+    }
+    raw.to_string()
+}
+
+/// What the client sends instead of the identifier: hex SHA-256 of its normalized form.
+pub fn hash_identifier(raw: &str) -> String {
+    hex::encode(Sha256::digest(normalize_identifier(raw).as_bytes()))
+}
+
+impl DiscoveryClient {
+    pub fn new(base: &str, client: reqwest::Client) -> Self {
+        Self { base: base.trim_end_matches('/').to_string(), client }
+    }
+
+    pub fn base(&self) -> &str {
+        &self.base
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base, path)
+    }
+
+    async fn check(&self, res: reqwest::Response, path: &str) -> Result<reqwest::Response> {
+        let status = res.status();
+        if !status.is_success() {
+            let body = res.text().await.unwrap_or_default();
+            bail!("Discovery returned {} for {}: {}", status, path, body);
+        }
+        Ok(res)
+    }
+
+    /// `POST /psi/intersect`. Returns the submitted identifiers the service knows, with their DIDs.
+    pub async fn intersect(&self, identifiers: &[String]) -> Result<BTreeMap<String, String>> {
+        let res = self.client.post(self.url("/psi/intersect"))
+            .json(&IntersectRequest { identifiers })
+            .send().await
+            .with_context(|| format!("Failed to reach discovery at {}", self.base))?;
+        let body: IntersectResponse = self.check(res, "/psi/intersect").await?
+            .json().await
+            .context("Unexpected /psi/intersect response from discovery")?;
+        Ok(body.matches)
+    }
+
+    /// `POST /admin/add-contact`.
+    pub async fn add_contact(&self, identifier: &str, did: &str) -> Result<()> {
+        let res = self.client.post(self.url("/admin/add-contact"))
+            .json(&AddContactRequest { identifier, did })
+            .send().await
+            .with_context(|| format!("Failed to reach discovery at {}", self.base))?;
+        self.check(res, "/admin/add-contact").await?;
+        Ok(())
+    }
+
+    /// `GET /admin/list`: the whole directory, identifier -> DID.
+    pub async fn list(&self) -> Result<BTreeMap<String, String>> {
+        let res = self.client.get(self.url("/admin/list")).send().await
+            .with_context(|| format!("Failed to reach discovery at {}", self.base))?;
+        self.check(res, "/admin/list").await?
+            .json().await
+            .context("Unexpected /admin/list response from discovery")
+    }
+}
@@ -0,0 +1,363 @@
+use anyhow::{anyhow, bail, Context, Result};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use oap::oaep::did::{Did, DidKey};
+use oap::oaep::keys::{SessionKey, X25519Public, X25519Secret};
+use oap::oaep::messages::{
+    ConnectionRequest, ConnectionResponse, HandshakeTranscript, KeyExchangeAccept, KeyExchangeOffer, RequestBody,
+    RequestProof, ResponseBody, ResponseProof,
+};
+use oap::oaep::types::{CipherSuite, Nonce, Timestamp};
+use serde::Serialize;
+use serde_json::Value;
+use crate::resolver::{self, Resolver};
+
+/// HKDF info used by OAEP v1 for the session key pair (see inspector `xray.rs`).
+pub const SESSION_INFO: &[u8] = b"OAEP-v1-Session-Keys";
+
+/// How far a request's `created` time may be from the responder's clock, in seconds.
+pub const MAX_REQUEST_AGE_SECS: i64 = 300;
+
+/// Everything observed during one handshake, for `--verbose`/`--json` output.
+#[derive(Debug, Serialize)]
+pub struct HandshakeTrace {
+    pub endpoint: String,
+    pub request: ConnectionRequest,
+    pub response: ConnectionResponse,
+    pub cipher_suite: CipherSuite,
+    pub initiator_nonce: Nonce,
+    pub responder_nonce: Nonce,
+    pub transcript_hash: String,
+    pub transcript_hash_matches: bool,
+    pub proof_verified: bool,
+    pub kid: String,
+}
+
+/// Key ID of a session: hex of the first 16 bytes of the transcript hash.
+pub fn kid(transcript_hash: &[u8]) -> String {
+    hex::encode(&transcript_hash[..16.min(transcript_hash.len())])
+}
+
+/// The `#fragment` verification method of a did:key.
+pub fn verification_method(did_key: &DidKey) -> String {
+    let did = did_key.did().to_string();
+    let fragment = did.strip_prefix("did:key:").unwrap_or(&did).to_string();
+    format!("{}#{}", did, fragment)
+}
+
+/// Canonical JSON: object members sorted by key at every level, no insignificant
+/// whitespace. Does not depend on the order serde_json keeps map keys in.
+pub fn canonical_json(value: &Value) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_canonical(value, &mut out);
+    out
+}
+
+fn write_canonical(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Object(obj) => {
+            let mut members: Vec<_> = obj.iter().collect();
+            members.sort_by(|a, b| a.0.cmp(b.0));
+            out.push(b'{');
+            for (i, (key, value)) in members.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                write_canonical(&Value::String(key.clone()), out);
+                out.push(b':');
+                write_canonical(value, out);
+            }
+            out.push(b'}');
+        }
+        Value::Array(items) => {
+            out.push(b'[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                write_canonical(item, out);
+            }
+            out.push(b']');
+        }
+        scalar => out.extend(serde_json::to_vec(scalar).unwrap_or_default()),
+    }
+}
+
+/// Bytes covered by a message's proof: the canonical JSON of the message without `proof`.
+pub fn signing_input<T: Serialize>(message: &T) -> Result<Vec<u8>> {
+    let mut value = serde_json::to_value(message)?;
+    if let Some(obj) = value.as_object_mut() {
+        obj.remove("proof");
+    }
+    Ok(canonical_json(&value))
+}
+
+/// Ed25519 signature of `message` by the identity key, hex encoded.
+pub fn sign(identity: &DidKey, message: &[u8]) -> String {
+    let key = SigningKey::from_bytes(identity.keypair().secret_key().as_bytes());
+    hex::encode(key.sign(message).to_bytes())
+}
+
+pub fn build_request(identity: &DidKey, peer: &str, ephemeral: &X25519Secret) -> Result<ConnectionRequest> {
+    let mut request = ConnectionRequest {
+        id: format!("urn:uuid:{}", uuid::Uuid::new_v4()),
+        from: identity.did(),
+        to: peer.parse::<Did>().context("Invalid peer DID")?,
+        created: Timestamp::now(),
+        body: RequestBody {
+            nonce: Nonce::generate(),
+            key_exchange: KeyExchangeOffer {
+                public_key: ephemeral.public_key().to_multibase(),
+                supported_suites: CipherSuite::supported(),
+            },
+        },
+        proof: RequestProof {
+            verification_method: verification_method(identity),
+            signature: String::new(),
+        },
+    };
+    request.proof.signature = sign(identity, &signing_input(&request)?);
+    Ok(request)
+}
+
+/// Builds the responder's answer: picks our preferred offered suite, signs the transcript hash.
+/// Returns the response together with the responder's ephemeral secret.
+pub fn build_response(
+    identity: &DidKey,
+    did: &Did,
+    verification_method: &str,
+    request: &ConnectionRequest,
+) -> Result<(ConnectionResponse, X25519Secret)> {
+    let suite = CipherSuite::supported().into_iter()
+        .find(|s| request.body.key_exchange.supported_suites.contains(s))
+        .ok_or_else(|| anyhow!("No supported cipher suite was offered"))?;
+
+    let ephemeral = X25519Secret::generate();
+    let mut response = ConnectionResponse {
+        id: format!("urn:uuid:{}", uuid::Uuid::new_v4()),
+        reply_to: request.id.clone(),
+        from: did.clone(),
+        to: request.from.clone(),
+        created: Timestamp::now(),
+        body: ResponseBody {
+            nonce: Nonce::generate(),
+            key_exchange: KeyExchangeAccept {
+                public_key: ephemeral.public_key().to_multibase(),
+                negotiated_suite: suite,
+            },
+        },
+        proof: ResponseProof {
+            verification_method: verification_method.to_string(),
+            transcript_hash: String::new(),
+            signature: String::new(),
+        },
+    };
+    let hash = transcript_hash(request, &response);
+    response.proof.transcript_hash = hex::encode(hash);
+    response.proof.signature = sign(identity, &hash);
+    Ok((response, ephemeral))
+}
+
+pub fn transcript_hash(request: &ConnectionRequest, response: &ConnectionResponse) -> [u8; 32] {
+    HandshakeTranscript::from_messages(request, response).hash()
+}
+
+/// Looks up the Ed25519 key for a `verificationMethod` id in the signer's DID document.
+pub async fn signer_key(resolver: &Resolver, verification_method: &str) -> Result<VerifyingKey> {
+    let did = verification_method.split('#').next().unwrap_or(verification_method);
+    let result = resolver.resolve(did).await?;
+    let key = resolver::verification_key(result.document(), verification_method)
+        .ok_or_else(|| anyhow!("Verification method {} not found in DID document", verification_method))?;
+    VerifyingKey::from_bytes(&key).map_err(|e| anyhow!("Invalid verification key: {}", e))
+}
+
+fn verify_signature(key: &VerifyingKey, message: &[u8], signature_hex: &str) -> bool {
+    let Ok(bytes) = hex::decode(signature_hex) else { return false };
+    let Ok(bytes) = <[u8; 64]>::try_from(bytes.as_slice()) else { return false };
+    key.verify_strict(message, &Signature::from_bytes(&bytes)).is_ok()
+}
+
+/// Checks that a request is signed by its sender (and addressed to `expected_to`, if given).
+/// Returns whether the proof signature verified.
+pub async fn verify_request(resolver: &Resolver, request: &ConnectionRequest, expected_to: Option<&str>) -> Result<bool> {
+    if let Some(expected_to) = expected_to {
+        if request.to.to_string() != expected_to {
+            bail!("Request is addressed to {} but this agent is {}", request.to, expected_to);
+        }
+    }
+    if !request.proof.verification_method.starts_with(&request.from.to_string()) {
+        bail!("Proof verification method is not controlled by {}", request.from);
+    }
+    let key = signer_key(resolver, &request.proof.verification_method).await?;
+    Ok(verify_signature(&key, &signing_input(request)?, &request.proof.signature))
+}
+
+/// Rejects a request whose `created` time is more than [`MAX_REQUEST_AGE_SECS`] away from
+/// `now`, so a captured request cannot be replayed later.
+pub fn check_fresh(request: &ConnectionRequest, now: chrono::DateTime<chrono::Utc>) -> Result<()> {
+    let created = serde_json::to_value(&request.created)?;
+    let created = created.as_str().ok_or_else(|| anyhow!("Request has no created time"))?;
+    let created = chrono::DateTime::parse_from_rfc3339(created)
+        .with_context(|| format!("Invalid created time {}", created))?;
+    let age = (now - created.with_timezone(&chrono::Utc)).num_seconds();
+    if age.abs() > MAX_REQUEST_AGE_SECS {
+        bail!("Request created {}s from now is outside the {}s window (replay?)", -age, MAX_REQUEST_AGE_SECS);
+    }
+    Ok(())
+}
+
+/// Checks the response against our request: linkage, suite, transcript hash and proof.
+/// Returns `(transcript_hash_matches, proof_verified)`.
+pub async fn verify_response(
+    resolver: &Resolver,
+    request: &ConnectionRequest,
+    response: &ConnectionResponse,
+) -> Result<(bool, bool)> {
+    if response.reply_to != request.id {
+        bail!("Response replies to {} but request id is {}", response.reply_to, request.id);
+    }
+    if response.from != request.to {
+        bail!("Response is from {} but request was sent to {}", response.from, request.to);
+    }
+    if !request.body.key_exchange.supported_suites.contains(&response.body.key_exchange.negotiated_suite) {
+        bail!("Responder negotiated a suite that was not offered: {}", response.body.key_exchange.negotiated_suite);
+    }
+    if !response.proof.verification_method.starts_with(&response.from.to_string()) {
+        bail!("Proof verification method is not controlled by {}", response.from);
+    }
+
+    let hash = transcript_hash(request, response);
+    let matches = hex::encode(hash) == response.proof.transcript_hash.to_lowercase();
+
+    let key = signer_key(resolver, &response.proof.verification_method).await?;
+    let verified = verify_signature(&key, &hash, &response.proof.signature);
+    Ok((matches, verified))
+}
+
+/// Derives `(initiator_to_responder, responder_to_initiator)` session keys.
+pub fn derive_session_keys(
+    secret: &X25519Secret,
+    peer_ephemeral: &str,
+    transcript_hash: &[u8],
+) -> Result<(SessionKey, SessionKey)> {
+    let peer = X25519Public::from_multibase(peer_ephemeral)
+        .map_err(|e| anyhow!("Invalid peer ephemeral key: {}", e))?;
+    let shared = secret.diffie_hellman(&peer);
+    Ok(SessionKey::derive(&shared, transcript_hash, SESSION_INFO))
+}
+
+/// Runs the initiator side: POST the request to the peer's OAEP endpoint and verify the answer.
+pub async fn initiate(
+    client: &reqwest::Client,
+    resolver: &Resolver,
+    identity: &DidKey,
+    peer: &str,
+) -> Result<(HandshakeTrace, X25519Secret)> {
+    let peer_doc = resolver.resolve(peer).await?;
+    let endpoint = resolver::service_endpoint(peer_doc.document())
+        .ok_or_else(|| anyhow!("DID document of {} has no service endpoint", peer))?;
+
+    let ephemeral = X25519Secret::generate();
+    let request = build_request(identity, peer, &ephemeral)?;
+
+    let res = client.post(&endpoint).json(&request).send().await
+        .with_context(|| format!("Failed to send ConnectionRequest to {}", endpoint))?;
+    let status = res.status();
+    let body: Value = res.json().await.context("Response is not JSON")?;
+    if !status.is_success() {
+        bail!("Peer rejected ConnectionRequest ({}): {}", status, body);
+    }
+    let response: ConnectionResponse = serde_json::from_value(body)
+        .context("Response is not a ConnectionResponse")?;
+
+    let (transcript_hash_matches, proof_verified) = verify_response(resolver, &request, &response).await?;
+    let hash = transcript_hash(&request, &response);
+
+    let trace = HandshakeTrace {
+        endpoint,
+        cipher_suite: response.body.key_exchange.negotiated_suite.clone(),
+        initiator_nonce: request.body.nonce.clone(),
+        responder_nonce: response.body.nonce.clone(),
+        transcript_hash: hex::encode(hash),
+        transcript_hash_matches,
+        proof_verified,
+        kid: kid(&hash),
+        request,
+        response,
+    };
+    Ok((trace, ephemeral))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolver() -> Resolver {
+        Resolver::new(reqwest::Client::new(), false)
+    }
+
+    #[test]
+    fn stale_requests_are_not_fresh() {
+        let request = build_request(&DidKey::generate(), "did:web:example.com", &X25519Secret::generate()).unwrap();
+        let now = chrono::Utc::now();
+
+        assert!(check_fresh(&request, now).is_ok());
+        assert!(check_fresh(&request, now + chrono::Duration::seconds(MAX_REQUEST_AGE_SECS + 5)).is_err());
+        assert!(check_fresh(&request, now - chrono::Duration::seconds(MAX_REQUEST_AGE_SECS + 5)).is_err());
+    }
+
+    #[tokio::test]
+    async fn round_trip_derives_equal_session_keys() {
+        let initiator = DidKey::generate();
+        let responder = DidKey::generate();
+        let responder_did = responder.did();
+
+        let ephemeral = X25519Secret::generate();
+        let request = build_request(&initiator, &responder_did.to_string(), &ephemeral).unwrap();
+        assert!(verify_request(&resolver(), &request, Some(&responder_did.to_string())).await.unwrap());
+
+        let (response, responder_ephemeral) =
+            build_response(&responder, &responder_did, &verification_method(&responder), &request).unwrap();
+        assert_eq!(verify_response(&resolver(), &request, &response).await.unwrap(), (true, true));
+
+        let hash = transcript_hash(&request, &response);
+        let (i2r, r2i) = derive_session_keys(&ephemeral, &response.body.key_exchange.public_key, &hash).unwrap();
+        let (r_i2r, r_r2i) =
+            derive_session_keys(&responder_ephemeral, &request.body.key_exchange.public_key, &hash).unwrap();
+        assert_eq!(i2r.as_bytes(), r_i2r.as_bytes());
+        assert_eq!(r2i.as_bytes(), r_r2i.as_bytes());
+        assert_ne!(i2r.as_bytes(), r2i.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn tampering_breaks_the_proofs() {
+        let initiator = DidKey::generate();
+        let responder = DidKey::generate();
+        let responder_did = responder.did();
+
+        let mut request = build_request(&initiator, &responder_did.to_string(), &X25519Secret::generate()).unwrap();
+        let (mut response, _) =
+            build_response(&responder, &responder_did, &verification_method(&responder), &request).unwrap();
+
+        response.body.key_exchange.public_key = X25519Secret::generate().public_key().to_multibase();
+        assert_eq!(verify_response(&resolver(), &request, &response).await.unwrap(), (false, false));
+
+        request.body.key_exchange.public_key = X25519Secret::generate().public_key().to_multibase();
+        assert!(!verify_request(&resolver(), &request, None).await.unwrap());
+    }
+
+    #[test]
+    fn canonical_json_sorts_keys_at_every_level() {
+        let value = serde_json::json!({ "b": [{ "z": 1, "a": "x/y" }], "a": { "d": null, "c": true } });
+        assert_eq!(
+            String::from_utf8(canonical_json(&value)).unwrap(),
+            r#"{"a":{"c":true,"d":null},"b":[{"a":"x/y","z":1}]}"#
+        );
+    }
+
+    #[test]
+    fn signing_input_ignores_the_proof() {
+        let signed = serde_json::json!({ "id": "1", "proof": { "signature": "ab" } });
+        let unsigned = serde_json::json!({ "id": "1" });
+        assert_eq!(signing_input(&signed).unwrap(), signing_input(&unsigned).unwrap());
+    }
+}
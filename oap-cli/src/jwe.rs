@@ -0,0 +1,259 @@
+use anyhow::{anyhow, Context, Result};
+use base64::{Engine as _, engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD}};
+use oap::oatp::JweContainer;
+use serde::Serialize;
+use serde_json::Value;
+
+pub fn decode_b64(segment: &str) -> Result<Vec<u8>> {
+    URL_SAFE_NO_PAD.decode(segment)
+        .or_else(|_| STANDARD.decode(segment))
+        .map_err(|e| anyhow!("Invalid Base64: {}", e))
+}
+
+/// Parses a container in either compact (`a.b.c.d.e`) or JSON serialization.
+pub fn parse_container(raw: &str) -> Result<JweContainer> {
+    let raw = raw.trim();
+    if raw.starts_with('{') {
+        serde_json::from_str(raw).context("Failed to parse JWE JSON")
+    } else {
+        JweContainer::from_compact(raw).context("Failed to parse JWE")
+    }
+}
+
+/// Decodes the protected header of a compact or JSON serialized container.
+pub fn protected_header(raw: &str) -> Result<Value> {
+    let raw = raw.trim();
+    let segment = if raw.starts_with('{') {
+        let json: Value = serde_json::from_str(raw).context("Failed to parse JWE JSON")?;
+        json.get("protected")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow!("JWE JSON has no 'protected' header"))?
+            .to_string()
+    } else {
+        raw.split('.').next().unwrap_or_default().to_string()
+    };
+    let bytes = decode_b64(&segment).context("Failed to decode header Base64")?;
+    serde_json::from_slice(&bytes).context("Failed to parse header JSON")
+}
+
+/// JSON payloads stay structured; anything else becomes a string (UTF-8 or hex).
+pub fn payload_value(plaintext: &[u8]) -> Value {
+    if let Ok(json) = serde_json::from_slice::<Value>(plaintext) {
+        return json;
+    }
+    match std::str::from_utf8(plaintext) {
+        Ok(s) => Value::String(s.to_string()),
+        Err(_) => Value::String(format!("hex:{}", hex::encode(plaintext))),
+    }
+}
+
+/// Protected header field in which `encrypt_padded` records the padding block size.
+pub const PADDING_HEADER: &str = "pad";
+
+/// Names of the five compact JWE segments, in order.
+pub const SEGMENTS: [&str; 5] = ["protected", "encrypted_key", "iv", "ciphertext", "tag"];
+
+/// Decoded length of one JWE segment.
+#[derive(Debug, Serialize)]
+pub struct SegmentInfo {
+    pub name: &'static str,
+    pub length: usize,
+}
+
+/// Structural report on a JWE, produced without any key material.
+#[derive(Debug, Serialize)]
+pub struct Inspection {
+    pub serialization: &'static str,
+    pub header: Option<Value>,
+    pub kid: Option<String>,
+    pub alg: Option<String>,
+    pub enc: Option<String>,
+    pub seq: Option<u64>,
+    pub segments: Vec<SegmentInfo>,
+    /// Padding block size from the protected header; `None` when the header does not say
+    pub padding_block: Option<usize>,
+    pub errors: Vec<String>,
+}
+
+impl Inspection {
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// Expected IV length for the content encryption algorithms used by OATP.
+fn iv_length(enc: &str) -> Option<usize> {
+    match enc {
+        "XC20P" => Some(24),
+        "C20P" | "A128GCM" | "A192GCM" | "A256GCM" => Some(12),
+        _ => None,
+    }
+}
+
+/// Inspects a compact or JSON serialized JWE (including the OATP padded container).
+/// Every structural problem is collected in `errors` instead of stopping at the first one.
+pub fn inspect(raw: &str) -> Inspection {
+    let raw = raw.trim();
+    let mut report = Inspection {
+        serialization: if raw.starts_with('{') { "json" } else { "compact" },
+        header: None,
+        kid: None,
+        alg: None,
+        enc: None,
+        seq: None,
+        segments: Vec::new(),
+        padding_block: None,
+        errors: Vec::new(),
+    };
+
+    let encoded: Vec<Option<String>> = if report.serialization == "json" {
+        match serde_json::from_str::<Value>(raw) {
+            Ok(Value::Object(obj)) => SEGMENTS.iter()
+                .map(|name| obj.get(*name).and_then(|v| v.as_str()).map(str::to_string))
+                .collect(),
+            Ok(_) => {
+                report.errors.push("JWE JSON is not an object".to_string());
+                return report;
+            }
+            Err(e) => {
+                report.errors.push(format!("Invalid JWE JSON: {}", e));
+                return report;
+            }
+        }
+    } else {
+        let parts: Vec<&str> = raw.split('.').collect();
+        if parts.len() != SEGMENTS.len() {
+            report.errors.push(format!(
+                "Expected {} dot-separated segments, found {} (truncated or not a JWE?)",
+                SEGMENTS.len(),
+                parts.len()
+            ));
+        }
+        (0..SEGMENTS.len()).map(|i| parts.get(i).map(|s| s.to_string())).collect()
+    };
+
+    let mut decoded: Vec<Option<Vec<u8>>> = Vec::new();
+    for (name, segment) in SEGMENTS.iter().zip(encoded) {
+        let bytes = match segment {
+            None if report.serialization == "json" && *name == "encrypted_key" => Some(Vec::new()),
+            None if report.serialization == "json" => {
+                report.errors.push(format!("Missing '{}' member", name));
+                None
+            }
+            None => None,
+            Some(s) => match decode_b64(&s) {
+                Ok(bytes) => Some(bytes),
+                Err(e) => {
+                    report.errors.push(format!("Segment '{}' is not valid Base64url: {}", name, e));
+                    None
+                }
+            },
+        };
+        if let Some(bytes) = &bytes {
+            report.segments.push(SegmentInfo { name, length: bytes.len() });
+        }
+        decoded.push(bytes);
+    }
+
+    if let Some(header_bytes) = &decoded[0] {
+        match serde_json::from_slice::<Value>(header_bytes) {
+            Ok(header @ Value::Object(_)) => {
+                let field = |k: &str| header.get(k).and_then(|v| v.as_str()).map(str::to_string);
+                report.kid = field("kid");
+                report.alg = field("alg");
+                report.enc = field("enc");
+                report.seq = header.get("seq").and_then(|v| v.as_u64());
+                report.padding_block = header.get(PADDING_HEADER).and_then(|v| v.as_u64()).map(|v| v as usize);
+                for required in ["alg", "enc"] {
+                    if header.get(required).is_none() {
+                        report.errors.push(format!("Protected header has no '{}'", required));
+                    }
+                }
+                if header.get("seq").is_some() && report.seq.is_none() {
+                    report.errors.push("Header 'seq' is not an unsigned integer".to_string());
+                }
+                report.header = Some(header);
+            }
+            Ok(_) => report.errors.push("Protected header is not a JSON object".to_string()),
+            Err(e) => report.errors.push(format!("Protected header is not JSON: {}", e)),
+        }
+    }
+
+    if let (Some(iv), Some(expected)) = (&decoded[2], report.enc.as_deref().and_then(iv_length)) {
+        if iv.len() != expected {
+            report.errors.push(format!("IV is {} bytes, {} requires {}", iv.len(), report.enc.as_deref().unwrap_or_default(), expected));
+        }
+    }
+    if let Some(ciphertext) = &decoded[3] {
+        if ciphertext.is_empty() {
+            report.errors.push("Ciphertext is empty".to_string());
+        } else if let Some(block) = report.padding_block.filter(|b| *b > 0) {
+            if ciphertext.len() % block != 0 {
+                report.errors.push(format!("Ciphertext length {} is not a multiple of the {}-byte padding block", ciphertext.len(), block));
+            }
+        }
+    }
+    if let Some(tag) = &decoded[4] {
+        if tag.len() != 16 {
+            report.errors.push(format!("Authentication tag is {} bytes, expected 16", tag.len()));
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oap::oatp::container::encrypt_padded;
+
+    fn padded(block: usize) -> String {
+        encrypt_padded(b"hello", &[7u8; 32], "kid-1", 3, block).unwrap().to_compact()
+    }
+
+    #[test]
+    fn inspects_an_encrypt_padded_container() {
+        let report = inspect(&padded(256));
+
+        assert!(report.is_valid(), "{:?}", report.errors);
+        assert_eq!(report.serialization, "compact");
+        assert_eq!(report.kid.as_deref(), Some("kid-1"));
+        assert_eq!(report.seq, Some(3));
+        assert_eq!(report.padding_block, Some(256));
+        assert_eq!(report.segments.len(), SEGMENTS.len());
+        assert_eq!(report.segments[3].length % 256, 0);
+    }
+
+    #[test]
+    fn padding_is_unknown_without_the_header_field() {
+        let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"dir","enc":"C20P"}"#);
+        let raw = format!("{}..{}.{}.{}", header, URL_SAFE_NO_PAD.encode([0u8; 12]), URL_SAFE_NO_PAD.encode([0u8; 64]), URL_SAFE_NO_PAD.encode([0u8; 16]));
+        let report = inspect(&raw);
+
+        assert!(report.is_valid(), "{:?}", report.errors);
+        assert_eq!(report.padding_block, None);
+    }
+
+    #[test]
+    fn reports_every_structural_problem() {
+        let raw = padded(256);
+        let parts: Vec<&str> = raw.split('.').collect();
+        let truncated = format!("{}.{}.{}.{}", parts[0], parts[1], "!!!", parts[3]);
+        let report = inspect(&truncated);
+
+        assert!(!report.is_valid());
+        assert!(report.errors.iter().any(|e| e.contains("segments")));
+        assert!(report.errors.iter().any(|e| e.contains("'iv'")));
+    }
+
+    #[test]
+    fn json_serialization_matches_compact() {
+        let container = encrypt_padded(b"hello", &[7u8; 32], "kid-1", 3, 256).unwrap();
+        let json = serde_json::to_string(&container).unwrap();
+
+        let report = inspect(&json);
+        assert_eq!(report.serialization, "json");
+        assert_eq!(report.padding_block, Some(256));
+        assert_eq!(protected_header(&json).unwrap(), protected_header(&container.to_compact()).unwrap());
+    }
+}
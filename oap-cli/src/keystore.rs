@@ -0,0 +1,407 @@
+use anyhow::{anyhow, bail, Context, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use colored::*;
+use oap::oaep::did::DidKey;
+use oap::oaep::keys::KeyPair;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Current on-disk format of `~/.oap/keystore/{alias}.json`.
+pub const KEYSTORE_VERSION: u32 = 1;
+
+/// Environment variable consulted before prompting for a passphrase (for CI).
+pub const PASSPHRASE_ENV: &str = "OAP_PASSPHRASE";
+
+/// Multicodec prefix (varint) of an ed25519-priv multibase secret, as written by `identity export`.
+pub const ED25519_PRIV_CODEC: [u8; 2] = [0x80, 0x26];
+
+const KDF_ALG: &str = "argon2id";
+const CIPHER_ALG: &str = "chacha20poly1305";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+    pub salt: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptedSecret {
+    pub kdf: String,
+    pub kdf_params: KdfParams,
+    pub cipher: String,
+    pub nonce: String,
+    pub ciphertext: String,
+}
+
+/// A single identity as stored on disk. Only the secret key is encrypted,
+/// so `list`/`show` work without a passphrase.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeystoreEntry {
+    pub version: u32,
+    pub alias: String,
+    pub did: String,
+    pub public_key: String,
+    pub created_at: u64,
+    pub crypto: EncryptedSecret,
+}
+
+pub struct Keystore {
+    dir: PathBuf,
+}
+
+impl Keystore {
+    /// Opens the default keystore at `~/.oap/keystore`.
+    pub fn open_default() -> Result<Self> {
+        let home = dirs::home_dir().ok_or_else(|| anyhow!("Could not determine home directory"))?;
+        Ok(Self::open(home.join(".oap").join("keystore")))
+    }
+
+    pub fn open(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    pub fn path_for(&self, alias: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", alias))
+    }
+
+    pub fn exists(&self, alias: &str) -> bool {
+        validate_alias(alias).is_ok() && self.path_for(alias).exists()
+    }
+
+    /// Encrypts the identity's secret key under `passphrase` and writes it to disk.
+    pub fn save(&self, alias: &str, did_key: &DidKey, passphrase: &str) -> Result<PathBuf> {
+        validate_alias(alias)?;
+        if self.exists(alias) {
+            bail!("Identity '{}' already exists in keystore", alias);
+        }
+
+        let secret = did_key.keypair().secret_key().as_bytes();
+        let entry = KeystoreEntry {
+            version: KEYSTORE_VERSION,
+            alias: alias.to_string(),
+            did: did_key.did().to_string(),
+            public_key: hex::encode(did_key.keypair().public_key().as_bytes()),
+            created_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            crypto: seal(secret, passphrase)?,
+        };
+
+        self.write_entry(&entry)
+    }
+
+    /// Reads the entry metadata without decrypting the secret.
+    pub fn entry(&self, alias: &str) -> Result<KeystoreEntry> {
+        validate_alias(alias)?;
+        let path = self.path_for(alias);
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Identity '{}' not found in keystore", alias))?;
+        let entry: KeystoreEntry = serde_json::from_str(&content)
+            .with_context(|| format!("Corrupt keystore file {}", path.display()))?;
+        if entry.version != KEYSTORE_VERSION {
+            bail!(
+                "Unsupported keystore version {} for '{}' (expected {})",
+                entry.version, alias, KEYSTORE_VERSION
+            );
+        }
+        Ok(entry)
+    }
+
+    /// Decrypts the identity stored under `alias`.
+    pub fn load(&self, alias: &str, passphrase: &str) -> Result<DidKey> {
+        let entry = self.entry(alias)?;
+        let secret = open(&entry.crypto, passphrase)
+            .with_context(|| format!("Failed to unlock identity '{}'", alias))?;
+        let keypair = KeyPair::from_secret_bytes(&secret)?;
+        let did_key = DidKey::from_keypair(keypair);
+
+        if did_key.did().to_string() != entry.did {
+            bail!("Keystore entry '{}' does not match its DID", alias);
+        }
+        Ok(did_key)
+    }
+
+    /// Lists all entries, sorted by alias. Unreadable files (corrupt, or written by
+    /// another keystore version) are skipped with a warning on stderr.
+    pub fn list(&self) -> Result<Vec<KeystoreEntry>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut entries = Vec::new();
+        for file in fs::read_dir(&self.dir)? {
+            let path = file?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            let Some(alias) = path.file_stem().and_then(|s| s.to_str()) else { continue };
+            match self.entry(alias) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => eprintln!("{} skipping {}: {:#}", "Warning:".yellow(), path.display(), e),
+            }
+        }
+        entries.sort_by(|a, b| a.alias.cmp(&b.alias));
+        Ok(entries)
+    }
+
+    pub fn delete(&self, alias: &str) -> Result<()> {
+        self.entry(alias)?;
+        fs::remove_file(self.path_for(alias))
+            .with_context(|| format!("Failed to delete identity '{}'", alias))
+    }
+
+    /// Renames an entry. The secret stays encrypted; only the alias changes.
+    pub fn rename(&self, from: &str, to: &str) -> Result<PathBuf> {
+        validate_alias(to)?;
+        if self.exists(to) {
+            bail!("Identity '{}' already exists in keystore", to);
+        }
+        let mut entry = self.entry(from)?;
+        entry.alias = to.to_string();
+        let path = self.write_entry(&entry)?;
+        fs::remove_file(self.path_for(from))?;
+        Ok(path)
+    }
+
+    fn write_entry(&self, entry: &KeystoreEntry) -> Result<PathBuf> {
+        create_private_dir(&self.dir)?;
+        let path = self.path_for(&entry.alias);
+        write_private(&path, serde_json::to_string_pretty(entry)?.as_bytes())?;
+        Ok(path)
+    }
+}
+
+/// Loads `alias` from the default keystore, prompting for the passphrase.
+pub fn load_identity(alias: &str) -> Result<DidKey> {
+    let keystore = Keystore::open_default()?;
+    // Fail on a missing alias before asking for a passphrase
+    keystore.entry(alias)?;
+    let passphrase = read_passphrase(&format!("Passphrase for '{}': ", alias), false)?;
+    keystore.load(alias, &passphrase)
+}
+
+/// Reads a passphrase from `OAP_PASSPHRASE` or the terminal.
+pub fn read_passphrase(prompt: &str, confirm: bool) -> Result<String> {
+    if let Ok(p) = std::env::var(PASSPHRASE_ENV) {
+        return Ok(p);
+    }
+    let passphrase = rpassword::prompt_password(prompt).context("Failed to read passphrase")?;
+    if confirm {
+        let again = rpassword::prompt_password("Confirm passphrase: ")
+            .context("Failed to read passphrase")?;
+        if again != passphrase {
+            bail!("Passphrases do not match");
+        }
+    }
+    Ok(passphrase)
+}
+
+/// Parses a 32-byte secret key from a file path (raw hex or `{"secret_key": ...}`
+/// JSON as written by `did gen --format json`), a hex string or a multibase string.
+pub fn parse_secret(input: &str) -> Result<[u8; 32]> {
+    let bytes = if Path::new(input).exists() {
+        let content = fs::read_to_string(input).context("Failed to read key file")?;
+        match serde_json::from_str::<serde_json::Value>(&content) {
+            Ok(json) => match json.get("secret_key").and_then(|v| v.as_str()) {
+                Some(sk) => hex::decode(sk).context("Failed to decode secret_key hex")?,
+                None => hex::decode(content.trim()).context("Failed to decode key hex")?,
+            },
+            Err(_) => hex::decode(content.trim()).context("Failed to decode key hex")?,
+        }
+    } else if let Ok(bytes) = hex::decode(input) {
+        bytes
+    } else if let Ok((_, bytes)) = multibase::decode(input) {
+        match bytes.strip_prefix(ED25519_PRIV_CODEC.as_slice()) {
+            Some(key) if key.len() == 32 => key.to_vec(),
+            _ => bytes,
+        }
+    } else {
+        bail!("Key is neither a file, a hex string nor a multibase string");
+    };
+
+    bytes
+        .try_into()
+        .map_err(|b: Vec<u8>| anyhow!("Invalid secret key length: expected 32 bytes, got {}", b.len()))
+}
+
+pub fn validate_alias(alias: &str) -> Result<()> {
+    let valid = !alias.is_empty()
+        && alias.len() <= 64
+        && alias.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+        && !alias.starts_with('.');
+    if !valid {
+        bail!("Invalid alias '{}': use letters, digits, '-', '_' or '.'", alias);
+    }
+    Ok(())
+}
+
+fn derive_key(passphrase: &str, params: &KdfParams) -> Result<[u8; 32]> {
+    let salt = hex::decode(&params.salt).context("Invalid KDF salt")?;
+    let argon_params = Params::new(params.m_cost, params.t_cost, params.p_cost, Some(32))
+        .map_err(|e| anyhow!("Invalid KDF parameters: {}", e))?;
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, argon_params)
+        .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+        .map_err(|e| anyhow!("Key derivation failed: {}", e))?;
+    Ok(key)
+}
+
+fn seal(secret: &[u8; 32], passphrase: &str) -> Result<EncryptedSecret> {
+    let mut salt = [0u8; 16];
+    let mut nonce = [0u8; 12];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let params = KdfParams {
+        m_cost: Params::DEFAULT_M_COST,
+        t_cost: Params::DEFAULT_T_COST,
+        p_cost: Params::DEFAULT_P_COST,
+        salt: hex::encode(salt),
+    };
+    let key = derive_key(passphrase, &params)?;
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), secret.as_slice())
+        .map_err(|_| anyhow!("Failed to encrypt secret key"))?;
+
+    Ok(EncryptedSecret {
+        kdf: KDF_ALG.to_string(),
+        kdf_params: params,
+        cipher: CIPHER_ALG.to_string(),
+        nonce: hex::encode(nonce),
+        ciphertext: hex::encode(ciphertext),
+    })
+}
+
+fn open(crypto: &EncryptedSecret, passphrase: &str) -> Result<[u8; 32]> {
+    if crypto.kdf != KDF_ALG || crypto.cipher != CIPHER_ALG {
+        bail!("Unsupported keystore algorithms {}/{}", crypto.kdf, crypto.cipher);
+    }
+    let key = derive_key(passphrase, &crypto.kdf_params)?;
+    let nonce = hex::decode(&crypto.nonce).context("Invalid nonce")?;
+    if nonce.len() != 12 {
+        bail!("Invalid nonce length");
+    }
+    let ciphertext = hex::decode(&crypto.ciphertext).context("Invalid ciphertext")?;
+
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+        .map_err(|_| anyhow!("Wrong passphrase or corrupted keystore file"))?;
+
+    plaintext
+        .try_into()
+        .map_err(|_| anyhow!("Invalid secret key length"))
+}
+
+/// Creates `dir` (and parents) readable only by the current user.
+pub fn create_private_dir(dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+    }
+    Ok(())
+}
+
+/// Writes `contents` to `path` with `0600` permissions on unix.
+pub fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::io::Write;
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        file.write_all(contents)?;
+        // `mode` only applies on creation; tighten pre-existing files too
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+    #[cfg(not(unix))]
+    fs::write(path, contents).with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn keystore() -> (tempfile::TempDir, Keystore) {
+        let dir = tempfile::tempdir().unwrap();
+        let keystore = Keystore::open(dir.path().join("keystore"));
+        (dir, keystore)
+    }
+
+    #[test]
+    fn seal_open_round_trip() {
+        let secret = [7u8; 32];
+        let sealed = seal(&secret, "correct horse").unwrap();
+        assert_eq!(open(&sealed, "correct horse").unwrap(), secret);
+        assert!(open(&sealed, "wrong horse").unwrap_err().to_string().contains("Wrong passphrase"));
+    }
+
+    #[test]
+    fn parse_secret_accepts_exported_multibase() {
+        let secret = [9u8; 32];
+        let exported = multibase::encode(multibase::Base::Base58Btc, [ED25519_PRIV_CODEC.as_slice(), &secret].concat());
+        assert_eq!(parse_secret(&exported).unwrap(), secret);
+        assert_eq!(parse_secret(&multibase::encode(multibase::Base::Base58Btc, secret)).unwrap(), secret);
+        assert_eq!(parse_secret(&hex::encode(secret)).unwrap(), secret);
+    }
+
+    #[test]
+    fn save_and_load_identity() {
+        let (_dir, keystore) = keystore();
+        let did_key = DidKey::generate();
+        keystore.save("agent", &did_key, "pw").unwrap();
+
+        let loaded = keystore.load("agent", "pw").unwrap();
+        assert_eq!(loaded.did(), did_key.did());
+        assert!(keystore.load("agent", "other").is_err());
+        assert!(keystore.save("agent", &did_key, "pw").is_err());
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let (_dir, keystore) = keystore();
+        keystore.save("agent", &DidKey::generate(), "pw").unwrap();
+        let path = keystore.path_for("agent");
+        let mut entry: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        entry["version"] = json!(KEYSTORE_VERSION + 1);
+        fs::write(&path, entry.to_string()).unwrap();
+
+        assert!(keystore.entry("agent").unwrap_err().to_string().contains("Unsupported keystore version"));
+    }
+
+    #[test]
+    fn aliases_cannot_leave_the_keystore() {
+        let (dir, keystore) = keystore();
+        fs::write(dir.path().join("outside.json"), "{}").unwrap();
+        for alias in ["../outside", "a/b", ".hidden", ""] {
+            assert!(keystore.entry(alias).is_err(), "{}", alias);
+            assert!(keystore.delete(alias).is_err(), "{}", alias);
+            assert!(!keystore.exists(alias), "{}", alias);
+        }
+        assert!(dir.path().join("outside.json").exists());
+    }
+
+    #[test]
+    fn list_skips_unreadable_files() {
+        let (_dir, keystore) = keystore();
+        keystore.save("good", &DidKey::generate(), "pw").unwrap();
+        fs::write(keystore.path_for("corrupt"), "not json").unwrap();
+
+        let aliases: Vec<String> = keystore.list().unwrap().into_iter().map(|e| e.alias).collect();
+        assert_eq!(aliases, ["good"]);
+    }
+}
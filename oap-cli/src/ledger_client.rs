@@ -0,0 +1,88 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// The localnet ledger counts in micro-units: 1 OAP = 1_000_000.
+pub const UNITS_PER_OAP: f64 = 1_000_000.0;
+
+/// Client for the OAPP ledger HTTP API (as implemented by the localnet mock ledger).
+pub struct LedgerClient {
+    base: String,
+    client: reqwest::Client,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Balance {
+    pub did: String,
+    pub balance: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FaucetReceipt {
+    pub did: String,
+    pub new_balance: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TransferRequest {
+    pub sender_did: String,
+    pub recipient_did: String,
+    pub amount: u64,
+    pub signature: String,
+}
+
+/// A confirmed transfer as returned by `POST /transfer`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transaction {
+    pub id: String,
+    pub sender: String,
+    pub recipient: String,
+    pub amount: u64,
+    pub status: String,
+}
+
+impl LedgerClient {
+    pub fn new(base: &str, client: reqwest::Client) -> Self {
+        Self { base: base.trim_end_matches('/').to_string(), client }
+    }
+
+    pub fn base(&self) -> &str {
+        &self.base
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base, path)
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let res = self.client.get(self.url(path)).send().await
+            .with_context(|| format!("Failed to reach ledger at {}", self.base))?;
+        let status = res.status();
+        if !status.is_success() {
+            let body = res.text().await.unwrap_or_default();
+            bail!("Ledger returned {} for {}: {}", status, path, body);
+        }
+        res.json().await.with_context(|| format!("Unexpected {} response from ledger", path))
+    }
+
+    /// `GET /faucet/{did}`.
+    pub async fn faucet(&self, did: &str) -> Result<FaucetReceipt> {
+        self.get(&format!("/faucet/{}", did)).await
+    }
+
+    /// `GET /balance/{did}`.
+    pub async fn balance(&self, did: &str) -> Result<Balance> {
+        self.get(&format!("/balance/{}", did)).await
+    }
+
+    /// `POST /transfer`.
+    pub async fn transfer(&self, request: &TransferRequest) -> Result<Transaction> {
+        let res = self.client.post(self.url("/transfer")).json(request).send().await
+            .with_context(|| format!("Failed to reach ledger at {}", self.base))?;
+        let status = res.status();
+        if !status.is_success() {
+            let body = res.text().await.unwrap_or_default();
+            bail!("Ledger rejected transfer ({}): {}", status, body);
+        }
+        res.json().await.context("Unexpected /transfer response from ledger")
+    }
+}
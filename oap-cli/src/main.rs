@@ -1,9 +1,21 @@
-use clap::{Parser, Subcommand};
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand};
 use std::path::PathBuf;
-use colored::*;
 
 mod commands;
 mod config;
+mod discovery_client;
+mod handshake;
+mod jwe;
+mod keystore;
+mod ledger_client;
+mod oacp;
+mod output;
+mod relay_client;
+mod resolver;
+mod responder;
+mod scenario;
+mod schema;
+mod session;
 
 #[derive(Parser)]
 #[command(name = "oap")]
@@ -15,6 +27,10 @@ struct Cli {
     #[arg(short, long, global = true)]
     config: Option<PathBuf>,
 
+    /// Config profile to use (e.g. localnet, staging)
+    #[arg(long, global = true)]
+    profile: Option<String>,
+
     /// Output in JSON format
     #[arg(long, global = true)]
     json: bool,
@@ -31,6 +47,11 @@ enum Commands {
         #[command(subcommand)]
         command: DidCommands,
     },
+    /// Keystore Identity Management
+    Identity {
+        #[command(subcommand)]
+        command: IdentityCommands,
+    },
     /// Relay Operations
     Relay {
         #[command(subcommand)]
@@ -48,20 +69,124 @@ enum Commands {
         /// Identity alias to use
         #[arg(long)]
         identity: Option<String>,
+        /// Resolve did:web over plain HTTP (e.g. a local `oap listen --port` agent)
+        #[arg(long)]
+        insecure: bool,
+    },
+    /// Build and verify OAEP handshake messages offline
+    Handshake {
+        #[command(subcommand)]
+        command: HandshakeCommands,
     },
     /// Listen for incoming connections
     Listen {
-        /// Port or Relay URL to listen on (optional)
+        /// Serve an embedded responder agent on this port instead of polling the relay
         #[arg(short, long)]
         port: Option<u16>,
+        /// Identity alias to listen as
+        #[arg(long)]
+        identity: Option<String>,
+        /// Relay URL to poll (defaults to config)
+        #[arg(long)]
+        relay: Option<String>,
+        /// Poll once and exit
+        #[arg(long, conflicts_with = "port")]
+        once: bool,
+        /// Stop after this many seconds with a timeout error (unless --count is reached first)
+        #[arg(long, conflicts_with = "port")]
+        timeout: Option<u64>,
+        /// Exit after receiving N messages
+        #[arg(long, conflicts_with = "port")]
+        count: Option<usize>,
+        /// Poll interval in milliseconds
+        #[arg(long, default_value_t = 2000)]
+        interval: u64,
+        /// Host name for the did:web and endpoint when serving with --port
+        #[arg(long, default_value = "localhost")]
+        host: String,
+        /// Resolve initiators' did:web over plain HTTP when serving with --port
+        #[arg(long, requires = "port")]
+        insecure: bool,
     },
     /// Send a message
     Send {
-        /// Message content
-        message: String,
+        /// Message content (- for stdin)
+        message: Option<String>,
+        /// Read the message body from a file (- for stdin)
+        #[arg(short, long)]
+        file: Option<PathBuf>,
         /// Recipient DID
         #[arg(long)]
         recipient: String,
+        /// Relay URL (defaults to config)
+        #[arg(long)]
+        relay: Option<String>,
+    },
+    /// Manage established sessions
+    Session {
+        #[command(subcommand)]
+        command: SessionCommands,
+    },
+    /// Build OACP negotiation objects and run scripted negotiations
+    Oacp {
+        #[command(subcommand)]
+        command: OacpCommands,
+    },
+    /// Find contacts on OAP via the discovery service (private set intersection)
+    Discover {
+        /// Discovery URL (defaults to config)
+        #[arg(long, global = true)]
+        discovery: Option<String>,
+        /// Send normalized identifiers instead of SHA-256 hashes
+        #[arg(long, global = true)]
+        plain: bool,
+        #[command(subcommand)]
+        command: DiscoverCommands,
+    },
+    /// OAPP payments against the ledger
+    Pay {
+        /// Ledger URL (defaults to config)
+        #[arg(long, global = true)]
+        ledger: Option<String>,
+        #[command(subcommand)]
+        command: PayCommands,
+    },
+    /// Validate OACP/OAPP payloads against their JSON Schemas
+    Validate {
+        /// Payload JSON file, `msg decrypt` output, or JSONL (- for stdin)
+        file: PathBuf,
+        /// Schema to use instead of detecting it from @context
+        #[arg(long, value_enum)]
+        schema: Option<schema::Protocol>,
+    },
+    /// Run test scenarios (TOML, YAML or JSON) and report them as JUnit XML
+    Run {
+        /// Scenario files; each one is a test suite
+        #[arg(required = true)]
+        scenarios: Vec<PathBuf>,
+        /// Write a JUnit XML report to this file (- for stdout)
+        #[arg(long)]
+        junit: Option<PathBuf>,
+        /// Relay URL (overrides the scenario and config)
+        #[arg(long)]
+        relay: Option<String>,
+        /// Ledger URL (overrides the scenario and config)
+        #[arg(long)]
+        ledger: Option<String>,
+    },
+    /// Interactive shell that keeps the identity, sessions and relay in memory
+    Shell {
+        /// Identity alias to start with (defaults to config, else an ephemeral identity)
+        #[arg(long)]
+        identity: Option<String>,
+        /// Inbox poll interval in milliseconds
+        #[arg(long, default_value_t = 2000)]
+        interval: u64,
+    },
+    /// Read and write the config file
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
     },
     /// Generate shell completions
     Completions {
@@ -80,12 +205,332 @@ enum DidCommands {
         /// Save identity with alias
         #[arg(long)]
         alias: Option<String>,
+        /// Also print the secret key when saving to the keystore
+        #[arg(long, requires = "alias")]
+        show_secret: bool,
     },
-    /// Resolve a DID
+    /// Resolve a DID (did:key, did:web)
     Resolve {
         /// The DID to resolve
         did: String,
+        /// Fetch did:web documents over plain HTTP (localnet)
+        #[arg(long)]
+        insecure: bool,
+        /// Bypass the on-disk resolution cache
+        #[arg(long)]
+        no_cache: bool,
+        /// Resolve only from a directory of DID documents (offline)
+        #[arg(long)]
+        fixtures: Option<PathBuf>,
+    },
+    /// Remove all cached DID documents
+    ClearCache,
+}
+
+#[derive(Subcommand)]
+enum IdentityCommands {
+    /// List stored identities
+    List,
+    /// Show details of a stored identity
+    Show {
+        /// Identity alias
+        alias: String,
+    },
+    /// Export an identity's keys
+    Export {
+        /// Identity alias
+        alias: String,
+        /// Export format (jwk or multibase)
+        #[arg(short, long, default_value = "jwk")]
+        format: String,
+        /// Include the secret key (requires the passphrase)
+        #[arg(long)]
+        private: bool,
+    },
+    /// Import an identity from a secret key
+    Import {
+        /// Alias to store the identity under
+        alias: String,
+        /// Secret key as hex, multibase or key file path (prompted if omitted)
+        #[arg(long)]
+        secret: Option<String>,
+    },
+    /// Delete a stored identity
+    Delete {
+        /// Identity alias
+        alias: String,
+        /// Skip the confirmation prompt
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Rename a stored identity
+    Rename {
+        /// Current alias
+        from: String,
+        /// New alias
+        to: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum HandshakeCommands {
+    /// Emit a signed ConnectionRequest
+    BuildRequest {
+        /// Peer DID the request is addressed to
+        peer: String,
+        /// Identity alias to sign with (defaults to config, else ephemeral)
+        #[arg(long)]
+        identity: Option<String>,
+        /// Write the ephemeral X25519 secret (hex) here, for `session derive`
+        #[arg(long)]
+        ephemeral_out: Option<PathBuf>,
+    },
+    /// Produce a ConnectionResponse for a request
+    Respond {
+        /// ConnectionRequest JSON file (- for stdin)
+        request: PathBuf,
+        /// Identity alias to sign with (defaults to config, else ephemeral)
+        #[arg(long)]
+        identity: Option<String>,
+        /// Responder DID (defaults to the request's `to`)
+        #[arg(long)]
+        did: Option<String>,
+        /// Write the ephemeral X25519 secret (hex) here, for `session derive`
+        #[arg(long)]
+        ephemeral_out: Option<PathBuf>,
+        /// Answer even if the request proof does not verify
+        #[arg(long)]
+        no_verify: bool,
+        /// Resolve did:web over plain HTTP
+        #[arg(long)]
+        insecure: bool,
+    },
+    /// Check a request's proof, or a response's proof and transcript hash
+    Verify {
+        /// ConnectionRequest or ConnectionResponse JSON file (- for stdin)
+        message: PathBuf,
+        /// The ConnectionRequest a response answers
+        #[arg(long)]
+        request: Option<PathBuf>,
+        /// Resolve did:web over plain HTTP
+        #[arg(long)]
+        insecure: bool,
+    },
+}
+
+#[derive(Args)]
+struct TermsArgs {
+    /// Price amount
+    #[arg(long)]
+    amount: Option<f64>,
+    /// Price currency (e.g. EUR)
+    #[arg(long)]
+    currency: Option<String>,
+    /// JSON file with extra `details` members
+    #[arg(long)]
+    details: Option<PathBuf>,
+    /// Expire the object after this many seconds
+    #[arg(long)]
+    expires_in: Option<i64>,
+}
+
+impl TermsArgs {
+    fn into_terms(self) -> (oacp::Terms, Option<PathBuf>) {
+        let terms = oacp::Terms {
+            amount: self.amount,
+            currency: self.currency,
+            expires_in: self.expires_in,
+            ..Default::default()
+        };
+        (terms, self.details)
+    }
+}
+
+#[derive(Subcommand)]
+enum OacpCommands {
+    /// Create an Offer (starts a thread)
+    Offer {
+        /// Provider DID
+        #[arg(long)]
+        provider: Option<String>,
+        /// Consumer DID
+        #[arg(long)]
+        consumer: Option<String>,
+        /// JSON object to start from
+        #[arg(long)]
+        template: Option<PathBuf>,
+        #[command(flatten)]
+        terms: TermsArgs,
+    },
+    /// Create a CounterProposal to an Offer or CounterProposal
+    Counter {
+        /// Object being answered (- for stdin)
+        previous: PathBuf,
+        #[command(flatten)]
+        terms: TermsArgs,
+    },
+    /// Accept an Offer or CounterProposal
+    Accept {
+        /// Object being answered (- for stdin)
+        previous: PathBuf,
+    },
+    /// Reject an Offer or CounterProposal
+    Reject {
+        /// Object being answered (- for stdin)
+        previous: PathBuf,
+        /// Reason given to the peer
+        #[arg(long)]
+        reason: Option<String>,
+    },
+    /// Create the Agreement for an Acceptance
+    Agree {
+        /// Acceptance (- for stdin)
+        previous: PathBuf,
+    },
+    /// Run a scripted negotiation (TOML or JSON) against a peer
+    Play {
+        /// Script file
+        script: PathBuf,
+        /// Relay URL (defaults to config)
+        #[arg(long)]
+        relay: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum DiscoverCommands {
+    /// Look up which contacts have a DID
+    Lookup {
+        /// Phone numbers or emails
+        identifiers: Vec<String>,
+        /// Contact list: one identifier per line or a JSON array (- for stdin)
+        #[arg(long)]
+        contacts: Option<PathBuf>,
+    },
+    /// Admin: link an identifier to a DID
+    Add {
+        identifier: String,
+        did: String,
+    },
+    /// Admin: link every `<identifier> <did>` line (or JSON object entry) of a file
+    Seed {
+        /// Seed file (- for stdin)
+        file: PathBuf,
+    },
+    /// Admin: list the registered (hashed) identifiers
+    List,
+}
+
+#[derive(Subcommand)]
+enum PayCommands {
+    /// Top up a DID from the ledger faucet
+    Fund {
+        /// DID to fund (defaults to --identity or the default identity)
+        did: Option<String>,
+        /// Keystore alias whose DID to fund
+        #[arg(long)]
+        identity: Option<String>,
+    },
+    /// Show the ledger balance of a DID
+    Balance {
+        /// DID to look up (defaults to --identity or the default identity)
+        did: Option<String>,
+        /// Keystore alias whose DID to look up
+        #[arg(long)]
+        identity: Option<String>,
+    },
+    /// Create a PaymentRequest
+    Request {
+        /// Amount in whole units (e.g. 2.5)
+        #[arg(long)]
+        amount: f64,
+        /// Currency code
+        #[arg(long, default_value = "OAP")]
+        currency: String,
+        /// DID to be paid (defaults to --identity or the default identity)
+        #[arg(long)]
+        recipient: Option<String>,
+        /// Keystore alias to be paid
+        #[arg(long, conflicts_with = "recipient")]
+        identity: Option<String>,
+        /// OACP Agreement this payment is for (- for stdin)
+        #[arg(long)]
+        agreement: Option<PathBuf>,
+    },
+    /// Pay a PaymentRequest via the ledger and print the PaymentProof
+    Settle {
+        /// PaymentRequest JSON (- for stdin)
+        request: PathBuf,
+        /// Keystore alias of the payer
+        #[arg(long)]
+        identity: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum SessionCommands {
+    /// List stored sessions
+    List,
+    /// Show a session
+    Show {
+        /// Peer DID
+        peer: String,
+        /// Also print the session keys
+        #[arg(long)]
+        reveal: bool,
+    },
+    /// Delete a session
+    Drop {
+        /// Peer DID
+        peer: Option<String>,
+        /// Delete all sessions
+        #[arg(long)]
+        all: bool,
+    },
+    /// Derive the session keys of a captured handshake
+    Derive {
+        /// ConnectionRequest JSON file
+        #[arg(long)]
+        request: PathBuf,
+        /// ConnectionResponse JSON file
+        #[arg(long)]
+        response: PathBuf,
+        /// Ephemeral X25519 secret of either side (hex, multibase, or key file)
+        #[arg(long)]
+        secret: String,
+        /// Side the secret belongs to (detected from the ephemeral keys by default)
+        #[arg(long, value_enum)]
+        role: Option<session::Role>,
+    },
+    /// Override the sequence counters of a session
+    Seq {
+        /// Peer DID
+        peer: String,
+        /// Next outgoing sequence number
+        #[arg(long)]
+        tx: Option<u64>,
+        /// Last received sequence number
+        #[arg(long)]
+        rx: Option<u64>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// Print a config value (from the selected profile's section, if any)
+    Get {
+        /// Key name (default_relay, default_identity, timeout_secs, output, active_profile)
+        key: String,
+    },
+    /// Set a config value (in the selected profile's section, if any)
+    Set {
+        /// Key name
+        key: String,
+        /// New value
+        value: String,
     },
+    /// Show the effective configuration
+    List,
 }
 
 #[derive(Subcommand)]
@@ -95,72 +540,370 @@ enum RelayCommands {
         /// Relay URL (defaults to config)
         url: Option<String>,
     },
+    /// Probe health, endpoints, protocol version and TLS certificate
+    Info {
+        /// Relay URL (defaults to config)
+        url: Option<String>,
+        /// CA or certificate (PEM/DER) the relay should present, e.g. the localnet nginx cert
+        #[arg(long)]
+        ca: Option<PathBuf>,
+    },
+    /// Measure latency and error rate over repeated requests
+    Bench {
+        /// Relay URL (defaults to config)
+        url: Option<String>,
+        /// What each request does
+        #[arg(long, value_enum, default_value = "ping")]
+        mode: commands::relay::BenchMode,
+        /// Number of requests
+        #[arg(short = 'n', long, default_value = "50")]
+        count: usize,
+        /// Pause between requests in milliseconds
+        #[arg(long, default_value = "0")]
+        interval: u64,
+    },
+    /// Show the relay's recent log lines (localnet)
+    Logs {
+        /// Relay URL (defaults to config)
+        url: Option<String>,
+        /// Keep printing new lines until Ctrl-C
+        #[arg(short, long)]
+        follow: bool,
+        /// Poll interval for --follow in milliseconds
+        #[arg(long, default_value = "1000")]
+        interval: u64,
+    },
+    /// List messages stored on the relay (localnet)
+    Messages {
+        /// Relay URL (defaults to config)
+        url: Option<String>,
+        /// Only messages for this DID
+        #[arg(long)]
+        recipient: Option<String>,
+    },
+    /// Delete all messages stored on the relay (localnet)
+    Clear {
+        /// Relay URL (defaults to config)
+        url: Option<String>,
+        /// Skip the confirmation prompt
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Show or change the relay's fault injection; omitted faults keep their setting (localnet)
+    Chaos {
+        /// Relay URL (defaults to config)
+        url: Option<String>,
+        /// Share of requests answered with a 500 (0.0 - 1.0)
+        #[arg(long)]
+        failure_rate: Option<f64>,
+        /// Delay added to every request
+        #[arg(long)]
+        latency_ms: Option<u64>,
+        /// Corrupt the last byte of stored messages (`--corrupt false` to stop)
+        #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+        corrupt: Option<bool>,
+        /// Switch off every fault not given on the command line
+        #[arg(long)]
+        reset: bool,
+    },
 }
 
 #[derive(Subcommand)]
 enum MsgCommands {
-    /// Decode JWE Headers (no decryption)
+    /// Inspect JWE structure and headers (no decryption)
     Decode {
-        /// Base64 encoded JWE string
+        /// Compact or JSON serialized JWE (use - for stdin)
         jwe: String,
     },
+    /// Encrypt a payload into a JWE
+    Encrypt {
+        /// Plaintext (use - for stdin)
+        message: Option<String>,
+        /// Read the plaintext from a file (- for stdin)
+        #[arg(short, long)]
+        file: Option<PathBuf>,
+        /// Recipient X25519 public key (did:key, hex or multibase)
+        #[arg(long)]
+        to: Option<String>,
+        /// Use the stored session with this peer DID
+        #[arg(long)]
+        session: Option<String>,
+        /// Raw 32-byte content key (hex), e.g. from `oap session show --reveal`
+        #[arg(long)]
+        session_key: Option<String>,
+        /// Key ID for the protected header (defaults to the session's)
+        #[arg(long)]
+        kid: Option<String>,
+        /// Sequence number (defaults to the session's next; the counter is not advanced when set)
+        #[arg(long)]
+        seq: Option<u64>,
+        /// Padding block size in bytes
+        #[arg(long)]
+        padding: Option<usize>,
+        /// Output serialization: compact or json
+        #[arg(long, default_value = "compact")]
+        format: String,
+    },
     /// Decrypt JWE Payload
     Decrypt {
-        /// Base64 encoded JWE string
-        jwe: String,
-        /// Path to Secret Key file (or hex string)
+        /// Compact or JSON serialized JWE (use - for stdin)
+        #[arg(required_unless_present = "input", conflicts_with = "input")]
+        jwe: Option<String>,
+        /// Decrypt a dump: newline-delimited JWEs or the relay's /messages JSON (- for stdin).
+        /// Prints one JSON line per message.
         #[arg(short, long)]
-        key: String,
+        input: Option<PathBuf>,
+        /// Path to Secret Key file, keystore alias, or hex string.
+        /// Without it, stored sessions (by kid) and keystore identities are tried.
+        #[arg(short, long)]
+        key: Option<String>,
     },
 }
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let cli = Cli::parse();
+async fn main() {
+    let matches = match Cli::command().try_get_matches() {
+        Ok(matches) => matches,
+        // Argument errors get an envelope too, so `--json` callers always get JSON back
+        Err(e) if e.use_stderr() && std::env::args().any(|a| a == "--json") => {
+            let message = e.render().to_string();
+            let message = message.lines().next().unwrap_or_default().trim_start_matches("error: ");
+            std::process::exit(output::report(true, &output::error(output::ErrorKind::Usage, message)));
+        }
+        Err(e) => e.exit(),
+    };
+    output::set_command(command_path(&matches));
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    // `config` commands must work even when the file doesn't parse
+    let cfg = match &cli.command {
+        Commands::Config { .. } => Ok(config::Config::default()),
+        _ => config::load(cli.config.clone(), cli.profile.clone()).await,
+    };
+    let cfg = match cfg {
+        Ok(cfg) => cfg,
+        Err(e) => std::process::exit(output::report(cli.json, &e)),
+    };
 
     // Pass global flags to context or commands
     let ctx = commands::Context {
-        json: cli.json,
+        json: cli.json || cfg.output == "json",
         verbose: cli.verbose,
         config: cli.config,
+        profile: cli.profile,
     };
 
-    match cli.command {
+    let json = ctx.json;
+    if let Err(e) = run(cli.command, cfg, ctx).await {
+        std::process::exit(output::report(json, &e));
+    }
+}
+
+/// Subcommand names down to the leaf, e.g. `relay bench`.
+fn command_path(matches: &clap::ArgMatches) -> String {
+    let mut names = Vec::new();
+    let mut current = matches;
+    while let Some((name, sub)) = current.subcommand() {
+        names.push(name);
+        current = sub;
+    }
+    names.join(" ")
+}
+
+async fn run(command: Commands, cfg: config::Config, ctx: commands::Context) -> anyhow::Result<()> {
+    match command {
         Commands::Did { command } => match command {
-            DidCommands::Gen { format, alias } => {
-                // Override format if json flag is set
-                let fmt = if ctx.json { "json".to_string() } else { format };
-                commands::did::generate(fmt, alias).await?;
+            DidCommands::Gen { format, alias, show_secret } => {
+                commands::did::generate(format, alias, show_secret, &ctx).await?;
+            }
+            DidCommands::Resolve { did, insecure, no_cache, fixtures } => {
+                commands::did::resolve(did, insecure, no_cache, fixtures, &ctx).await?;
+            }
+            DidCommands::ClearCache => {
+                commands::did::clear_cache(&ctx).await?;
+            }
+        },
+        Commands::Identity { command } => match command {
+            IdentityCommands::List => {
+                commands::identity::list(&ctx).await?;
             }
-            DidCommands::Resolve { did } => {
-                commands::did::resolve(did, &ctx).await?;
+            IdentityCommands::Show { alias } => {
+                commands::identity::show(alias, &ctx).await?;
+            }
+            IdentityCommands::Export { alias, format, private } => {
+                commands::identity::export(alias, format, private, &ctx).await?;
+            }
+            IdentityCommands::Import { alias, secret } => {
+                commands::identity::import(alias, secret, &ctx).await?;
+            }
+            IdentityCommands::Delete { alias, yes } => {
+                commands::identity::delete(alias, yes, &ctx).await?;
+            }
+            IdentityCommands::Rename { from, to } => {
+                commands::identity::rename(from, to, &ctx).await?;
             }
         },
         Commands::Relay { command } => match command {
             RelayCommands::Ping { url } => {
-                let url = url.unwrap_or_else(|| "http://localhost:3000".to_string());
+                let url = url.unwrap_or_else(|| cfg.default_relay.clone());
                 commands::relay::ping(url, &ctx).await?;
             }
+            RelayCommands::Info { url, ca } => {
+                let url = url.unwrap_or_else(|| cfg.default_relay.clone());
+                commands::relay::info(url, ca, &ctx).await?;
+            }
+            RelayCommands::Bench { url, mode, count, interval } => {
+                let url = url.unwrap_or_else(|| cfg.default_relay.clone());
+                commands::relay::bench(url, mode, count, interval, &ctx).await?;
+            }
+            RelayCommands::Logs { url, follow, interval } => {
+                let url = url.unwrap_or_else(|| cfg.default_relay.clone());
+                commands::relay::logs(url, follow, interval, &ctx).await?;
+            }
+            RelayCommands::Messages { url, recipient } => {
+                let url = url.unwrap_or_else(|| cfg.default_relay.clone());
+                commands::relay::messages(url, recipient, &ctx).await?;
+            }
+            RelayCommands::Clear { url, yes } => {
+                let url = url.unwrap_or_else(|| cfg.default_relay.clone());
+                commands::relay::clear(url, yes, &ctx).await?;
+            }
+            RelayCommands::Chaos { url, failure_rate, latency_ms, corrupt, reset } => {
+                let url = url.unwrap_or_else(|| cfg.default_relay.clone());
+                let changes = commands::relay::ChaosChanges { failure_rate, latency_ms, corrupt, reset };
+                commands::relay::chaos(url, changes, &ctx).await?;
+            }
         },
         Commands::Msg { command } => match command {
             MsgCommands::Decode { jwe } => {
                 commands::msg::decode(jwe, &ctx).await?;
             }
-            MsgCommands::Decrypt { jwe, key } => {
-                commands::msg::decrypt(jwe, key, &ctx).await?;
+            MsgCommands::Encrypt { message, file, to, session, session_key, kid, seq, padding, format } => {
+                let opts = commands::msg::EncryptOptions { to, session, session_key, kid, seq, padding, format };
+                commands::msg::encrypt(message, file, opts, &ctx).await?;
             }
+            MsgCommands::Decrypt { jwe, input, key } => match (jwe, input) {
+                (_, Some(input)) => commands::msg::decrypt_batch(input, key, &ctx).await?,
+                (Some(jwe), None) => commands::msg::decrypt(jwe, key, &ctx).await?,
+                (None, None) => unreachable!("clap requires a JWE or --input"),
+            },
         },
-        Commands::Connect { did, identity } => {
-            commands::connect::run(did, identity, &ctx).await?;
+        Commands::Connect { did, identity, insecure } => {
+            commands::connect::run(did, identity, insecure, &ctx).await?;
         }
-        Commands::Listen { port } => {
-            commands::listen::run(port, &ctx).await?;
+        Commands::Listen { port, identity, relay, once, timeout, count, interval, host, insecure } => {
+            let opts = commands::listen::ListenOptions { relay, once, timeout, count, interval_ms: interval, host, insecure };
+            commands::listen::run(port, identity, opts, &ctx).await?;
         }
-        Commands::Send { message, recipient } => {
-            commands::send::run(message, recipient, &ctx).await?;
+        Commands::Send { message, file, recipient, relay } => {
+            commands::send::run(message, file, recipient, relay, &ctx).await?;
         }
+        Commands::Handshake { command } => match command {
+            HandshakeCommands::BuildRequest { peer, identity, ephemeral_out } => {
+                commands::handshake::build_request(peer, identity, ephemeral_out, &ctx).await?;
+            }
+            HandshakeCommands::Respond { request, identity, did, ephemeral_out, no_verify, insecure } => {
+                commands::handshake::respond(request, identity, did, ephemeral_out, no_verify, insecure, &ctx).await?;
+            }
+            HandshakeCommands::Verify { message, request, insecure } => {
+                commands::handshake::verify(message, request, insecure, &ctx).await?;
+            }
+        },
+        Commands::Session { command } => match command {
+            SessionCommands::List => {
+                commands::session::list(&ctx).await?;
+            }
+            SessionCommands::Show { peer, reveal } => {
+                commands::session::show(peer, reveal, &ctx).await?;
+            }
+            SessionCommands::Drop { peer, all } => {
+                commands::session::drop(peer, all, &ctx).await?;
+            }
+            SessionCommands::Derive { request, response, secret, role } => {
+                commands::session::derive(request, response, secret, role, &ctx).await?;
+            }
+            SessionCommands::Seq { peer, tx, rx } => {
+                commands::session::set_seq(peer, tx, rx, &ctx).await?;
+            }
+        },
+        Commands::Oacp { command } => match command {
+            OacpCommands::Offer { provider, consumer, template, terms } => {
+                let (mut terms, details) = terms.into_terms();
+                terms.provider = provider;
+                terms.consumer = consumer;
+                commands::oacp::create("Offer", None, template, terms, details, &ctx).await?;
+            }
+            OacpCommands::Counter { previous, terms } => {
+                let (terms, details) = terms.into_terms();
+                commands::oacp::create("CounterProposal", Some(previous), None, terms, details, &ctx).await?;
+            }
+            OacpCommands::Accept { previous } => {
+                commands::oacp::create("Acceptance", Some(previous), None, oacp::Terms::default(), None, &ctx).await?;
+            }
+            OacpCommands::Reject { previous, reason } => {
+                let terms = oacp::Terms { reason, ..Default::default() };
+                commands::oacp::create("Reject", Some(previous), None, terms, None, &ctx).await?;
+            }
+            OacpCommands::Agree { previous } => {
+                commands::oacp::create("Agreement", Some(previous), None, oacp::Terms::default(), None, &ctx).await?;
+            }
+            OacpCommands::Play { script, relay } => {
+                commands::oacp::play(script, relay, &ctx).await?;
+            }
+        },
+        Commands::Discover { discovery, plain, command } => {
+            let opts = commands::discover::DiscoverOptions { discovery, plain };
+            match command {
+                DiscoverCommands::Lookup { identifiers, contacts } => {
+                    commands::discover::lookup(identifiers, contacts, opts, &ctx).await?;
+                }
+                DiscoverCommands::Add { identifier, did } => {
+                    commands::discover::add(identifier, did, opts, &ctx).await?;
+                }
+                DiscoverCommands::Seed { file } => {
+                    commands::discover::seed(file, opts, &ctx).await?;
+                }
+                DiscoverCommands::List => {
+                    commands::discover::list(opts, &ctx).await?;
+                }
+            }
+        }
+        Commands::Pay { ledger, command } => match command {
+            PayCommands::Fund { did, identity } => {
+                commands::pay::fund(did, identity, ledger, &ctx).await?;
+            }
+            PayCommands::Balance { did, identity } => {
+                commands::pay::balance(did, identity, ledger, &ctx).await?;
+            }
+            PayCommands::Request { amount, currency, recipient, identity, agreement } => {
+                commands::pay::request(amount, currency, recipient, identity, agreement, &ctx).await?;
+            }
+            PayCommands::Settle { request, identity } => {
+                commands::pay::settle(request, identity, ledger, &ctx).await?;
+            }
+        },
+        Commands::Validate { file, schema } => {
+            commands::validate::run(file, schema, &ctx).await?;
+        }
+        Commands::Run { scenarios, junit, relay, ledger } => {
+            let opts = commands::run::RunOptions { junit, relay, ledger };
+            commands::run::run(scenarios, opts, &ctx).await?;
+        }
+        Commands::Shell { identity, interval } => {
+            commands::shell::run(identity, interval, cfg, &ctx).await?;
+        }
+        Commands::Config { command } => match command {
+            ConfigCommands::Get { key } => {
+                commands::config::get(key, &ctx).await?;
+            }
+            ConfigCommands::Set { key, value } => {
+                commands::config::set(key, value, &ctx).await?;
+            }
+            ConfigCommands::List => {
+                commands::config::list(&ctx).await?;
+            }
+        },
         Commands::Completions { shell } => {
-            use clap::CommandFactory;
             let mut cmd = Cli::command();
             let bin_name = cmd.get_name().to_string();
             clap_complete::generate(shell, &mut cmd, bin_name, &mut std::io::stdout());
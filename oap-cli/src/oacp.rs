@@ -0,0 +1,115 @@
+use anyhow::{anyhow, bail, Result};
+use serde_json::{json, Map, Value};
+use crate::schema::{self, Protocol, OACP_CONTEXT};
+
+/// Fields of an OACP object that can be set from flags, templates or play scripts.
+#[derive(Debug, Default, Clone, serde::Deserialize)]
+pub struct Terms {
+    pub provider: Option<String>,
+    pub consumer: Option<String>,
+    pub amount: Option<f64>,
+    pub currency: Option<String>,
+    /// Extra `details` members, merged over the inherited ones
+    pub details: Option<Value>,
+    pub expires_in: Option<i64>,
+    /// Reject only
+    pub reason: Option<String>,
+}
+
+/// Object types a reply of `kind` may follow.
+fn allowed_previous(kind: &str) -> Result<&'static [&'static str]> {
+    Ok(match kind {
+        "Offer" => &[],
+        "CounterProposal" | "Acceptance" | "Reject" => &["Offer", "CounterProposal"],
+        "Agreement" => &["Acceptance"],
+        other => bail!("Unknown OACP type '{}'", other),
+    })
+}
+
+/// Builds an OACP object of `kind`. Replies inherit parties and details from `previous`
+/// and are linked to it by `previous` (its id) and `thread` (the id of the opening Offer).
+/// The result is validated against the OACP schema.
+pub fn build(kind: &str, previous: Option<&Value>, template: Option<Value>, terms: Terms) -> Result<Value> {
+    let allowed = allowed_previous(kind)?;
+    match previous {
+        None if !allowed.is_empty() => bail!("{} needs the object it replies to", kind),
+        Some(_) if allowed.is_empty() => bail!("{} starts a thread and cannot reply to another object", kind),
+        Some(prev) => {
+            let prev_type = prev.get("type").and_then(|v| v.as_str()).unwrap_or("unknown");
+            if !allowed.contains(&prev_type) {
+                bail!("{} cannot follow {} (expected {})", kind, prev_type, allowed.join(" or "));
+            }
+        }
+        None => {}
+    }
+
+    let mut object = match template {
+        Some(Value::Object(map)) => map,
+        Some(_) => bail!("Template is not a JSON object"),
+        None => Map::new(),
+    };
+    let now = chrono::Utc::now();
+
+    object.insert("@context".into(), json!(OACP_CONTEXT));
+    object.insert("id".into(), json!(format!("urn:uuid:{}", uuid::Uuid::new_v4())));
+    object.insert("type".into(), json!(kind));
+    object.insert("created".into(), json!(now.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)));
+    object.remove("expires");
+    if let Some(secs) = terms.expires_in {
+        let expires = now + chrono::Duration::seconds(secs);
+        object.insert("expires".into(), json!(expires.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)));
+    }
+
+    let mut details = object.remove("details").unwrap_or_else(|| json!({}));
+    if let Some(prev) = previous {
+        for party in ["provider", "consumer"] {
+            if let Some(v) = prev.get(party) {
+                object.insert(party.into(), v.clone());
+            }
+        }
+        if let Some(prev_details) = prev.get("details") {
+            details = prev_details.clone();
+        }
+        let prev_id = prev.get("id").cloned().ok_or_else(|| anyhow!("Previous object has no id"))?;
+        let thread = prev.get("thread").cloned().unwrap_or_else(|| prev_id.clone());
+        object.insert("previous".into(), prev_id);
+        object.insert("thread".into(), thread);
+    }
+    if !details.is_object() {
+        details = json!({});
+    }
+    if let Some(provider) = terms.provider {
+        object.insert("provider".into(), json!(provider));
+    }
+    if let Some(consumer) = terms.consumer {
+        object.insert("consumer".into(), json!(consumer));
+    }
+
+    if let Some(Value::Object(extra)) = terms.details {
+        for (k, v) in extra {
+            details[k] = v;
+        }
+    }
+    if terms.amount.is_some() || terms.currency.is_some() {
+        let price = &mut details["price"];
+        if let Some(amount) = terms.amount {
+            price["amount"] = json!(amount);
+        }
+        if let Some(currency) = terms.currency {
+            price["currency"] = json!(currency);
+        }
+    }
+    if details.as_object().is_some_and(|d| !d.is_empty()) {
+        object.insert("details".into(), details);
+    }
+    if let Some(reason) = terms.reason {
+        object.insert("reason".into(), json!(reason));
+    }
+
+    let object = Value::Object(object);
+    let errors = schema::validate(Protocol::Oacp, &object)?;
+    if let Some(e) = errors.first() {
+        bail!("Generated {} is invalid at '{}': {}", kind, e.path, e.message);
+    }
+    Ok(object)
+}
@@ -0,0 +1,227 @@
+//! Machine-readable output. With `--json` every line on stdout is an envelope
+//! `{ok, command, data, error}`, and failures exit non-zero with a code per error kind.
+
+use anyhow::Result;
+use colored::*;
+use serde::Serialize;
+use std::fmt;
+use std::sync::Mutex;
+
+/// Subcommand path of the running command, set by [`set_command`].
+static COMMAND: Mutex<String> = Mutex::new(String::new());
+
+/// Category of a failure, reported as `error.kind` and mapped to the exit code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// Anything not classified below
+    Error,
+    /// Bad arguments or input the command can't work with
+    Usage,
+    /// A relay, resolver, ledger or discovery service couldn't be reached or refused
+    Network,
+    /// A file, identity, session or DID that doesn't exist
+    NotFound,
+    /// Reading or writing a local file failed for a reason other than it not existing
+    Io,
+    /// Input that isn't valid JSON/TOML
+    Parse,
+    /// A payload failed schema or structural validation
+    Validation,
+    /// Decryption or signature verification failed
+    Crypto,
+    /// The OAEP handshake didn't complete
+    Handshake,
+    /// Waited for messages or replies that never came
+    Timeout,
+}
+
+impl ErrorKind {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::Error => 1,
+            ErrorKind::Usage => 2,
+            ErrorKind::Network => 3,
+            ErrorKind::NotFound => 4,
+            ErrorKind::Io => 5,
+            ErrorKind::Parse => 6,
+            ErrorKind::Validation => 7,
+            ErrorKind::Crypto => 8,
+            ErrorKind::Handshake => 9,
+            ErrorKind::Timeout => 10,
+        }
+    }
+}
+
+/// An error with an explicit kind. `reported` marks errors whose envelope was already
+/// printed (with partial `data`), so `main` only sets the exit code.
+#[derive(Debug)]
+pub struct CliError {
+    pub kind: ErrorKind,
+    pub message: String,
+    reported: bool,
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for CliError {}
+
+/// Builds an error of `kind`.
+pub fn error(kind: ErrorKind, message: impl Into<String>) -> anyhow::Error {
+    CliError { kind, message: message.into(), reported: false }.into()
+}
+
+#[derive(Serialize)]
+struct Envelope<T: Serialize> {
+    ok: bool,
+    command: String,
+    data: Option<T>,
+    error: Option<ErrorInfo>,
+}
+
+#[derive(Serialize)]
+struct ErrorInfo {
+    kind: ErrorKind,
+    message: String,
+}
+
+/// Records the subcommand path (e.g. `relay bench`) used in every envelope.
+pub fn set_command(command: String) {
+    if let Ok(mut current) = COMMAND.lock() {
+        *current = command;
+    }
+}
+
+fn command() -> String {
+    COMMAND.lock().map(|c| c.clone()).unwrap_or_default()
+}
+
+fn envelope<T: Serialize>(data: Option<T>, error: Option<ErrorInfo>) -> Envelope<T> {
+    Envelope { ok: error.is_none(), command: command(), data, error }
+}
+
+/// Prints a successful result.
+pub fn emit<T: Serialize>(data: &T) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(&envelope(Some(data), None))?);
+    Ok(())
+}
+
+/// Prints one event of a streaming command as a single line (JSONL).
+pub fn emit_line<T: Serialize>(data: &T) -> Result<()> {
+    println!("{}", serde_json::to_string(&envelope(Some(data), None))?);
+    Ok(())
+}
+
+/// The `data` of an envelope, so `--json` output can be piped back in; other values as-is.
+pub fn unwrap_envelope(value: serde_json::Value) -> serde_json::Value {
+    let is_envelope = value.as_object().is_some_and(|o| {
+        o.contains_key("ok") && o.contains_key("command") && o.contains_key("data")
+    });
+    if is_envelope {
+        value["data"].clone()
+    } else {
+        value
+    }
+}
+
+/// Classifies an error by the first typed cause in its chain.
+pub fn classify(err: &anyhow::Error) -> ErrorKind {
+    if let Some(e) = err.chain().find_map(|c| c.downcast_ref::<CliError>()) {
+        return e.kind;
+    }
+    for cause in err.chain() {
+        if cause.is::<reqwest::Error>() {
+            return ErrorKind::Network;
+        }
+        if cause.is::<serde_json::Error>() || cause.is::<toml::de::Error>() {
+            return ErrorKind::Parse;
+        }
+        if let Some(e) = cause.downcast_ref::<crate::resolver::ResolutionError>() {
+            return match e.code {
+                "notFound" => ErrorKind::NotFound,
+                "networkError" => ErrorKind::Network,
+                "invalidDid" => ErrorKind::Usage,
+                _ => ErrorKind::Validation,
+            };
+        }
+        if let Some(io) = cause.downcast_ref::<std::io::Error>() {
+            return if io.kind() == std::io::ErrorKind::NotFound { ErrorKind::NotFound } else { ErrorKind::Io };
+        }
+    }
+    ErrorKind::Error
+}
+
+/// A failure that still has a result to show (e.g. a handshake trace or validation
+/// reports). In JSON mode the envelope carries both `data` and `error`.
+pub fn fail<T: Serialize>(json: bool, data: &T, err: anyhow::Error) -> anyhow::Error {
+    if !json {
+        return err;
+    }
+    let kind = classify(&err);
+    let message = format!("{:#}", err);
+    let info = ErrorInfo { kind, message: message.clone() };
+    match serde_json::to_string_pretty(&envelope(Some(data), Some(info))) {
+        Ok(line) => println!("{}", line),
+        Err(_) => return err,
+    }
+    CliError { kind, message, reported: true }.into()
+}
+
+/// Reports a failed command and returns its exit code.
+pub fn report(json: bool, err: &anyhow::Error) -> i32 {
+    let kind = classify(err);
+    let reported = err.chain().any(|c| c.downcast_ref::<CliError>().is_some_and(|e| e.reported));
+    if reported {
+        return kind.exit_code();
+    }
+    if json {
+        let info = ErrorInfo { kind, message: format!("{:#}", err) };
+        println!("{}", serde_json::to_string_pretty(&envelope::<()>(None, Some(info))).unwrap_or_default());
+    } else {
+        eprintln!("{} {:#}", "Error:".red(), err);
+    }
+    kind.exit_code()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_error_kind_has_its_own_exit_code() {
+        let kinds = [
+            (ErrorKind::Error, 1),
+            (ErrorKind::Usage, 2),
+            (ErrorKind::Network, 3),
+            (ErrorKind::NotFound, 4),
+            (ErrorKind::Io, 5),
+            (ErrorKind::Parse, 6),
+            (ErrorKind::Validation, 7),
+            (ErrorKind::Crypto, 8),
+            (ErrorKind::Handshake, 9),
+            (ErrorKind::Timeout, 10),
+        ];
+        for (kind, code) in kinds {
+            assert_eq!(kind.exit_code(), code, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn errors_are_classified_by_their_first_typed_cause() {
+        let err = error(ErrorKind::Timeout, "late").context("waiting");
+        assert_eq!(classify(&err), ErrorKind::Timeout);
+
+        let missing = std::io::Error::new(std::io::ErrorKind::NotFound, "gone");
+        assert_eq!(classify(&anyhow::Error::new(missing)), ErrorKind::NotFound);
+        let denied = std::io::Error::new(std::io::ErrorKind::PermissionDenied, "no");
+        assert_eq!(classify(&anyhow::Error::new(denied)), ErrorKind::Io);
+
+        let parse = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+        assert_eq!(classify(&anyhow::Error::new(parse)), ErrorKind::Parse);
+        assert_eq!(classify(&anyhow::anyhow!("plain")), ErrorKind::Error);
+    }
+}
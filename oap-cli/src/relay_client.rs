@@ -0,0 +1,136 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+/// Client for the OATP relay HTTP API (as implemented by the localnet relay).
+#[derive(Clone)]
+pub struct RelayClient {
+    base: String,
+    client: reqwest::Client,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InboxReceipt {
+    pub message_id: String,
+}
+
+/// A stored message as returned by `GET /messages`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RelayMessage {
+    pub id: String,
+    pub recipient: String,
+    pub content: String,
+    #[serde(default)]
+    pub received_at: Option<f64>,
+    #[serde(default)]
+    pub status: Option<String>,
+}
+
+/// Fault injection settings of the localnet relay (`GET`/`POST /chaos`).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChaosConfig {
+    pub failure_rate: f64,
+    pub latency_ms: u64,
+    pub corrupt_bytes: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChaosResponse {
+    config: ChaosConfig,
+}
+
+impl RelayClient {
+    pub fn new(base: &str, client: reqwest::Client) -> Self {
+        Self { base: base.trim_end_matches('/').to_string(), client }
+    }
+
+    pub fn base(&self) -> &str {
+        &self.base
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base, path)
+    }
+
+    /// `POST /inbox` with `{message, recipient}`.
+    pub async fn post_inbox(&self, message: &str, recipient: &str) -> Result<InboxReceipt> {
+        let res = self.client.post(self.url("/inbox"))
+            .json(&json!({ "message": message, "recipient": recipient }))
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach relay at {}", self.base))?;
+        let status = res.status();
+        if !status.is_success() {
+            let body = res.text().await.unwrap_or_default();
+            bail!("Relay rejected message ({}): {}", status, body);
+        }
+        res.json().await.context("Unexpected /inbox response from relay")
+    }
+
+    /// `GET /messages[?recipient=]`.
+    pub async fn messages(&self, recipient: Option<&str>) -> Result<Vec<RelayMessage>> {
+        let mut req = self.client.get(self.url("/messages"));
+        if let Some(r) = recipient {
+            req = req.query(&[("recipient", r)]);
+        }
+        let res = req.send().await
+            .with_context(|| format!("Failed to reach relay at {}", self.base))?;
+        let status = res.status();
+        if !status.is_success() {
+            bail!("Relay returned {} for /messages", status);
+        }
+        res.json().await.context("Unexpected /messages response from relay")
+    }
+
+    async fn check(&self, res: reqwest::Result<reqwest::Response>, path: &str) -> Result<reqwest::Response> {
+        let res = res.with_context(|| format!("Failed to reach relay at {}", self.base))?;
+        let status = res.status();
+        if !status.is_success() {
+            let body = res.text().await.unwrap_or_default();
+            bail!("Relay returned {} for {}: {}", status, path, body);
+        }
+        Ok(res)
+    }
+
+    /// `DELETE /messages` (localnet admin).
+    pub async fn clear_messages(&self) -> Result<()> {
+        self.check(self.client.delete(self.url("/messages")).send().await, "/messages").await?;
+        Ok(())
+    }
+
+    /// `DELETE /messages/{id}` (localnet admin): removes a single stored message.
+    pub async fn delete_message(&self, id: &str) -> Result<()> {
+        let path = format!("/messages/{}", id);
+        self.check(self.client.delete(self.url(&path)).send().await, &path).await?;
+        Ok(())
+    }
+
+    /// `GET /system/logs` (localnet admin): the relay's most recent log lines, oldest first.
+    pub async fn logs(&self) -> Result<Vec<String>> {
+        self.check(self.client.get(self.url("/system/logs")).send().await, "/system/logs").await?
+            .json().await
+            .context("Unexpected /system/logs response from relay")
+    }
+
+    /// `GET /chaos` (localnet admin): the fault injection currently in effect, or `None`
+    /// for relays that predate the endpoint.
+    pub async fn chaos(&self) -> Result<Option<ChaosConfig>> {
+        let res = self.client.get(self.url("/chaos")).send().await;
+        if res.as_ref().is_ok_and(|r| matches!(r.status().as_u16(), 404 | 405)) {
+            return Ok(None);
+        }
+        let body: ChaosResponse = self.check(res, "/chaos").await?
+            .json().await
+            .context("Unexpected /chaos response from relay")?;
+        Ok(Some(body.config))
+    }
+
+    /// `POST /chaos` (localnet admin). Returns the configuration now in effect.
+    pub async fn set_chaos(&self, config: &ChaosConfig) -> Result<ChaosConfig> {
+        let res = self.client.post(self.url("/chaos")).json(config).send().await;
+        let body: ChaosResponse = self.check(res, "/chaos").await?
+            .json().await
+            .context("Unexpected /chaos response from relay")?;
+        Ok(body.config)
+    }
+}
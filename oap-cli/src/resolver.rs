@@ -0,0 +1,681 @@
+use oap::oaep::did::DidDocument;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::config::Config;
+
+/// Multicodec prefixes (varint) for did:key
+pub const ED25519_PUB_CODEC: [u8; 2] = [0xed, 0x01];
+const X25519_PUB_CODEC: [u8; 2] = [0xec, 0x01];
+
+const DID_CONTEXT: &str = "https://www.w3.org/ns/did/v1";
+const ED25519_2020_CONTEXT: &str = "https://w3id.org/security/suites/ed25519-2020/v1";
+const X25519_2020_CONTEXT: &str = "https://w3id.org/security/suites/x25519-2020/v1";
+
+/// A DID resolution error, named after the W3C DID Resolution error codes, plus
+/// `networkError` for a did:web host that could not be reached.
+#[derive(Debug, Clone)]
+pub struct ResolutionError {
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl ResolutionError {
+    pub fn new(code: &'static str, message: impl Into<String>) -> Self {
+        Self { code, message: message.into() }
+    }
+}
+
+impl fmt::Display for ResolutionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for ResolutionError {}
+
+/// W3C DID resolution result (`didDocument` + metadata).
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResolutionResult {
+    #[serde(rename = "@context")]
+    pub context: &'static str,
+    pub did_document: Option<Value>,
+    pub did_resolution_metadata: Value,
+    pub did_document_metadata: Value,
+}
+
+impl ResolutionResult {
+    fn ok(document: Value, metadata: Value) -> Self {
+        Self {
+            context: "https://w3id.org/did-resolution/v1",
+            did_document: Some(document),
+            did_resolution_metadata: metadata,
+            did_document_metadata: json!({}),
+        }
+    }
+
+    pub fn error(err: &ResolutionError) -> Self {
+        Self {
+            context: "https://w3id.org/did-resolution/v1",
+            did_document: None,
+            did_resolution_metadata: json!({ "error": err.code, "errorMessage": err.message }),
+            did_document_metadata: json!({}),
+        }
+    }
+
+    pub fn document(&self) -> &Value {
+        self.did_document.as_ref().unwrap_or(&Value::Null)
+    }
+}
+
+/// On-disk cache entry for a network-resolved document.
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    cached_at: u64,
+    document: Value,
+    metadata: Value,
+}
+
+/// Resolves DIDs through up to three layers, in order:
+/// fixtures (a directory of pinned documents), the TTL'd on-disk cache, the network.
+/// did:key never leaves the process.
+pub struct Resolver {
+    client: reqwest::Client,
+    /// Fetch did:web documents over plain HTTP (localnet)
+    insecure: bool,
+    fixtures: Option<PathBuf>,
+    cache: Option<(PathBuf, u64)>,
+}
+
+impl Resolver {
+    pub fn new(client: reqwest::Client, insecure: bool) -> Self {
+        Self { client, insecure, fixtures: None, cache: None }
+    }
+
+    /// Builds a resolver from the effective config (`did_fixtures`, `did_cache_ttl_secs`).
+    pub fn from_config(cfg: &Config, insecure: bool) -> anyhow::Result<Self> {
+        let mut resolver = Self::new(cfg.http_client()?, insecure);
+        if let Some(dir) = &cfg.did_fixtures {
+            resolver = resolver.with_fixtures(PathBuf::from(dir));
+        }
+        if cfg.did_cache_ttl_secs > 0 {
+            resolver = resolver.with_cache(default_cache_dir()?, cfg.did_cache_ttl_secs);
+        }
+        Ok(resolver)
+    }
+
+    /// Resolve only from `dir`; no network access. Every `*.json` file in the
+    /// directory is indexed by its `id`.
+    pub fn with_fixtures(mut self, dir: PathBuf) -> Self {
+        self.fixtures = Some(dir);
+        self
+    }
+
+    pub fn with_cache(mut self, dir: PathBuf, ttl_secs: u64) -> Self {
+        self.cache = Some((dir, ttl_secs));
+        self
+    }
+
+    pub fn without_cache(mut self) -> Self {
+        self.cache = None;
+        self
+    }
+
+    pub async fn resolve(&self, did: &str) -> Result<ResolutionResult, ResolutionError> {
+        let method = parse_method(did)?;
+        if method == "key" {
+            return resolve_did_key(did);
+        }
+
+        if let Some(dir) = &self.fixtures {
+            return resolve_fixture(dir, did);
+        }
+
+        if method != "web" {
+            return Err(ResolutionError::new(
+                "methodNotSupported",
+                format!("did:{} is not supported (expected did:key or did:web)", method),
+            ));
+        }
+
+        if let Some(hit) = self.read_cache(did) {
+            return Ok(hit);
+        }
+        let result = self.resolve_did_web(did).await?;
+        self.write_cache(did, &result);
+        Ok(result)
+    }
+
+    fn read_cache(&self, did: &str) -> Option<ResolutionResult> {
+        let (dir, ttl) = self.cache.as_ref()?;
+        let content = fs::read_to_string(cache_path(dir, did, self.insecure)).ok()?;
+        let entry: CacheEntry = serde_json::from_str(&content).ok()?;
+        if entry.document.get("id").and_then(|v| v.as_str()) != Some(did) {
+            return None;
+        }
+        let age = now_secs().saturating_sub(entry.cached_at);
+        if age >= *ttl {
+            return None;
+        }
+        let mut metadata = entry.metadata;
+        metadata["source"] = json!("cache");
+        metadata["cacheAgeSecs"] = json!(age);
+        Some(ResolutionResult::ok(entry.document, metadata))
+    }
+
+    /// Best effort: a failed cache write never fails the resolution.
+    fn write_cache(&self, did: &str, result: &ResolutionResult) {
+        let Some((dir, _)) = &self.cache else { return };
+        let Some(document) = &result.did_document else { return };
+        let entry = CacheEntry {
+            cached_at: now_secs(),
+            document: document.clone(),
+            metadata: result.did_resolution_metadata.clone(),
+        };
+        if fs::create_dir_all(dir).is_ok() {
+            if let Ok(content) = serde_json::to_string_pretty(&entry) {
+                let _ = fs::write(cache_path(dir, did, self.insecure), content);
+            }
+        }
+    }
+
+    async fn resolve_did_web(&self, did: &str) -> Result<ResolutionResult, ResolutionError> {
+        let url = did_web_url(did, self.insecure)?;
+        let res = self.client.get(&url).send().await
+            .map_err(|e| ResolutionError::new("networkError", format!("GET {} failed: {}", url, e)))?;
+
+        let status = res.status();
+        if status == reqwest::StatusCode::NOT_FOUND || status == reqwest::StatusCode::GONE {
+            return Err(ResolutionError::new("notFound", format!("GET {} returned {}", url, status)));
+        }
+        if !status.is_success() {
+            return Err(ResolutionError::new("networkError", format!("GET {} returned {}", url, status)));
+        }
+        let content_type = res.headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("application/did+json")
+            .to_string();
+
+        let body = res.bytes().await
+            .map_err(|e| ResolutionError::new("networkError", format!("Failed to read body from {}: {}", url, e)))?;
+        let document: Value = serde_json::from_slice(&body)
+            .map_err(|e| ResolutionError::new("representationNotSupported", format!("Document at {} is not JSON: {}", url, e)))?;
+
+        validate_document(did, &document)?;
+
+        Ok(ResolutionResult::ok(document, json!({
+            "contentType": content_type,
+            "retrieved": chrono::Utc::now().to_rfc3339(),
+            "url": url,
+            "source": "network",
+        })))
+    }
+}
+
+pub fn default_cache_dir() -> anyhow::Result<PathBuf> {
+    let home = dirs::home_dir().ok_or_else(|| anyhow::anyhow!("Could not determine home directory"))?;
+    Ok(home.join(".oap").join("cache").join("did"))
+}
+
+/// Removes all cached documents. Returns the number of files removed.
+pub fn clear_cache(dir: &Path) -> anyhow::Result<usize> {
+    if !dir.exists() {
+        return Ok(0);
+    }
+    let mut removed = 0;
+    for file in fs::read_dir(dir)? {
+        let path = file?.path();
+        if path.extension().and_then(|e| e.to_str()) == Some("json") {
+            fs::remove_file(path)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Files are named by the SHA-256 of the DID, so distinct DIDs never share an entry.
+/// Documents fetched over plain HTTP (`--insecure`) are cached apart from https ones,
+/// so an insecure fetch is never served to a secure resolution.
+fn cache_path(dir: &Path, did: &str, insecure: bool) -> PathBuf {
+    let scheme = if insecure { "http" } else { "https" };
+    dir.join(format!("{}_{}.json", scheme, hex::encode(Sha256::digest(did.as_bytes()))))
+}
+
+fn resolve_fixture(dir: &Path, did: &str) -> Result<ResolutionResult, ResolutionError> {
+    let files = fs::read_dir(dir).map_err(|e| {
+        ResolutionError::new("notFound", format!("Cannot read fixtures directory {}: {}", dir.display(), e))
+    })?;
+
+    for file in files.flatten() {
+        let path = file.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+        let Ok(content) = fs::read_to_string(&path) else { continue };
+        let Ok(document) = serde_json::from_str::<Value>(&content) else { continue };
+        // Accept bare documents as well as saved resolution results
+        let document = match document.get("didDocument") {
+            Some(inner) => inner.clone(),
+            None => document,
+        };
+        if document.get("id").and_then(|v| v.as_str()) != Some(did) {
+            continue;
+        }
+
+        validate_document(did, &document)?;
+        return Ok(ResolutionResult::ok(document, json!({
+            "contentType": "application/did+json",
+            "retrieved": chrono::Utc::now().to_rfc3339(),
+            "source": "fixture",
+            "path": path,
+        })));
+    }
+
+    Err(ResolutionError::new("notFound", format!("No fixture for {} in {}", did, dir.display())))
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Returns the method name of a syntactically valid DID.
+pub fn parse_method(did: &str) -> Result<&str, ResolutionError> {
+    let mut parts = did.splitn(3, ':');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some("did"), Some(method), Some(id))
+            if !method.is_empty()
+                && method.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+                && !id.is_empty() =>
+        {
+            Ok(method)
+        }
+        _ => Err(ResolutionError::new("invalidDid", format!("'{}' is not a valid DID", did))),
+    }
+}
+
+/// Expands a did:key into its DID document, with an Ed25519 verification method
+/// and the derived X25519 key agreement key.
+pub fn resolve_did_key(did: &str) -> Result<ResolutionResult, ResolutionError> {
+    let fragment = did.strip_prefix("did:key:")
+        .ok_or_else(|| ResolutionError::new("invalidDid", "Expected did:key"))?;
+    if !fragment.starts_with('z') {
+        return Err(ResolutionError::new("invalidDid", "did:key identifier must be base58btc multibase ('z' prefix)"));
+    }
+    let (_, bytes) = multibase::decode(fragment)
+        .map_err(|e| ResolutionError::new("invalidDid", format!("Invalid multibase: {}", e)))?;
+
+    if bytes.len() < 2 {
+        return Err(ResolutionError::new("invalidPublicKeyLength", "Key is shorter than its multicodec prefix"));
+    }
+    let (codec, key) = bytes.split_at(2);
+    if codec != ED25519_PUB_CODEC {
+        return Err(ResolutionError::new(
+            "unsupportedPublicKeyType",
+            format!("Multicodec 0x{} is not ed25519-pub (0xed01)", hex::encode(codec)),
+        ));
+    }
+    let key: [u8; 32] = key.try_into().map_err(|_| {
+        ResolutionError::new("invalidPublicKeyLength", format!("Expected 32 key bytes, got {}", key.len()))
+    })?;
+    let verifying = ed25519_dalek::VerifyingKey::from_bytes(&key)
+        .map_err(|e| ResolutionError::new("invalidPublicKey", format!("Not a valid Ed25519 point: {}", e)))?;
+
+    let x25519 = verifying.to_montgomery().to_bytes();
+    let x25519_multibase = multibase::encode(multibase::Base::Base58Btc, [X25519_PUB_CODEC.as_slice(), &x25519].concat());
+
+    let vm_id = format!("{}#{}", did, fragment);
+    let ka_id = format!("{}#{}", did, x25519_multibase);
+    let document = json!({
+        "@context": [DID_CONTEXT, ED25519_2020_CONTEXT, X25519_2020_CONTEXT],
+        "id": did,
+        "verificationMethod": [{
+            "id": vm_id,
+            "type": "Ed25519VerificationKey2020",
+            "controller": did,
+            "publicKeyMultibase": fragment,
+        }],
+        "authentication": [vm_id],
+        "assertionMethod": [vm_id],
+        "capabilityInvocation": [vm_id],
+        "capabilityDelegation": [vm_id],
+        "keyAgreement": [{
+            "id": ka_id,
+            "type": "X25519KeyAgreementKey2020",
+            "controller": did,
+            "publicKeyMultibase": x25519_multibase,
+        }],
+    });
+
+    validate_document(did, &document)?;
+
+    Ok(ResolutionResult::ok(document, json!({
+        "contentType": "application/did+ld+json",
+        "retrieved": chrono::Utc::now().to_rfc3339(),
+        "source": "local",
+    })))
+}
+
+/// Minimal did:web document for a single Ed25519 key and an OAEP service endpoint.
+pub fn did_web_document(did: &str, public_key: &[u8; 32], endpoint: &str) -> Value {
+    let multibase_key = multibase::encode(multibase::Base::Base58Btc, [ED25519_PUB_CODEC.as_slice(), public_key].concat());
+    let vm_id = format!("{}#key-1", did);
+    json!({
+        "@context": [DID_CONTEXT, ED25519_2020_CONTEXT],
+        "id": did,
+        "verificationMethod": [{
+            "id": vm_id,
+            "type": "Ed25519VerificationKey2020",
+            "controller": did,
+            "publicKeyMultibase": multibase_key,
+        }],
+        "authentication": [vm_id],
+        "assertionMethod": [vm_id],
+        "service": [{
+            "id": format!("{}#oaep", did),
+            "type": "OAEPEndpoint",
+            "serviceEndpoint": endpoint,
+        }],
+    })
+}
+
+/// Maps a did:web to its document URL, as in the did:web spec.
+/// `did:web:example.com` -> `https://example.com/.well-known/did.json`,
+/// `did:web:example.com:user:alice` -> `https://example.com/user/alice/did.json`.
+/// A port must be percent-encoded (`did:web:localhost%3A8080`); every other
+/// colon separates path segments.
+pub fn did_web_url(did: &str, insecure: bool) -> Result<String, ResolutionError> {
+    let id = did.strip_prefix("did:web:")
+        .ok_or_else(|| ResolutionError::new("invalidDid", "Expected did:web"))?;
+    let mut segments = id.split(':');
+    let host = segments.next().unwrap_or_default().replace("%3A", ":").replace("%3a", ":");
+    let path: Vec<&str> = segments.collect();
+    if host.is_empty() || host.contains('/') || path.iter().any(|s| s.is_empty() || s.contains('/')) {
+        return Err(ResolutionError::new("invalidDid", format!("'{}' has an empty or invalid path segment", did)));
+    }
+
+    let scheme = if insecure { "http" } else { "https" };
+    if path.is_empty() {
+        Ok(format!("{}://{}/.well-known/did.json", scheme, host))
+    } else {
+        Ok(format!("{}://{}/{}/did.json", scheme, host, path.join("/")))
+    }
+}
+
+/// Raw Ed25519 key of the verification method `id` (a DID URL, or a bare `#fragment`).
+pub fn verification_key(document: &Value, id: &str) -> Option<[u8; 32]> {
+    let fragment = id.find('#').map(|i| &id[i..]).unwrap_or(id);
+    let vm = document.get("verificationMethod")?.as_array()?.iter().find(|vm| {
+        vm.get("id").and_then(|v| v.as_str()).is_some_and(|vm_id| vm_id == id || vm_id.ends_with(fragment))
+    })?;
+    let (_, bytes) = multibase::decode(vm.get("publicKeyMultibase")?.as_str()?).ok()?;
+    let key = bytes.strip_prefix(ED25519_PUB_CODEC.as_slice()).unwrap_or(&bytes);
+    key.try_into().ok()
+}
+
+/// Raw X25519 key of the first `keyAgreement` entry (embedded or referenced).
+pub fn key_agreement_key(document: &Value) -> Option<[u8; 32]> {
+    let entry = document.get("keyAgreement")?.as_array()?.first()?;
+    let method = match entry {
+        Value::String(id) => document.get("verificationMethod")?.as_array()?.iter()
+            .find(|vm| vm.get("id").and_then(|v| v.as_str()) == Some(id.as_str()))?,
+        other => other,
+    };
+    let (_, bytes) = multibase::decode(method.get("publicKeyMultibase")?.as_str()?).ok()?;
+    let key = bytes.strip_prefix(X25519_PUB_CODEC.as_slice()).unwrap_or(&bytes);
+    key.try_into().ok()
+}
+
+/// Parses a recipient X25519 public key: a did:key, hex, or multibase (with or without
+/// the x25519-pub multicodec prefix).
+pub fn parse_x25519_public(input: &str) -> anyhow::Result<[u8; 32]> {
+    if input.starts_with("did:key:") {
+        let result = resolve_did_key(input)?;
+        return key_agreement_key(result.document())
+            .ok_or_else(|| anyhow::anyhow!("{} has no X25519 key agreement key", input));
+    }
+    let bytes = match hex::decode(input) {
+        Ok(bytes) => bytes,
+        Err(_) => {
+            let (_, bytes) = multibase::decode(input)
+                .map_err(|_| anyhow::anyhow!("Public key is neither a did:key, hex nor multibase"))?;
+            bytes.strip_prefix(X25519_PUB_CODEC.as_slice()).map(<[u8]>::to_vec).unwrap_or(bytes)
+        }
+    };
+    bytes
+        .try_into()
+        .map_err(|b: Vec<u8>| anyhow::anyhow!("Invalid public key length: expected 32 bytes, got {}", b.len()))
+}
+
+/// The agent endpoint of a DID document: the first `OAEP`/`OAP` service, else the first service.
+pub fn service_endpoint(document: &Value) -> Option<String> {
+    let services = document.get("service")?.as_array()?;
+    let service = services.iter()
+        .find(|s| s.get("type").and_then(|t| t.as_str()).is_some_and(|t| t.starts_with("OAEP") || t.starts_with("OAP")))
+        .or_else(|| services.first())?;
+    match service.get("serviceEndpoint")? {
+        Value::String(url) => Some(url.clone()),
+        Value::Object(obj) => obj.get("uri").and_then(|v| v.as_str()).map(str::to_string),
+        _ => None,
+    }
+}
+
+/// Checks the structural requirements of a DID document for `did`.
+pub fn validate_document(did: &str, document: &Value) -> Result<(), ResolutionError> {
+    let obj = document.as_object()
+        .ok_or_else(|| ResolutionError::new("invalidDidDocument", "Document is not a JSON object"))?;
+
+    match obj.get("id").and_then(|v| v.as_str()) {
+        Some(id) if id == did => {}
+        Some(id) => {
+            return Err(ResolutionError::new("invalidDidDocument", format!("Document id '{}' does not match '{}'", id, did)))
+        }
+        None => return Err(ResolutionError::new("invalidDidDocument", "Document has no 'id'")),
+    }
+
+    if let Some(vms) = obj.get("verificationMethod") {
+        let vms = vms.as_array()
+            .ok_or_else(|| ResolutionError::new("invalidDidDocument", "'verificationMethod' is not an array"))?;
+        for (i, vm) in vms.iter().enumerate() {
+            for field in ["id", "type", "controller"] {
+                if vm.get(field).and_then(|v| v.as_str()).is_none() {
+                    return Err(ResolutionError::new(
+                        "invalidDidDocument",
+                        format!("verificationMethod[{}] is missing '{}'", i, field),
+                    ));
+                }
+            }
+        }
+    }
+
+    serde_json::from_value::<DidDocument>(document.clone())
+        .map_err(|e| ResolutionError::new("invalidDidDocument", e.to_string()))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DID: &str = "did:web:shop.example";
+
+    fn cached(dir: &Path, ttl: u64, insecure: bool) -> Resolver {
+        Resolver::new(reqwest::Client::new(), insecure).with_cache(dir.to_path_buf(), ttl)
+    }
+
+    fn result() -> ResolutionResult {
+        ResolutionResult::ok(json!({ "id": DID }), json!({ "source": "network" }))
+    }
+
+    #[test]
+    fn cache_hits_until_the_ttl_expires() {
+        let dir = tempfile::tempdir().unwrap();
+        let resolver = cached(dir.path(), 60, false);
+        resolver.write_cache(DID, &result());
+
+        let hit = resolver.read_cache(DID).unwrap();
+        assert_eq!(hit.document()["id"], DID);
+        assert_eq!(hit.did_resolution_metadata["source"], "cache");
+
+        let path = cache_path(dir.path(), DID, false);
+        let mut entry: CacheEntry = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        entry.cached_at -= 61;
+        fs::write(&path, serde_json::to_string(&entry).unwrap()).unwrap();
+        assert!(resolver.read_cache(DID).is_none());
+        assert!(cached(dir.path(), 120, false).read_cache(DID).is_some());
+    }
+
+    #[test]
+    fn insecure_fetches_are_cached_separately() {
+        let dir = tempfile::tempdir().unwrap();
+        let secure = cached(dir.path(), 60, false);
+        let insecure = cached(dir.path(), 60, true);
+
+        insecure.write_cache(DID, &result());
+        assert!(insecure.read_cache(DID).is_some());
+        assert!(secure.read_cache(DID).is_none());
+
+        secure.write_cache(DID, &result());
+        assert!(secure.read_cache(DID).is_some());
+        assert_eq!(clear_cache(dir.path()).unwrap(), 2);
+    }
+
+    #[test]
+    fn similar_dids_do_not_share_a_cache_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let resolver = cached(dir.path(), 60, false);
+        let (a, b) = ("did:web:example.com:a_b", "did:web:example.com:a:b");
+        assert_ne!(cache_path(dir.path(), a, false), cache_path(dir.path(), b, false));
+
+        resolver.write_cache(a, &ResolutionResult::ok(json!({ "id": a }), json!({})));
+        assert!(resolver.read_cache(a).is_some());
+        assert!(resolver.read_cache(b).is_none());
+    }
+
+    #[test]
+    fn cache_entries_for_another_did_are_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        let resolver = cached(dir.path(), 60, false);
+        resolver.write_cache(DID, &result());
+
+        let path = cache_path(dir.path(), DID, false);
+        let mut entry: CacheEntry = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        entry.document["id"] = json!("did:web:evil.example");
+        fs::write(&path, serde_json::to_string(&entry).unwrap()).unwrap();
+        assert!(resolver.read_cache(DID).is_none());
+    }
+
+    /// A valid did:web document: a did:key document with its ids rewritten.
+    fn web_document(did: &str) -> Value {
+        let did_key = oap::oaep::did::DidKey::generate().did().to_string();
+        let document = resolve_did_key(&did_key).unwrap().document().to_string();
+        serde_json::from_str(&document.replace(&did_key, did)).unwrap()
+    }
+
+    #[test]
+    fn fixtures_are_found_by_document_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let other = "did:web:other.example";
+        fs::write(dir.path().join("a.json"), web_document(other).to_string()).unwrap();
+        // Saved resolution results are unwrapped; other files are ignored
+        fs::write(dir.path().join("b.json"), json!({ "didDocument": web_document(DID) }).to_string()).unwrap();
+        fs::write(dir.path().join("notes.txt"), "not a fixture").unwrap();
+        fs::write(dir.path().join("broken.json"), "{").unwrap();
+
+        let hit = resolve_fixture(dir.path(), DID).unwrap();
+        assert_eq!(hit.document()["id"], DID);
+        assert_eq!(hit.did_resolution_metadata["source"], "fixture");
+        assert_eq!(hit.did_resolution_metadata["path"], json!(dir.path().join("b.json")));
+        assert_eq!(resolve_fixture(dir.path(), other).unwrap().document()["id"], other);
+
+        assert_eq!(resolve_fixture(dir.path(), "did:web:missing.example").unwrap_err().code, "notFound");
+        assert_eq!(resolve_fixture(&dir.path().join("absent"), DID).unwrap_err().code, "notFound");
+    }
+
+    #[test]
+    fn invalid_fixtures_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut document = web_document(DID);
+        document["verificationMethod"] = json!([{ "id": format!("{}#key-1", DID) }]);
+        fs::write(dir.path().join("shop.json"), document.to_string()).unwrap();
+
+        assert_eq!(resolve_fixture(dir.path(), DID).unwrap_err().code, "invalidDidDocument");
+    }
+
+    #[test]
+    fn did_key_documents_carry_both_keys() {
+        let did_key = oap::oaep::did::DidKey::generate();
+        let did = did_key.did().to_string();
+        let fragment = did.strip_prefix("did:key:").unwrap();
+
+        let result = resolve_did_key(&did).unwrap();
+        let document = result.document();
+        assert_eq!(document["id"], did);
+        assert_eq!(document["verificationMethod"][0]["publicKeyMultibase"], fragment);
+        assert_eq!(verification_key(document, &format!("#{}", fragment)).unwrap(), *did_key.keypair().public_key().as_bytes());
+        assert_eq!(
+            key_agreement_key(document).unwrap(),
+            *did_key.keypair().to_x25519().public_key().as_bytes()
+        );
+        assert_eq!(result.did_resolution_metadata["source"], "local");
+    }
+
+    #[test]
+    fn malformed_did_keys_are_rejected() {
+        let x25519 = multibase::encode(multibase::Base::Base58Btc, [X25519_PUB_CODEC.as_slice(), &[1u8; 32]].concat());
+        let short = multibase::encode(multibase::Base::Base58Btc, [ED25519_PUB_CODEC.as_slice(), &[1u8; 16]].concat());
+        let cases = [
+            ("did:web:example.com".to_string(), "invalidDid"),
+            ("did:key:f00".to_string(), "invalidDid"),
+            ("did:key:z0OIl".to_string(), "invalidDid"),
+            (format!("did:key:{}", x25519), "unsupportedPublicKeyType"),
+            (format!("did:key:{}", short), "invalidPublicKeyLength"),
+        ];
+        for (did, code) in cases {
+            assert_eq!(resolve_did_key(&did).unwrap_err().code, code, "{}", did);
+        }
+    }
+
+    #[tokio::test]
+    async fn unreachable_hosts_are_network_errors() {
+        // Nothing listens on port 1, so the connection is refused
+        let resolver = Resolver::new(reqwest::Client::new(), true);
+        let err = resolver.resolve("did:web:127.0.0.1%3A1").await.unwrap_err();
+        assert_eq!(err.code, "networkError");
+        assert_eq!(crate::output::classify(&anyhow::Error::new(err)), crate::output::ErrorKind::Network);
+
+        let not_found = ResolutionError::new("notFound", "GET returned 404");
+        assert_eq!(crate::output::classify(&anyhow::Error::new(not_found)), crate::output::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn did_web_url_mapping() {
+        let cases = [
+            ("did:web:example.com", "https://example.com/.well-known/did.json"),
+            ("did:web:example.com:user:alice", "https://example.com/user/alice/did.json"),
+            ("did:web:example.com:2024", "https://example.com/2024/did.json"),
+            ("did:web:localhost%3A8080", "https://localhost:8080/.well-known/did.json"),
+            ("did:web:localhost%3a8080:agents:7", "https://localhost:8080/agents/7/did.json"),
+        ];
+        for (did, url) in cases {
+            assert_eq!(did_web_url(did, false).unwrap(), url, "{}", did);
+        }
+        assert_eq!(
+            did_web_url("did:web:localhost%3A8080", true).unwrap(),
+            "http://localhost:8080/.well-known/did.json"
+        );
+    }
+
+    #[test]
+    fn did_web_url_rejects_bad_segments() {
+        for did in ["did:web:", "did:web:example.com::x", "did:web:example.com:a/b", "did:key:z6Mk"] {
+            assert!(did_web_url(did, false).is_err(), "{}", did);
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use anyhow::Result;
+use axum::extract::Json;
+use axum::http::StatusCode;
+use axum::routing::{get, post};
+use axum::{Extension, Router};
+use colored::*;
+use oap::oaep::did::{Did, DidKey};
+use oap::oaep::messages::{ConnectionRequest, ConnectionResponse};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use crate::commands::listen;
+use crate::handshake;
+use crate::output;
+use crate::relay_client::RelayMessage;
+use crate::resolver::{self, Resolver};
+use crate::session::{now_secs, Role, SessionStore, StoredSession};
+
+/// A throwaway OAEP responder agent served by `oap listen --port`.
+///
+/// Routes:
+/// - `GET /.well-known/did.json`: the agent's did:web document
+/// - `POST /oaep`: ConnectionRequest -> ConnectionResponse
+/// - `POST /inbox`: direct delivery, same body as the relay (`{message, recipient}`)
+/// - `GET /health`
+pub struct Responder {
+    pub did: String,
+    pub endpoint: String,
+    identity: DidKey,
+    alias: Option<String>,
+    document: Value,
+    resolver: Resolver,
+    store: SessionStore,
+    /// Ids of accepted requests, with the unix time after which they can no longer be fresh.
+    seen_requests: Mutex<HashMap<String, u64>>,
+    json: bool,
+}
+
+type ApiError = (StatusCode, Json<Value>);
+
+#[derive(Deserialize)]
+struct Delivery {
+    message: String,
+    recipient: Option<String>,
+}
+
+impl Responder {
+    pub fn new(
+        identity: DidKey,
+        alias: Option<String>,
+        host: &str,
+        port: u16,
+        resolver: Resolver,
+        json: bool,
+    ) -> Result<Self> {
+        let did = format!("did:web:{}%3A{}", host, port);
+        let endpoint = format!("http://{}:{}/oaep", host, port);
+        let document = resolver::did_web_document(&did, identity.keypair().public_key().as_bytes(), &endpoint);
+        Ok(Self {
+            did,
+            endpoint,
+            identity,
+            alias,
+            document,
+            resolver,
+            store: SessionStore::open_default()?,
+            seen_requests: Mutex::new(HashMap::new()),
+            json,
+        })
+    }
+
+    pub async fn serve(self, port: u16) -> Result<()> {
+        let app = Router::new()
+            .route("/.well-known/did.json", get(did_document))
+            .route("/oaep", post(connection_request))
+            .route("/inbox", post(delivery))
+            .route("/health", get(health))
+            .layer(Extension(Arc::new(self)));
+
+        let addr = SocketAddr::from(([0, 0, 0, 0], port));
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        axum::serve(listener, app).await?;
+        Ok(())
+    }
+
+    async fn accept(&self, request: ConnectionRequest) -> Result<(ConnectionResponse, StoredSession)> {
+        if !handshake::verify_request(&self.resolver, &request, Some(&self.did)).await? {
+            anyhow::bail!("Request proof signature invalid");
+        }
+        handshake::check_fresh(&request, chrono::Utc::now())?;
+        self.remember_request(&request.id)?;
+
+        let did: Did = self.did.parse()?;
+        let verification_method = format!("{}#key-1", self.did);
+        let (response, ephemeral) = handshake::build_response(&self.identity, &did, &verification_method, &request)?;
+
+        let hash = handshake::transcript_hash(&request, &response);
+        let keys = handshake::derive_session_keys(&ephemeral, &request.body.key_exchange.public_key, &hash)?;
+        let session = StoredSession::new(
+            request.from.to_string(),
+            self.did.clone(),
+            self.alias.clone(),
+            Role::Responder,
+            &hash,
+            response.body.key_exchange.negotiated_suite.to_string(),
+            keys,
+        );
+        self.store.save(&session)?;
+        Ok((response, session))
+    }
+
+    /// Refuses a request id that was already accepted within the freshness window.
+    fn remember_request(&self, id: &str) -> Result<()> {
+        let now = now_secs();
+        let mut seen = self.seen_requests.lock().map_err(|_| anyhow::anyhow!("Request cache poisoned"))?;
+        seen.retain(|_, expires| *expires > now);
+        if seen.contains_key(id) {
+            anyhow::bail!("Request {} was already accepted (replay?)", id);
+        }
+        seen.insert(id.to_string(), now + 2 * handshake::MAX_REQUEST_AGE_SECS as u64);
+        Ok(())
+    }
+}
+
+fn api_error(status: StatusCode, e: impl std::fmt::Display) -> ApiError {
+    (status, Json(json!({ "error": e.to_string() })))
+}
+
+async fn did_document(Extension(state): Extension<Arc<Responder>>) -> Json<Value> {
+    Json(state.document.clone())
+}
+
+async fn health() -> Json<Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+async fn connection_request(
+    Extension(state): Extension<Arc<Responder>>,
+    Json(request): Json<ConnectionRequest>,
+) -> Result<Json<ConnectionResponse>, ApiError> {
+    let peer = request.from.to_string();
+    match state.accept(request).await {
+        Ok((response, session)) => {
+            if state.json {
+                let _ = output::emit_line(&json!({ "event": "handshake", "peer": peer, "kid": session.kid, "status": "accepted" }));
+            } else {
+                println!("Handshake from {} {} (kid: {})", peer.cyan(), "ACCEPTED".green(), session.kid);
+            }
+            Ok(Json(response))
+        }
+        Err(e) => {
+            if state.json {
+                let _ = output::emit_line(&json!({ "event": "handshake", "peer": peer, "status": "rejected", "error": e.to_string() }));
+            } else {
+                println!("Handshake from {} {}: {}", peer.cyan(), "REJECTED".red(), e);
+            }
+            Err(api_error(StatusCode::BAD_REQUEST, e))
+        }
+    }
+}
+
+async fn delivery(
+    Extension(state): Extension<Arc<Responder>>,
+    Json(body): Json<Delivery>,
+) -> Result<Json<Value>, ApiError> {
+    let recipient = body.recipient.unwrap_or_else(|| state.did.clone());
+    if recipient != state.did {
+        return Err(api_error(StatusCode::NOT_FOUND, format!("Unknown recipient {}", recipient)));
+    }
+
+    let message = RelayMessage {
+        id: uuid::Uuid::new_v4().to_string(),
+        recipient,
+        content: body.message,
+        received_at: Some(now_secs() as f64),
+        status: None,
+    };
+    let message_id = message.id.clone();
+    let incoming = listen::process(&state.store, &state.did, message);
+    listen::print_incoming(&incoming, state.json)
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+    match incoming.error {
+        None => Ok(Json(json!({ "status": "delivered", "message_id": message_id }))),
+        Some(e) => Err(api_error(StatusCode::UNPROCESSABLE_ENTITY, e)),
+    }
+}
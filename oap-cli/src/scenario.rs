@@ -0,0 +1,422 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+use crate::output::{self, ErrorKind};
+use crate::schema::Protocol;
+
+/// A test scenario for `oap run`, e.g.
+///
+/// ```toml
+/// name = "shop agent"
+/// peer = "did:web:localhost%3A8080"
+/// insecure = true
+///
+/// [[steps]]
+/// identity = "buyer"
+///
+/// [[steps]]
+/// connect = "{{peer}}"
+///
+/// [[steps]]
+/// send = { type = "ping" }
+///
+/// [[steps]]
+/// expect = { type = "pong" }
+/// timeout_secs = 10
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    /// Test suite name; defaults to the file name
+    pub name: Option<String>,
+    /// Default target of `connect`, `send` and `expect`, also available as `{{peer}}`
+    pub peer: Option<String>,
+    pub relay: Option<String>,
+    pub ledger: Option<String>,
+    /// Resolve did:web over plain HTTP
+    #[serde(default)]
+    pub insecure: bool,
+    /// How long each `expect` step waits, unless the step sets its own
+    #[serde(default = "default_timeout")]
+    pub timeout_secs: u64,
+    pub steps: Vec<Step>,
+}
+
+fn default_timeout() -> u64 {
+    30
+}
+
+/// One step: exactly one action (`identity`, `connect`, `send`, `expect`, `validate`,
+/// `fund` or `balance`) plus the options that apply to it.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Step {
+    /// Test case name; defaults to the action
+    pub name: Option<String>,
+
+    /// Creates a throwaway identity under this name (its DID is `{{name}}`)
+    pub identity: Option<String>,
+    /// Handshake with this DID and store the session
+    pub connect: Option<String>,
+    /// Payload to send over the session: a string, or any JSON value
+    pub send: Option<Value>,
+    /// Pattern the next message from the peer must match
+    pub expect: Option<Value>,
+    /// Validate the last received payload against a schema
+    pub validate: Option<SchemaChoice>,
+    /// Top up an identity (name or DID) from the ledger faucet
+    pub fund: Option<String>,
+    /// Check the ledger balance of an identity (name or DID)
+    pub balance: Option<String>,
+
+    /// `identity`: load this keystore alias instead of generating a key
+    pub keystore: Option<String>,
+    /// `connect`: identity to connect as (defaults to the only one defined);
+    /// `send`/`expect`: identity whose session to use (defaults to the one connected to the peer)
+    #[serde(rename = "as")]
+    pub as_identity: Option<String>,
+    /// `send`: recipient (defaults to `peer`)
+    pub to: Option<String>,
+    /// `expect`: sender (defaults to `peer`)
+    pub from: Option<String>,
+    /// `expect`: overrides the scenario timeout
+    pub timeout_secs: Option<u64>,
+    /// `balance`: assertions, in OAP
+    pub equals: Option<f64>,
+    pub at_least: Option<f64>,
+    pub at_most: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SchemaChoice {
+    /// Detect from `@context`
+    Auto,
+    Oacp,
+    Oapp,
+}
+
+impl SchemaChoice {
+    pub fn protocol(self) -> Option<Protocol> {
+        match self {
+            SchemaChoice::Auto => None,
+            SchemaChoice::Oacp => Some(Protocol::Oacp),
+            SchemaChoice::Oapp => Some(Protocol::Oapp),
+        }
+    }
+}
+
+pub enum Action<'a> {
+    Identity(&'a str),
+    Connect(&'a str),
+    Send(&'a Value),
+    Expect(&'a Value),
+    Validate(SchemaChoice),
+    Fund(&'a str),
+    Balance(&'a str),
+}
+
+impl Step {
+    pub fn action(&self) -> Result<Action<'_>> {
+        let mut actions = Vec::new();
+        if let Some(v) = &self.identity { actions.push(Action::Identity(v)); }
+        if let Some(v) = &self.connect { actions.push(Action::Connect(v)); }
+        if let Some(v) = &self.send { actions.push(Action::Send(v)); }
+        if let Some(v) = &self.expect { actions.push(Action::Expect(v)); }
+        if let Some(v) = self.validate { actions.push(Action::Validate(v)); }
+        if let Some(v) = &self.fund { actions.push(Action::Fund(v)); }
+        if let Some(v) = &self.balance { actions.push(Action::Balance(v)); }
+        match actions.len() {
+            1 => Ok(actions.remove(0)),
+            0 => bail!("no action (set one of identity, connect, send, expect, validate, fund, balance)"),
+            _ => bail!("more than one action"),
+        }
+    }
+
+    /// The test case name: `name`, or the action and its argument.
+    pub fn label(&self) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+        match self.action() {
+            Ok(Action::Identity(name)) => format!("identity {}", name),
+            Ok(Action::Connect(did)) => format!("connect {}", did),
+            Ok(Action::Send(payload)) => format!("send {}", short_json(payload)),
+            Ok(Action::Expect(pattern)) => format!("expect {}", short_json(pattern)),
+            Ok(Action::Validate(choice)) => format!("validate {:?}", choice).to_lowercase(),
+            Ok(Action::Fund(who)) => format!("fund {}", who),
+            Ok(Action::Balance(who)) => format!("balance {}", who),
+            Err(_) => "invalid step".to_string(),
+        }
+    }
+}
+
+fn short_json(value: &Value) -> String {
+    let text = match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    };
+    if text.chars().count() > 60 {
+        format!("{}…", text.chars().take(60).collect::<String>())
+    } else {
+        text
+    }
+}
+
+/// Loads a scenario from TOML, YAML (`.yaml`/`.yml`) or JSON, picked by extension.
+pub fn load(path: &Path) -> Result<Scenario> {
+    let content = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let scenario: Scenario = match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => serde_json::from_str(&content).with_context(|| format!("Invalid scenario {}", path.display()))?,
+        Some("yaml" | "yml") => serde_yaml::from_str(&content).with_context(|| format!("Invalid scenario {}", path.display()))?,
+        _ => toml::from_str(&content).with_context(|| format!("Invalid scenario {}", path.display()))?,
+    };
+    for (i, step) in scenario.steps.iter().enumerate() {
+        if let Err(e) = step.action() {
+            let message = format!("{}: step {}: {}", path.display(), i + 1, e);
+            return Err(output::error(ErrorKind::Usage, message));
+        }
+    }
+    Ok(scenario)
+}
+
+/// Replaces `{{name}}` in `text` with the value of `vars[name]`.
+pub fn substitute(text: &str, vars: &BTreeMap<String, String>) -> Result<String> {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let end = rest[start..].find("}}")
+            .ok_or_else(|| output::error(ErrorKind::Usage, format!("Unclosed '{{{{' in {:?}", text)))?;
+        let name = rest[start + 2..start + end].trim();
+        let value = vars.get(name)
+            .ok_or_else(|| output::error(ErrorKind::Usage, format!("Unknown variable {{{{{}}}}}", name)))?;
+        out.push_str(&rest[..start]);
+        out.push_str(value);
+        rest = &rest[start + end + 2..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// [`substitute`] on every string in a JSON value.
+pub fn substitute_value(value: &Value, vars: &BTreeMap<String, String>) -> Result<Value> {
+    Ok(match value {
+        Value::String(s) => Value::String(substitute(s, vars)?),
+        Value::Array(items) => Value::Array(items.iter().map(|v| substitute_value(v, vars)).collect::<Result<_>>()?),
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(k, v)| Ok((k.clone(), substitute_value(v, vars)?)))
+                .collect::<Result<_>>()?,
+        ),
+        other => other.clone(),
+    })
+}
+
+/// Where `value` first differs from `pattern`, or `None` if it matches. Objects match if
+/// every member of the pattern matches (extra members are fine), arrays element by element,
+/// numbers by value, and the string `"*"` matches anything.
+pub fn mismatch(pattern: &Value, value: &Value) -> Option<String> {
+    fn walk(pattern: &Value, value: &Value, path: &str) -> Option<String> {
+        let at = if path.is_empty() { "/" } else { path };
+        match (pattern, value) {
+            (Value::String(p), _) if p == "*" => None,
+            (Value::Object(p), Value::Object(v)) => p.iter().find_map(|(key, expected)| {
+                let path = format!("{}/{}", path, key);
+                match v.get(key) {
+                    Some(actual) => walk(expected, actual, &path),
+                    None => Some(format!("{} is missing", path)),
+                }
+            }),
+            (Value::Array(p), Value::Array(v)) if p.len() != v.len() => {
+                Some(format!("{}: expected {} items, got {}", at, p.len(), v.len()))
+            }
+            (Value::Array(p), Value::Array(v)) => p.iter().zip(v)
+                .enumerate()
+                .find_map(|(i, (expected, actual))| walk(expected, actual, &format!("{}/{}", path, i))),
+            (Value::Number(p), Value::Number(v)) if p.as_f64() == v.as_f64() => None,
+            _ if pattern == value => None,
+            _ => Some(format!("{}: expected {}, got {}", at, short_json(pattern), short_json(value))),
+        }
+    }
+    walk(pattern, value, "")
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum Outcome {
+    Passed,
+    Failed { kind: ErrorKind, message: String },
+    Skipped { message: String },
+}
+
+#[derive(Debug, Serialize)]
+pub struct CaseResult {
+    pub name: String,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+    #[serde(serialize_with = "secs")]
+    pub time: Duration,
+    /// What the step produced (received payload, balance, ...), shown as `system-out`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<Value>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SuiteResult {
+    pub name: String,
+    pub file: String,
+    pub timestamp: String,
+    #[serde(serialize_with = "secs")]
+    pub time: Duration,
+    pub cases: Vec<CaseResult>,
+}
+
+fn secs<S: serde::Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_f64(d.as_secs_f64())
+}
+
+impl SuiteResult {
+    pub fn failures(&self) -> usize {
+        self.cases.iter().filter(|c| matches!(c.outcome, Outcome::Failed { .. })).count()
+    }
+
+    pub fn skipped(&self) -> usize {
+        self.cases.iter().filter(|c| matches!(c.outcome, Outcome::Skipped { .. })).count()
+    }
+}
+
+fn xml_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            // Not allowed in XML 1.0
+            c if (c as u32) < 0x20 && !matches!(c, '\t' | '\n' | '\r') => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Renders the results as a JUnit XML report: one `<testsuite>` per scenario, one
+/// `<testcase>` per step.
+pub fn junit(suites: &[SuiteResult]) -> String {
+    let tests: usize = suites.iter().map(|s| s.cases.len()).sum();
+    let failures: usize = suites.iter().map(SuiteResult::failures).sum();
+    let skipped: usize = suites.iter().map(SuiteResult::skipped).sum();
+    let time: f64 = suites.iter().map(|s| s.time.as_secs_f64()).sum();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<testsuites name=\"oap run\" tests=\"{}\" failures=\"{}\" errors=\"0\" skipped=\"{}\" time=\"{:.3}\">\n",
+        tests, failures, skipped, time
+    ));
+    for suite in suites {
+        xml.push_str(&format!(
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"0\" skipped=\"{}\" time=\"{:.3}\" timestamp=\"{}\" file=\"{}\">\n",
+            xml_escape(&suite.name), suite.cases.len(), suite.failures(), suite.skipped(),
+            suite.time.as_secs_f64(), xml_escape(&suite.timestamp), xml_escape(&suite.file)
+        ));
+        for case in &suite.cases {
+            xml.push_str(&format!(
+                "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+                xml_escape(&case.name), xml_escape(&suite.name), case.time.as_secs_f64()
+            ));
+            if matches!(case.outcome, Outcome::Passed) && case.output.is_none() {
+                xml.push_str("/>\n");
+                continue;
+            }
+            xml.push_str(">\n");
+            match &case.outcome {
+                Outcome::Passed => {}
+                Outcome::Failed { kind, message } => {
+                    let kind = serde_json::to_value(kind).ok().and_then(|v| v.as_str().map(str::to_string)).unwrap_or_default();
+                    let summary = message.lines().next().unwrap_or_default();
+                    xml.push_str(&format!(
+                        "      <failure type=\"{}\" message=\"{}\">{}</failure>\n",
+                        xml_escape(&kind), xml_escape(summary), xml_escape(message)
+                    ));
+                }
+                Outcome::Skipped { message } => {
+                    xml.push_str(&format!("      <skipped message=\"{}\"/>\n", xml_escape(message)));
+                }
+            }
+            if let Some(output) = &case.output {
+                let text = serde_json::to_string_pretty(output).unwrap_or_default();
+                xml.push_str(&format!("      <system-out>{}</system-out>\n", xml_escape(&text)));
+            }
+            xml.push_str("    </testcase>\n");
+        }
+        xml.push_str("  </testsuite>\n");
+    }
+    xml.push_str("</testsuites>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn vars() -> BTreeMap<String, String> {
+        BTreeMap::from([("peer".to_string(), "did:web:shop".to_string())])
+    }
+
+    #[test]
+    fn wildcards_and_extra_members_match() {
+        let pattern = json!({ "text": "hi", "thread": "*", "items": [1, "*"] });
+        let value = json!({ "text": "hi", "thread": { "id": 7 }, "items": [1.0, null], "extra": true });
+        assert_eq!(mismatch(&pattern, &value), None);
+    }
+
+    #[test]
+    fn mismatches_name_the_path() {
+        assert_eq!(mismatch(&json!({ "a": { "b": 1 } }), &json!({ "a": { "b": 2 } })).unwrap(), "/a/b: expected 1, got 2");
+        assert_eq!(mismatch(&json!({ "a": 1 }), &json!({})).unwrap(), "/a is missing");
+        assert_eq!(mismatch(&json!([1, 2]), &json!([1])).unwrap(), "/: expected 2 items, got 1");
+        assert!(mismatch(&json!("1"), &json!(1)).is_some());
+    }
+
+    #[test]
+    fn substitutes_variables_in_strings_and_values() {
+        assert_eq!(substitute("to {{ peer }}!", &vars()).unwrap(), "to did:web:shop!");
+        assert!(substitute("{{nobody}}", &vars()).is_err());
+        assert!(substitute("{{peer", &vars()).is_err());
+
+        let value = substitute_value(&json!({ "to": ["{{peer}}", 3] }), &vars()).unwrap();
+        assert_eq!(value, json!({ "to": ["did:web:shop", 3] }));
+    }
+
+    #[test]
+    fn junit_escapes_names_and_messages() {
+        let suite = SuiteResult {
+            name: "a <b> & \"c\"".to_string(),
+            file: "s.toml".to_string(),
+            timestamp: "2026-01-01T00:00:00Z".to_string(),
+            time: Duration::from_millis(1500),
+            cases: vec![
+                CaseResult { name: "ok".to_string(), outcome: Outcome::Passed, time: Duration::ZERO, output: None },
+                CaseResult {
+                    name: "expect 'x'".to_string(),
+                    outcome: Outcome::Failed { kind: ErrorKind::Timeout, message: "no <reply>\u{1}\nsecond line".to_string() },
+                    time: Duration::ZERO,
+                    output: None,
+                },
+            ],
+        };
+        let xml = junit(&[suite]);
+
+        assert!(xml.contains("tests=\"2\" failures=\"1\" errors=\"0\" skipped=\"0\" time=\"1.500\""));
+        assert!(xml.contains("<testsuite name=\"a &lt;b&gt; &amp; &quot;c&quot;\""));
+        assert!(xml.contains("<testcase name=\"ok\" classname=\"a &lt;b&gt; &amp; &quot;c&quot;\" time=\"0.000\"/>"));
+        assert!(xml.contains("<failure type=\"timeout\" message=\"no &lt;reply&gt;\">no &lt;reply&gt;\nsecond line</failure>"));
+        assert!(xml.contains("name=\"expect &apos;x&apos;\""));
+    }
+}
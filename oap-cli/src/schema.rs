@@ -0,0 +1,85 @@
+use anyhow::{anyhow, bail, Result};
+use serde::Serialize;
+use serde_json::Value;
+
+/// JSON Schemas (draft-07) shipped with the VS Code extension, vendored in `schemas/`.
+const OACP_SCHEMA: &str = include_str!("../schemas/oacp.schema.json");
+const OAPP_SCHEMA: &str = include_str!("../schemas/oapp.schema.json");
+
+pub const OACP_CONTEXT: &str = "https://w3id.org/oacp/v1";
+pub const OAPP_CONTEXT: &str = "https://w3id.org/oapp/v1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    Oacp,
+    Oapp,
+}
+
+impl Protocol {
+    fn schema(&self) -> &'static str {
+        match self {
+            Protocol::Oacp => OACP_SCHEMA,
+            Protocol::Oapp => OAPP_SCHEMA,
+        }
+    }
+
+    /// Detects the protocol from `@context` (a string, or an array containing one).
+    pub fn detect(payload: &Value) -> Result<Self> {
+        let contexts: Vec<&str> = match payload.get("@context") {
+            Some(Value::String(c)) => vec![c.as_str()],
+            Some(Value::Array(items)) => items.iter().filter_map(|v| v.as_str()).collect(),
+            Some(_) => bail!("'@context' is neither a string nor an array"),
+            None => bail!("Payload has no '@context'; pass --schema oacp|oapp"),
+        };
+        if contexts.contains(&OACP_CONTEXT) {
+            Ok(Protocol::Oacp)
+        } else if contexts.contains(&OAPP_CONTEXT) {
+            Ok(Protocol::Oapp)
+        } else {
+            Err(anyhow!("Unknown @context {:?} (expected {} or {})", contexts, OACP_CONTEXT, OAPP_CONTEXT))
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SchemaError {
+    /// JSON pointer into the payload
+    pub path: String,
+    /// JSON pointer into the schema
+    pub schema_path: String,
+    pub message: String,
+}
+
+/// Validates `payload` against the protocol's schema, formats (e.g. `date-time`) included.
+pub fn validate(protocol: Protocol, payload: &Value) -> Result<Vec<SchemaError>> {
+    let schema: Value = serde_json::from_str(protocol.schema())?;
+    let validator = jsonschema::draft7::options()
+        .should_validate_formats(true)
+        .build(&schema)
+        .map_err(|e| anyhow!("Invalid bundled schema: {}", e))?;
+
+    Ok(validator.iter_errors(payload)
+        .map(|e| SchemaError {
+            path: e.instance_path.to_string(),
+            schema_path: e.schema_path.to_string(),
+            message: e.to_string(),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn vendored_schemas_match_the_vs_code_extension() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        for name in ["oacp.schema.json", "oapp.schema.json"] {
+            let vendored = fs::read(root.join("schemas").join(name)).unwrap();
+            let upstream = fs::read(root.join("../oap-vs-code-extension/schemas").join(name)).unwrap();
+            assert!(vendored == upstream, "schemas/{} differs from oap-vs-code-extension; copy it over", name);
+        }
+    }
+}
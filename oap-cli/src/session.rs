@@ -0,0 +1,416 @@
+use anyhow::{anyhow, bail, Context, Result};
+use colored::*;
+use fs2::FileExt;
+use oap::oaep::keys::SessionKey;
+use oap::oatp::container::{decrypt_padded, encrypt_padded, JweContainer};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::keystore::{create_private_dir, write_private};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Initiator,
+    Responder,
+}
+
+/// Plaintexts are padded to a multiple of this many bytes before encryption.
+pub const DEFAULT_PADDING_BLOCK: usize = 1024;
+
+/// Sessions expire after a day unless re-established.
+pub const DEFAULT_SESSION_TTL_SECS: u64 = 24 * 60 * 60;
+
+/// An established OAEP session, stored as `~/.oap/sessions/{sha256(peer)}.json` (mode `0600`).
+/// Keys are hex encoded and not encrypted: sessions are short-lived test material.
+/// Files that other users can read are refused instead of used.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredSession {
+    pub peer_did: String,
+    pub local_did: String,
+    pub identity: Option<String>,
+    pub role: Role,
+    pub kid: String,
+    pub transcript_hash: String,
+    pub cipher_suite: String,
+    pub tx_key: String,
+    pub rx_key: String,
+    /// Sequence number of the next outgoing message
+    pub tx_seq: u64,
+    /// Highest sequence number received so far
+    pub rx_seq: Option<u64>,
+    pub created_at: u64,
+    pub expires_at: u64,
+}
+
+impl StoredSession {
+    /// `keys` is the `(initiator_to_responder, responder_to_initiator)` pair from the handshake.
+    pub fn new(
+        peer_did: String,
+        local_did: String,
+        identity: Option<String>,
+        role: Role,
+        transcript_hash: &[u8],
+        cipher_suite: String,
+        keys: (SessionKey, SessionKey),
+    ) -> Self {
+        let (i2r, r2i) = keys;
+        let (tx, rx) = match role {
+            Role::Initiator => (i2r, r2i),
+            Role::Responder => (r2i, i2r),
+        };
+        Self {
+            peer_did,
+            local_did,
+            identity,
+            role,
+            kid: crate::handshake::kid(transcript_hash),
+            transcript_hash: hex::encode(transcript_hash),
+            cipher_suite,
+            tx_key: hex::encode(tx.as_bytes()),
+            rx_key: hex::encode(rx.as_bytes()),
+            tx_seq: 0,
+            rx_seq: None,
+            created_at: now_secs(),
+            expires_at: now_secs() + DEFAULT_SESSION_TTL_SECS,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        now_secs() >= self.expires_at
+    }
+
+    pub fn check_active(&self) -> Result<()> {
+        if self.is_expired() {
+            bail!("Session with {} has expired. Run `oap connect {}` again.", self.peer_did, self.peer_did);
+        }
+        Ok(())
+    }
+
+    pub fn tx_key_bytes(&self) -> Result<[u8; 32]> {
+        decode_key(&self.tx_key)
+    }
+
+    pub fn rx_key_bytes(&self) -> Result<[u8; 32]> {
+        decode_key(&self.rx_key)
+    }
+
+    /// Returns the sequence number to use for the next message and advances the counter.
+    /// Call it inside [`SessionStore::update`] so the new counter is saved under the lock.
+    pub fn next_tx_seq(&mut self) -> u64 {
+        let seq = self.tx_seq;
+        self.tx_seq += 1;
+        seq
+    }
+
+    /// Encrypts `plaintext` into a padded OATP container with the next sequence number.
+    pub fn seal(&mut self, plaintext: &[u8]) -> Result<(JweContainer, u64)> {
+        let key = self.tx_key_bytes()?;
+        let seq = self.next_tx_seq();
+        let container = encrypt_padded(plaintext, &key, &self.kid, seq, DEFAULT_PADDING_BLOCK)
+            .map_err(|e| anyhow!("Encryption failed: {}", e))?;
+        Ok((container, seq))
+    }
+
+    /// Decrypts an incoming container with the receive key.
+    pub fn open(&self, container: &JweContainer) -> Result<Vec<u8>> {
+        let key = self.rx_key_bytes()?;
+        decrypt_padded(container, &key).map_err(|e| anyhow!("Decryption failed: {}", e))
+    }
+
+    /// Records an incoming sequence number, rejecting replays and reordering.
+    pub fn record_rx_seq(&mut self, seq: u64) -> Result<()> {
+        if let Some(last) = self.rx_seq {
+            if seq <= last {
+                bail!("Sequence number {} is not greater than last received {} (replay?)", seq, last);
+            }
+        }
+        self.rx_seq = Some(seq);
+        Ok(())
+    }
+}
+
+fn decode_key(hex_key: &str) -> Result<[u8; 32]> {
+    hex::decode(hex_key)
+        .context("Corrupt session key")?
+        .try_into()
+        .map_err(|_| anyhow!("Invalid session key length"))
+}
+
+pub struct SessionStore {
+    dir: PathBuf,
+}
+
+impl SessionStore {
+    /// Opens the default store at `~/.oap/sessions`.
+    pub fn open_default() -> Result<Self> {
+        let home = dirs::home_dir().ok_or_else(|| anyhow!("Could not determine home directory"))?;
+        Ok(Self::open(home.join(".oap").join("sessions")))
+    }
+
+    pub fn open(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Files are named by the SHA-256 of the peer DID, so distinct peers never share one.
+    fn file_name(peer_did: &str) -> String {
+        hex::encode(Sha256::digest(peer_did.as_bytes()))
+    }
+
+    pub fn path_for(&self, peer_did: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", Self::file_name(peer_did)))
+    }
+
+    fn lock_path(&self, peer_did: &str) -> PathBuf {
+        self.dir.join(format!("{}.lock", Self::file_name(peer_did)))
+    }
+
+    /// Takes the exclusive lock guarding the session with `peer_did`. Released on drop.
+    fn lock(&self, peer_did: &str) -> Result<fs::File> {
+        create_private_dir(&self.dir)?;
+        let path = self.lock_path(peer_did);
+        loop {
+            let file = fs::OpenOptions::new().create(true).truncate(false).write(true).open(&path)
+                .with_context(|| format!("Failed to open {}", path.display()))?;
+            file.lock_exclusive().with_context(|| format!("Failed to lock {}", path.display()))?;
+            // `drop_session` removes the lock file while holding it; a lock on the removed
+            // file guards nothing, so start over with a fresh one
+            if is_same_file(&file, &path) {
+                return Ok(file);
+            }
+        }
+    }
+
+    pub fn save(&self, session: &StoredSession) -> Result<PathBuf> {
+        let _lock = self.lock(&session.peer_did)?;
+        self.write(session)
+    }
+
+    fn write(&self, session: &StoredSession) -> Result<PathBuf> {
+        let path = self.path_for(&session.peer_did);
+        write_private(&path, serde_json::to_string_pretty(session)?.as_bytes())?;
+        Ok(path)
+    }
+
+    /// Loads the session with `peer_did`, applies `change` and saves the result, all under
+    /// the session's lock, so concurrent processes never reuse a sequence number.
+    /// Nothing is saved when `change` fails.
+    pub fn update<T>(&self, peer_did: &str, change: impl FnOnce(&mut StoredSession) -> Result<T>) -> Result<T> {
+        let _lock = self.lock(peer_did)?;
+        let mut session = self.load(peer_did)?;
+        let value = change(&mut session)?;
+        self.write(&session)?;
+        Ok(value)
+    }
+
+    /// Loads the session with `peer_did`, whether or not it has expired.
+    pub fn load(&self, peer_did: &str) -> Result<StoredSession> {
+        let path = self.path_for(peer_did);
+        if !path.exists() {
+            bail!("No session with {}. Run `oap connect {}` first.", peer_did, peer_did);
+        }
+        let session = read_session(&path)?;
+        if session.peer_did != peer_did {
+            bail!("{} holds the session with {}, not {}", path.display(), session.peer_did, peer_did);
+        }
+        Ok(session)
+    }
+
+    /// Loads a session that is still valid.
+    pub fn load_active(&self, peer_did: &str) -> Result<StoredSession> {
+        let session = self.load(peer_did)?;
+        session.check_active()?;
+        Ok(session)
+    }
+
+    /// Finds the active session with key ID `kid` for the local DID.
+    pub fn find_by_kid(&self, kid: &str, local_did: Option<&str>) -> Result<Option<StoredSession>> {
+        Ok(self.list()?.into_iter().find(|s| {
+            s.kid == kid && !s.is_expired() && local_did.is_none_or(|d| d == s.local_did)
+        }))
+    }
+
+    /// Lists all sessions (including expired ones), sorted by peer. Files that cannot be
+    /// used (corrupt, or readable by other users) are skipped with a warning on stderr.
+    pub fn list(&self) -> Result<Vec<StoredSession>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut sessions = Vec::new();
+        for file in fs::read_dir(&self.dir)? {
+            let path = file?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            match read_session(&path) {
+                Ok(session) => sessions.push(session),
+                Err(e) => eprintln!("{} skipping {}: {:#}", "Warning:".yellow(), path.display(), e),
+            }
+        }
+        sessions.sort_by(|a, b| a.peer_did.cmp(&b.peer_did));
+        Ok(sessions)
+    }
+
+    pub fn drop_session(&self, peer_did: &str) -> Result<()> {
+        let path = self.path_for(peer_did);
+        if !path.exists() {
+            bail!("No session with {}", peer_did);
+        }
+        let _lock = self.lock(peer_did)?;
+        fs::remove_file(path)?;
+        fs::remove_file(self.lock_path(peer_did))?;
+        Ok(())
+    }
+}
+
+/// Whether the open `file` is still the one at `path`.
+fn is_same_file(file: &fs::File, path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        match (file.metadata(), fs::metadata(path)) {
+            (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+            _ => false,
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = file;
+        path.exists()
+    }
+}
+
+/// Reads a session file, refusing files that other users could read the keys from.
+fn read_session(path: &Path) -> Result<StoredSession> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(path)?.permissions().mode() & 0o777;
+        if mode & 0o077 != 0 {
+            bail!("{} is accessible by other users (mode {:o}); run `chmod 600` on it", path.display(), mode);
+        }
+    }
+    let content = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_str(&content).with_context(|| format!("Corrupt session file {}", path.display()))
+}
+
+pub fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn session(peer: &str) -> StoredSession {
+        StoredSession {
+            peer_did: peer.to_string(),
+            local_did: "did:key:z6MkLocal".to_string(),
+            identity: None,
+            role: Role::Initiator,
+            kid: "00".repeat(16),
+            transcript_hash: "00".repeat(32),
+            cipher_suite: "test".to_string(),
+            tx_key: "11".repeat(32),
+            rx_key: "22".repeat(32),
+            tx_seq: 0,
+            rx_seq: None,
+            created_at: now_secs(),
+            expires_at: now_secs() + 60,
+        }
+    }
+
+    #[test]
+    fn concurrent_updates_never_reuse_a_sequence_number() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(SessionStore::open(dir.path().to_path_buf()));
+        store.save(&session("did:web:peer")).unwrap();
+
+        let threads: Vec<_> = (0..8).map(|_| {
+            let store = store.clone();
+            std::thread::spawn(move || {
+                (0..25).map(|_| store.update("did:web:peer", |s| Ok(s.next_tx_seq())).unwrap()).collect::<Vec<_>>()
+            })
+        }).collect();
+        let mut seqs: Vec<u64> = threads.into_iter().flat_map(|t| t.join().unwrap()).collect();
+        seqs.sort();
+        assert_eq!(seqs, (0..200).collect::<Vec<_>>());
+        assert_eq!(store.load("did:web:peer").unwrap().tx_seq, 200);
+    }
+
+    #[test]
+    fn failed_updates_are_not_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SessionStore::open(dir.path().to_path_buf());
+        store.save(&session("did:web:peer")).unwrap();
+        store.update("did:web:peer", |s| s.record_rx_seq(5)).unwrap();
+
+        assert!(store.update("did:web:peer", |s| s.record_rx_seq(5)).is_err());
+        assert!(store.update("did:web:peer", |s| { s.tx_seq = 99; s.check_active() }).is_ok());
+        let mut expired = store.load("did:web:peer").unwrap();
+        expired.expires_at = 0;
+        store.save(&expired).unwrap();
+        assert!(store.update("did:web:peer", |s| { s.tx_seq = 1; s.check_active() }).is_err());
+        assert_eq!(store.load("did:web:peer").unwrap().tx_seq, 99);
+    }
+
+    #[test]
+    fn similar_peers_do_not_share_a_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SessionStore::open(dir.path().to_path_buf());
+        store.save(&session("did:web:example.com:a_b")).unwrap();
+
+        assert!(store.load("did:web:example.com:a:b").is_err());
+        store.save(&session("did:web:example.com:a:b")).unwrap();
+        assert_eq!(store.list().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn load_refuses_files_of_another_peer() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SessionStore::open(dir.path().to_path_buf());
+        store.save(&session("did:web:other")).unwrap();
+        fs::rename(store.path_for("did:web:other"), store.path_for("did:web:peer")).unwrap();
+
+        assert!(store.load("did:web:peer").unwrap_err().to_string().contains("not did:web:peer"));
+    }
+
+    #[test]
+    fn drop_removes_the_session_and_its_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SessionStore::open(dir.path().to_path_buf());
+        store.save(&session("did:web:peer")).unwrap();
+        assert!(store.lock_path("did:web:peer").exists());
+
+        store.drop_session("did:web:peer").unwrap();
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+        // The store keeps working after the lock file is gone
+        store.save(&session("did:web:peer")).unwrap();
+        assert_eq!(store.load("did:web:peer").unwrap().peer_did, "did:web:peer");
+    }
+
+    #[test]
+    fn list_skips_corrupt_and_exposed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SessionStore::open(dir.path().to_path_buf());
+        store.save(&session("did:web:good")).unwrap();
+        store.save(&session("did:web:exposed")).unwrap();
+        fs::write(dir.path().join("corrupt.json"), "{").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let path = store.path_for("did:web:exposed");
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+            assert!(store.load("did:web:exposed").unwrap_err().to_string().contains("other users"));
+        }
+
+        let peers: Vec<String> = store.list().unwrap().into_iter().map(|s| s.peer_did).collect();
+        #[cfg(unix)]
+        assert_eq!(peers, ["did:web:good"]);
+        #[cfg(not(unix))]
+        assert_eq!(peers, ["did:web:exposed", "did:web:good"]);
+    }
+}
@@ -65,6 +65,10 @@ async def chaos_middleware(request: Request, call_next):
 def health_check():
     return {"status": "ok", "uptime": "forever", "id": os.getenv("HOSTNAME", "unknown")}
 
+@app.get("/chaos")
+def get_chaos():
+    return {"config": chaos_config}
+
 @app.post("/chaos")
 def configure_chaos(cfg: ChaosConfig):
     chaos_config["failure_rate"] = cfg.failure_rate
@@ -113,6 +117,12 @@ def clear_messages():
     messages_db.clear()
     return {"status": "cleared"}
 
+@app.delete("/messages/{message_id}")
+def delete_message(message_id: str):
+    if messages_db.pop(message_id, None) is None:
+        raise HTTPException(status_code=404, detail="Message not found")
+    return {"status": "deleted"}
+
 if __name__ == "__main__":
     import uvicorn
     uvicorn.run(app, host="0.0.0.0", port=8080)